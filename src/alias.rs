@@ -0,0 +1,276 @@
+use crate::builtins::Stdio;
+use crate::parser::{self, ParseError, Token, TokenType};
+use crate::shell::Shell;
+use std::collections::BTreeMap;
+
+/// Replaces every alias found in command position with the tokens of its
+/// value. An alias is not expanded again inside its own value, and a value
+/// ending in a blank makes the word after it eligible for expansion too.
+/// The tokens of a value take the place of the alias in the source.
+pub fn expand_aliases(
+    aliases: &BTreeMap<String, String>,
+    tokens: Vec<Token>,
+) -> Result<Vec<Token>, ParseError> {
+    expand(aliases, tokens, &mut Vec::new()).map(|(tokens, _)| tokens)
+}
+
+/// Returns the expanded tokens and whether whatever comes after them is
+/// still in command position.
+fn expand(
+    aliases: &BTreeMap<String, String>,
+    tokens: Vec<Token>,
+    active: &mut Vec<String>,
+) -> Result<(Vec<Token>, bool), ParseError> {
+    let mut result = Vec::new();
+    let mut command_position = true;
+    let mut in_conditional = false;
+
+    for token in tokens {
+        let alias = match token.kind {
+            TokenType::Word(ref w)
+                if command_position && !in_conditional && !active.contains(w) =>
+            {
+                aliases.get(w).map(|value| (w.clone(), value))
+            }
+            _ => None,
+        };
+
+        if let Some((name, value)) = alias {
+            let (span, end) = (token.span, token.end);
+            let value_tokens = parser::parse(value)
+                .map_err(|e| ParseError { span, ..e })?
+                .into_iter()
+                .map(|t| Token { span, end, ..t })
+                .collect();
+
+            active.push(name);
+            let (expanded, next_is_command) = expand(aliases, value_tokens, active)?;
+            active.pop();
+
+            result.extend(expanded);
+            command_position = next_is_command || value.ends_with(' ') || value.ends_with('\t');
+            continue;
+        }
+
+        command_position = match token.kind {
+            TokenType::Word(ref w) if in_conditional => {
+                in_conditional = w != "]]";
+                false
+            }
+            TokenType::Word(ref w) if command_position && w == "[[" => {
+                in_conditional = true;
+                false
+            }
+            TokenType::Word(ref w) => command_position && (w == "{" || w == "!"),
+            TokenType::Assignment(_) => command_position,
+            TokenType::Semicolon
+            | TokenType::Newline
+            | TokenType::Ampersand
+            | TokenType::And
+            | TokenType::Or
+            | TokenType::Pipe
+            | TokenType::Parenthesis('(') => !in_conditional,
+            _ => false,
+        };
+
+        result.push(token);
+    }
+
+    Ok((result, command_position))
+}
+
+/// Single-quotes `value` so the shell reads it back as one word.
+pub fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains(|c: char| "/$`'\"\\ \t\n;&|()<>=".contains(c))
+}
+
+/// `alias` prints every alias, `alias name` prints one and `alias name=value`
+/// defines it. Printed aliases can be read back by the shell.
+pub fn execute_alias(shell: &mut Shell, argv: &[&str], stdio: &mut Stdio) -> i32 {
+    let arguments = &argv[1..];
+
+    if arguments.is_empty() {
+        for (name, value) in &shell.aliases {
+            writeln!(stdio.stdout, "alias {}={}", name, quote(value)).ok();
+        }
+
+        return 0;
+    }
+
+    let mut status = 0;
+
+    for argument in arguments {
+        match argument.find('=') {
+            Some(i) if is_valid_name(&argument[..i]) => {
+                shell
+                    .aliases
+                    .insert(argument[..i].to_string(), argument[i + 1..].to_string());
+            }
+            Some(i) => {
+                writeln!(
+                    stdio.stderr,
+                    "alias: `{}': invalid alias name",
+                    &argument[..i]
+                )
+                .ok();
+                status = 1;
+            }
+            None => match shell.aliases.get(*argument) {
+                Some(value) => {
+                    writeln!(stdio.stdout, "alias {}={}", argument, quote(value)).ok();
+                }
+                None => {
+                    writeln!(stdio.stderr, "alias: {}: not found", argument).ok();
+                    status = 1;
+                }
+            },
+        }
+    }
+
+    status
+}
+
+/// `unalias name…` removes aliases, `unalias -a` removes all of them.
+pub fn execute_unalias(shell: &mut Shell, argv: &[&str], stdio: &mut Stdio) -> i32 {
+    let arguments = &argv[1..];
+
+    if arguments.is_empty() {
+        writeln!(stdio.stderr, "unalias: usage: unalias [-a] name [name ...]").ok();
+        return 2;
+    }
+
+    if arguments[0] == "-a" {
+        shell.aliases.clear();
+        return 0;
+    }
+
+    let mut status = 0;
+
+    for argument in arguments {
+        if shell.aliases.remove(*argument).is_none() {
+            writeln!(stdio.stderr, "unalias: {}: not found", argument).ok();
+            status = 1;
+        }
+    }
+
+    status
+}
+
+#[cfg(test)]
+fn aliases(definitions: &[(&str, &str)]) -> BTreeMap<String, String> {
+    definitions
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+fn kinds(line: &str) -> Vec<TokenType> {
+    parser::parse(line)
+        .unwrap()
+        .into_iter()
+        .map(|token| token.kind)
+        .collect()
+}
+
+#[test]
+fn test_expand_command_position_only() {
+    let aliases = aliases(&[("ll", "ls -l")]);
+
+    assert_eq!(
+        expand_aliases(&aliases, parser::parse("ll ll; ll").unwrap()).unwrap(),
+        kinds("ls -l ll; ls -l")
+    );
+}
+
+#[test]
+fn test_expand_does_not_recurse() {
+    let aliases = aliases(&[("ls", "ls -F"), ("a", "b"), ("b", "a")]);
+
+    assert_eq!(
+        expand_aliases(&aliases, parser::parse("ls | a").unwrap()).unwrap(),
+        kinds("ls -F | a")
+    );
+}
+
+#[test]
+fn test_expand_trailing_blank() {
+    let aliases = aliases(&[("sudo", "sudo "), ("ll", "ls -l"), ("x", "echo")]);
+
+    assert_eq!(
+        expand_aliases(&aliases, parser::parse("sudo ll x").unwrap()).unwrap(),
+        kinds("sudo ls -l x")
+    );
+}
+
+#[test]
+fn test_expand_after_assignments() {
+    let aliases = aliases(&[("ll", "ls -l")]);
+
+    assert_eq!(
+        expand_aliases(&aliases, parser::parse("A=1 ll & ll\n ll").unwrap()).unwrap(),
+        kinds("A=1 ls -l & ls -l\n ls -l")
+    );
+}
+
+#[test]
+fn test_expand_skips_conditional() {
+    let aliases = aliases(&[("a", "echo")]);
+
+    assert_eq!(
+        expand_aliases(&aliases, parser::parse("[[ a && a ]] && a").unwrap()).unwrap(),
+        kinds("[[ a && a ]] && echo")
+    );
+}
+
+#[test]
+fn test_expansion_keeps_alias_position() {
+    let aliases = aliases(&[("ll", "ls -l"), ("bad", "echo 'x")]);
+    let tokens = parser::parse("echo; ll").unwrap();
+    let span = tokens[2].span;
+    let expanded = expand_aliases(&aliases, tokens).unwrap();
+
+    assert_eq!(expanded[2].span, span);
+    assert_eq!(expanded[3].span, span);
+
+    let tokens = parser::parse("echo; bad").unwrap();
+    let span = tokens[2].span;
+
+    assert_eq!(expand_aliases(&aliases, tokens).unwrap_err().span, span);
+}
+
+#[test]
+fn test_alias_and_unalias() {
+    use crate::builtins::capture;
+
+    let mut shell = Shell::new();
+
+    assert_eq!(capture(&mut shell, &["alias", "ll=ls -l", "la=ls -a"]).0, 0);
+    assert_eq!(shell.aliases.get("ll"), Some(&"ls -l".to_string()));
+    assert_eq!(
+        capture(&mut shell, &["alias", "ll", "nope"]),
+        (
+            1,
+            "alias ll='ls -l'\n".to_string(),
+            "alias: nope: not found\n".to_string()
+        )
+    );
+    assert_eq!(capture(&mut shell, &["alias", "a/b=c"]).0, 1);
+    assert_eq!(
+        capture(&mut shell, &["alias"]).1,
+        "alias la='ls -a'\nalias ll='ls -l'\n"
+    );
+    assert_eq!(capture(&mut shell, &["unalias", "ll"]).0, 0);
+    assert_eq!(capture(&mut shell, &["unalias", "ll"]).0, 1);
+    assert_eq!(capture(&mut shell, &["unalias", "-a"]).0, 0);
+    assert!(shell.aliases.is_empty());
+}
+
+#[test]
+fn test_quote() {
+    assert_eq!(quote("echo 'hi'"), "'echo '\\''hi'\\'''");
+}
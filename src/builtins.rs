@@ -0,0 +1,307 @@
+use crate::alias;
+use crate::condition;
+use crate::lookup;
+use crate::printf;
+use crate::process;
+use crate::read;
+use crate::shell::Shell;
+use crate::source;
+use crate::trap;
+use crate::variables;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::iter;
+use std::os::unix::io::RawFd;
+use std::rc::Rc;
+
+/// The standard streams handed to a builtin. When run by the shell they are
+/// its file descriptors 0, 1 and 2, so redirections and pipes apply to
+/// builtins just like they do to external programs.
+pub struct Stdio<'a> {
+    pub stdin: &'a mut dyn Read,
+    pub stdout: &'a mut dyn Write,
+    pub stderr: &'a mut dyn Write,
+}
+
+/// A command that runs inside the shell process and can therefore change its
+/// state. `argv[0]` is the name it was invoked as.
+pub trait Builtin {
+    fn run(&self, shell: &mut Shell, argv: &[&str], stdio: &mut Stdio) -> i32;
+}
+
+impl<F> Builtin for F
+where
+    F: Fn(&mut Shell, &[&str], &mut Stdio) -> i32,
+{
+    fn run(&self, shell: &mut Shell, argv: &[&str], stdio: &mut Stdio) -> i32 {
+        self(shell, argv, stdio)
+    }
+}
+
+/// Builtins by name. The default registry holds the shell's own builtins,
+/// applications embedding the shell can add or replace any of them.
+#[derive(Clone)]
+pub struct Builtins {
+    table: HashMap<String, Rc<dyn Builtin>>,
+}
+
+impl Builtins {
+    /// A registry without a single builtin.
+    pub fn empty() -> Builtins {
+        Builtins {
+            table: HashMap::new(),
+        }
+    }
+
+    pub fn register<B: Builtin + 'static>(&mut self, name: &str, builtin: B) {
+        self.table.insert(name.to_string(), Rc::new(builtin));
+    }
+
+    pub fn get(&self, name: &str) -> Option<Rc<dyn Builtin>> {
+        self.table.get(name).cloned()
+    }
+}
+
+impl Default for Builtins {
+    fn default() -> Builtins {
+        let mut builtins = Builtins::empty();
+
+        builtins.register("cd", process::execute_cd);
+        builtins.register("pwd", process::execute_pwd);
+        builtins.register("pushd", process::execute_pushd);
+        builtins.register("popd", process::execute_popd);
+        builtins.register("dirs", process::execute_dirs);
+        builtins.register("exit", exit);
+        builtins.register("set", set);
+        builtins.register("test", condition::execute_test);
+        builtins.register("[", condition::execute_test);
+        builtins.register("alias", alias::execute_alias);
+        builtins.register("unalias", alias::execute_unalias);
+        builtins.register("export", variables::execute_export);
+        builtins.register("readonly", variables::execute_readonly);
+        builtins.register("unset", variables::execute_unset);
+        builtins.register("env", variables::execute_env);
+        builtins.register("source", source::execute_source);
+        builtins.register(".", source::execute_source);
+        builtins.register("trap", trap::execute_trap);
+        builtins.register("type", lookup::execute_type);
+        builtins.register("command", lookup::execute_command);
+        builtins.register("hash", lookup::execute_hash);
+        builtins.register("read", read::execute_read);
+        builtins.register("echo", printf::execute_echo);
+        builtins.register("printf", printf::execute_printf);
+
+        builtins
+    }
+}
+
+impl fmt::Debug for Builtins {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut names: Vec<&String> = self.table.keys().collect();
+
+        names.sort();
+
+        f.debug_set().entries(names).finish()
+    }
+}
+
+/// Reads straight from a file descriptor. Unlike `io::stdin()` it keeps no
+/// buffer, so it never consumes input meant for the next command.
+pub struct FdReader(pub RawFd);
+
+impl Read for FdReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = unsafe { libc::read(self.0, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+
+        if n < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(n as usize)
+        }
+    }
+}
+
+/// `exit [n]` leaves the shell with status `n`, or with the status of the
+/// last command if none is given.
+fn exit(shell: &mut Shell, argv: &[&str], stdio: &mut Stdio) -> i32 {
+    let status = match &argv[1..] {
+        [] => shell.status,
+        [n] => match n.parse::<i64>() {
+            Ok(n) => (n & 0xff) as i32,
+            Err(_) => {
+                writeln!(stdio.stderr, "exit: {}: numeric argument required", n).ok();
+                2
+            }
+        },
+        _ => {
+            writeln!(stdio.stderr, "exit: too many arguments").ok();
+            return 1;
+        }
+    };
+
+    shell.status = status;
+    shell.exiting = true;
+
+    status
+}
+
+/// `set` lists every variable, `set -o name` and `set +o name` toggle an
+/// option and `set -- args` replaces the positional parameters.
+fn set(shell: &mut Shell, argv: &[&str], stdio: &mut Stdio) -> i32 {
+    if argv.len() == 1 {
+        variables::print_variables(shell, stdio);
+        return 0;
+    }
+
+    let mut arguments = argv[1..].iter();
+
+    while let Some(&flag) = arguments.next() {
+        let enable = match flag {
+            "-o" => true,
+            "+o" => false,
+            "--" => {
+                shell.positional = arguments.map(|a| a.to_string()).collect();
+                return 0;
+            }
+            _ if !flag.starts_with('-') && !flag.starts_with('+') => {
+                shell.positional = iter::once(flag)
+                    .chain(arguments.copied())
+                    .map(String::from)
+                    .collect();
+                return 0;
+            }
+            _ => {
+                writeln!(stdio.stderr, "set: {}: invalid option", flag).ok();
+                return 2;
+            }
+        };
+
+        let name = match arguments.next() {
+            Some(name) => name,
+            None => {
+                writeln!(stdio.stderr, "set: {}: option name required", flag).ok();
+                return 2;
+            }
+        };
+
+        match shell.options.get_mut(name) {
+            Some(option) => *option = enable,
+            None => {
+                writeln!(stdio.stderr, "set: {}: invalid option name", name).ok();
+                return 1;
+            }
+        }
+    }
+
+    0
+}
+
+/// Runs the builtin called `argv[0]` with an empty stdin and returns its
+/// status together with everything it wrote to stdout and stderr.
+#[cfg(test)]
+pub fn capture(shell: &mut Shell, argv: &[&str]) -> (i32, String, String) {
+    let builtin = shell.builtins.get(argv[0]).unwrap();
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+
+    let status = builtin.run(
+        shell,
+        argv,
+        &mut Stdio {
+            stdin: &mut io::empty(),
+            stdout: &mut stdout,
+            stderr: &mut stderr,
+        },
+    );
+
+    (
+        status,
+        String::from_utf8(stdout).unwrap(),
+        String::from_utf8(stderr).unwrap(),
+    )
+}
+
+#[test]
+fn test_register_custom_builtin() {
+    let mut shell = Shell::new();
+
+    shell.builtins.register(
+        "greet",
+        |_: &mut Shell, argv: &[&str], stdio: &mut Stdio| {
+            writeln!(stdio.stdout, "hello {}", argv[1]).ok();
+            0
+        },
+    );
+
+    assert_eq!(
+        capture(&mut shell, &["greet", "world"]),
+        (0, "hello world\n".to_string(), String::new())
+    );
+}
+
+#[test]
+fn test_exit() {
+    let mut shell = Shell::new();
+
+    shell.status = 3;
+
+    assert_eq!(capture(&mut shell, &["exit"]).0, 3);
+    assert!(shell.exiting);
+    assert_eq!(capture(&mut shell, &["exit", "-1"]).0, 255);
+    assert_eq!(
+        capture(&mut shell, &["exit", "x"]),
+        (
+            2,
+            String::new(),
+            "exit: x: numeric argument required\n".to_string()
+        )
+    );
+
+    shell.exiting = false;
+
+    assert_eq!(capture(&mut shell, &["exit", "1", "2"]).0, 1);
+    assert!(!shell.exiting);
+}
+
+#[test]
+fn test_set_options() {
+    let mut shell = Shell::new();
+
+    assert_eq!(capture(&mut shell, &["set", "-o", "pipefail"]).0, 0);
+    assert!(shell.options.pipefail);
+    assert!(shell.options.interactive_comments);
+    assert_eq!(
+        capture(&mut shell, &["set", "+o", "interactive_comments"]).0,
+        0
+    );
+    assert!(!shell.options.interactive_comments);
+    assert_eq!(
+        capture(&mut shell, &["set", "-o", "nope"]),
+        (
+            1,
+            String::new(),
+            "set: nope: invalid option name\n".to_string()
+        )
+    );
+    assert_eq!(capture(&mut shell, &["set", "-x"]).0, 2);
+}
+
+#[test]
+fn test_set_positional_and_listing() {
+    use crate::shell::Variable;
+
+    let mut shell = Shell::default();
+
+    assert_eq!(capture(&mut shell, &["set", "--", "a", "-b"]).0, 0);
+    assert_eq!(shell.positional, vec!["a", "-b"]);
+    assert_eq!(capture(&mut shell, &["set", "-o", "pipefail", "c"]).0, 0);
+    assert_eq!(shell.positional, vec!["c"]);
+    assert_eq!(capture(&mut shell, &["set", "--"]).0, 0);
+    assert!(shell.positional.is_empty());
+
+    shell.set_var("B", Variable::Scalar("x y".to_string()));
+    shell.set_var("A", Variable::Array(vec!["1".to_string(), "2".to_string()]));
+
+    assert_eq!(capture(&mut shell, &["set"]).1, "A=('1' '2')\nB='x y'\n");
+}
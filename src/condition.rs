@@ -0,0 +1,633 @@
+use crate::builtins::Stdio;
+use crate::error::ShellError;
+use crate::parser::TokenType;
+use crate::process::{remove_quotes, word_value};
+use crate::shell::{Shell, Variable};
+use crate::tree::{CondExpr, CondExprOptions, BINARY_TESTS, UNARY_TESTS};
+use std::ffi::CString;
+use std::fs;
+use std::mem;
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+
+/// The `test` and `[` builtins. `[` insists on a closing `]` as its last
+/// argument, after that both behave the same.
+pub fn execute_test(_shell: &mut Shell, argv: &[&str], stdio: &mut Stdio) -> i32 {
+    let arguments = if argv[0] == "[" {
+        match argv[1..].split_last() {
+            Some((&"]", rest)) => rest,
+            _ => {
+                writeln!(stdio.stderr, "[: missing `]'").ok();
+                return 2;
+            }
+        }
+    } else {
+        &argv[1..]
+    };
+
+    match test(arguments) {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(e) => {
+            writeln!(stdio.stderr, "{}: {}", argv[0], e).ok();
+            2
+        }
+    }
+}
+
+/// Evaluates `[[ … ]]`. Matches of `=~` are stored in `BASH_REMATCH`.
+pub fn execute_conditional(shell: &mut Shell, expr: &CondExpr) -> i32 {
+    match evaluate(shell, expr) {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(message) => ShellError::Builtin {
+            name: "[[".to_string(),
+            message,
+            status: 2,
+        }
+        .report(),
+    }
+}
+
+/// Follows the POSIX rules that decide what the arguments of `test` mean
+/// based on how many there are, and only falls back to parsing `-a`, `-o`,
+/// `!` and parentheses for longer expressions.
+fn test(args: &[&str]) -> Result<bool, String> {
+    match args.len() {
+        0 => Ok(false),
+        1 => Ok(!args[0].is_empty()),
+        2 if args[0] == "!" => Ok(args[1].is_empty()),
+        2 if UNARY_TESTS.contains(&args[0]) => unary_test(args[0], args[1]),
+        2 => Err(format!("{}: unary operator expected", args[0])),
+        3 if BINARY_TESTS.contains(&args[1]) => binary_test(args[0], args[1], args[2]),
+        3 if args[1] == "-a" => Ok(!args[0].is_empty() && !args[2].is_empty()),
+        3 if args[1] == "-o" => Ok(!args[0].is_empty() || !args[2].is_empty()),
+        3 if args[0] == "!" => Ok(!test(&args[1..])?),
+        3 if args[0] == "(" && args[2] == ")" => Ok(!args[1].is_empty()),
+        3 => Err(format!("{}: binary operator expected", args[1])),
+        4 if args[0] == "!" => Ok(!test(&args[1..])?),
+        4 if args[0] == "(" && args[3] == ")" => test(&args[1..3]),
+        _ => {
+            let mut parser = TestParser { args, pos: 0 };
+            let result = parser.or()?;
+
+            match parser.args.get(parser.pos) {
+                Some(arg) => Err(format!("{}: unexpected argument", arg)),
+                None => Ok(result),
+            }
+        }
+    }
+}
+
+struct TestParser<'a> {
+    args: &'a [&'a str],
+    pos: usize,
+}
+
+impl<'a> TestParser<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.args.get(self.pos).cloned()
+    }
+
+    fn next(&mut self) -> Result<&'a str, String> {
+        let arg = self.peek().ok_or_else(|| "argument expected".to_string())?;
+
+        self.pos += 1;
+
+        Ok(arg)
+    }
+
+    fn or(&mut self) -> Result<bool, String> {
+        let mut result = self.and()?;
+
+        while self.peek() == Some("-o") {
+            self.pos += 1;
+            result = self.and()? || result;
+        }
+
+        Ok(result)
+    }
+
+    fn and(&mut self) -> Result<bool, String> {
+        let mut result = self.not()?;
+
+        while self.peek() == Some("-a") {
+            self.pos += 1;
+            result = self.not()? && result;
+        }
+
+        Ok(result)
+    }
+
+    fn not(&mut self) -> Result<bool, String> {
+        if self.peek() == Some("!") {
+            self.pos += 1;
+
+            return Ok(!self.not()?);
+        }
+
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<bool, String> {
+        let arg = self.next()?;
+
+        if arg == "(" {
+            let result = self.or()?;
+
+            return match self.next() {
+                Ok(")") => Ok(result),
+                _ => Err("`)' expected".to_string()),
+            };
+        }
+
+        if UNARY_TESTS.contains(&arg) && self.peek().is_some() {
+            return unary_test(arg, self.next()?);
+        }
+
+        match self.peek() {
+            Some(op) if BINARY_TESTS.contains(&op) => {
+                self.pos += 1;
+                binary_test(arg, op, self.next()?)
+            }
+            _ => Ok(!arg.is_empty()),
+        }
+    }
+}
+
+fn evaluate(shell: &mut Shell, expr: &CondExpr) -> Result<bool, String> {
+    match &expr.value {
+        CondExprOptions::Word(word) => Ok(!word_value(&word.kind).is_empty()),
+        CondExprOptions::Unary(op, operand) => unary_test(op, &word_value(&operand.kind)),
+        CondExprOptions::Binary(left, op, right) => {
+            let left = word_value(&left.kind);
+            let right = &right.kind;
+            let quoted = !matches!(right, TokenType::Word(_));
+
+            match op.as_str() {
+                "=" | "==" => Ok(glob_match(&glob_pattern(right), &left)),
+                "!=" => Ok(!glob_match(&glob_pattern(right), &left)),
+                "=~" if quoted => regex_match(shell, &left, &regex_escape(&word_value(right))),
+                // the parser already turned an unquoted regex into its final form
+                "=~" => match right {
+                    TokenType::Word(regex) => regex_match(shell, &left, regex),
+                    _ => regex_match(shell, &left, &word_value(right)),
+                },
+                _ => binary_test(&left, op, &word_value(right)),
+            }
+        }
+        CondExprOptions::Not(inner) => Ok(!evaluate(shell, inner)?),
+        CondExprOptions::And(left, right) => Ok(evaluate(shell, left)? && evaluate(shell, right)?),
+        CondExprOptions::Or(left, right) => Ok(evaluate(shell, left)? || evaluate(shell, right)?),
+    }
+}
+
+fn unary_test(op: &str, operand: &str) -> Result<bool, String> {
+    let metadata = || fs::metadata(operand);
+
+    Ok(match op {
+        "-n" => !operand.is_empty(),
+        "-z" => operand.is_empty(),
+        "-e" => metadata().is_ok(),
+        "-f" => metadata().map(|m| m.is_file()).unwrap_or(false),
+        "-d" => metadata().map(|m| m.is_dir()).unwrap_or(false),
+        "-b" => metadata()
+            .map(|m| m.file_type().is_block_device())
+            .unwrap_or(false),
+        "-c" => metadata()
+            .map(|m| m.file_type().is_char_device())
+            .unwrap_or(false),
+        "-p" => metadata().map(|m| m.file_type().is_fifo()).unwrap_or(false),
+        "-S" => metadata()
+            .map(|m| m.file_type().is_socket())
+            .unwrap_or(false),
+        "-h" | "-L" => fs::symlink_metadata(operand)
+            .map(|m| m.file_type().is_symlink())
+            .unwrap_or(false),
+        "-s" => metadata().map(|m| m.len() > 0).unwrap_or(false),
+        "-g" => has_mode(operand, 0o2000),
+        "-u" => has_mode(operand, 0o4000),
+        "-k" => has_mode(operand, 0o1000),
+        "-O" => metadata()
+            .map(|m| m.uid() == unsafe { libc::geteuid() })
+            .unwrap_or(false),
+        "-G" => metadata()
+            .map(|m| m.gid() == unsafe { libc::getegid() })
+            .unwrap_or(false),
+        "-r" => access(operand, libc::R_OK),
+        "-w" => access(operand, libc::W_OK),
+        "-x" => access(operand, libc::X_OK),
+        "-t" => {
+            let fd = parse_integer(operand)?;
+
+            fd >= 0 && fd <= i64::from(i32::MAX) && unsafe { libc::isatty(fd as i32) } == 1
+        }
+        _ => return Err(format!("{}: unary operator expected", op)),
+    })
+}
+
+fn binary_test(left: &str, op: &str, right: &str) -> Result<bool, String> {
+    Ok(match op {
+        "=" | "==" => left == right,
+        "!=" => left != right,
+        "<" => left < right,
+        ">" => left > right,
+        "-eq" => parse_integer(left)? == parse_integer(right)?,
+        "-ne" => parse_integer(left)? != parse_integer(right)?,
+        "-lt" => parse_integer(left)? < parse_integer(right)?,
+        "-le" => parse_integer(left)? <= parse_integer(right)?,
+        "-gt" => parse_integer(left)? > parse_integer(right)?,
+        "-ge" => parse_integer(left)? >= parse_integer(right)?,
+        "-nt" => match (modified(left), modified(right)) {
+            (Some(l), Some(r)) => l > r,
+            (l, r) => l.is_some() && r.is_none(),
+        },
+        "-ot" => match (modified(left), modified(right)) {
+            (Some(l), Some(r)) => l < r,
+            (l, r) => l.is_none() && r.is_some(),
+        },
+        "-ef" => match (fs::metadata(left), fs::metadata(right)) {
+            (Ok(l), Ok(r)) => l.dev() == r.dev() && l.ino() == r.ino(),
+            _ => false,
+        },
+        _ => return Err(format!("{}: binary operator expected", op)),
+    })
+}
+
+fn parse_integer(s: &str) -> Result<i64, String> {
+    s.trim()
+        .parse()
+        .map_err(|_| format!("{}: integer expression expected", s))
+}
+
+fn has_mode(path: &str, mode: u32) -> bool {
+    fs::metadata(path)
+        .map(|m| m.permissions().mode() & mode != 0)
+        .unwrap_or(false)
+}
+
+fn access(path: &str, mode: libc::c_int) -> bool {
+    match CString::new(path) {
+        Ok(path) => unsafe { libc::access(path.as_ptr(), mode) == 0 },
+        Err(_) => false,
+    }
+}
+
+fn modified(path: &str) -> Option<std::time::SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Shell pattern matching: `*`, `?`, bracket expressions with ranges,
+/// negation and character classes, and backslash escapes.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    glob_match_from(&pattern, &text)
+}
+
+fn glob_match_from(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // where to resume if what follows the last `*` stops matching
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        let step = match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(1),
+            Some('[') => match match_bracket(&pattern[p..], text[t]) {
+                Some((matched, len)) => {
+                    if matched {
+                        Some(len)
+                    } else {
+                        None
+                    }
+                }
+                None if text[t] == '[' => Some(1),
+                None => None,
+            },
+            Some('\\') if p + 1 < pattern.len() => {
+                if pattern[p + 1] == text[t] {
+                    Some(2)
+                } else {
+                    None
+                }
+            }
+            Some(&c) if c == text[t] => Some(1),
+            _ => None,
+        };
+
+        match step {
+            Some(len) => {
+                p += len;
+                t += 1;
+            }
+            None => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Matches `c` against the bracket expression at the start of `pattern`.
+/// Returns whether it matched and how long the expression is, or `None` when
+/// the `[` is never closed and has to be taken literally.
+fn match_bracket(pattern: &[char], c: char) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negated = match pattern.get(i) {
+        Some('!') | Some('^') => {
+            i += 1;
+            true
+        }
+        _ => false,
+    };
+    let mut matched = false;
+    let mut first = true;
+
+    loop {
+        let current = *pattern.get(i)?;
+
+        if current == ']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+
+        first = false;
+
+        if current == '[' && pattern.get(i + 1) == Some(&':') {
+            let rest: String = pattern[i + 2..].iter().collect();
+
+            if let Some(end) = rest.find(":]") {
+                matched |= match &rest[..end] {
+                    "alpha" => c.is_alphabetic(),
+                    "digit" => c.is_ascii_digit(),
+                    "alnum" => c.is_alphanumeric(),
+                    "upper" => c.is_uppercase(),
+                    "lower" => c.is_lowercase(),
+                    "space" => c.is_whitespace(),
+                    "blank" => c == ' ' || c == '\t',
+                    "punct" => c.is_ascii_punctuation(),
+                    "xdigit" => c.is_ascii_hexdigit(),
+                    "cntrl" => c.is_control(),
+                    _ => false,
+                };
+                i += 2 + rest[..end].chars().count() + 2;
+                continue;
+            }
+        }
+
+        let low = if current == '\\' {
+            i += 1;
+            *pattern.get(i)?
+        } else {
+            current
+        };
+
+        if pattern.get(i + 1) == Some(&'-') && pattern.get(i + 2).is_some_and(|&h| h != ']') {
+            let high = pattern[i + 2];
+
+            matched |= low <= c && c <= high;
+            i += 3;
+        } else {
+            matched |= low == c;
+            i += 1;
+        }
+    }
+}
+
+/// The pattern on the right of `==` or `!=`, where quoted parts match
+/// literally.
+fn glob_pattern(token: &TokenType) -> String {
+    match token {
+        TokenType::Word(s) => escape_quoted(s, glob_escape),
+        _ => glob_escape(&word_value(token)),
+    }
+}
+
+fn glob_escape(s: &str) -> String {
+    let mut escaped = String::new();
+
+    for c in s.chars() {
+        if "\\*?[]".contains(c) {
+            escaped.push('\\');
+        }
+
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+/// Removes the quotes of a word as written and passes what they protected
+/// through `escape`, so that it matches literally in a pattern. Backslash
+/// escapes outside quotes are left for the pattern to interpret.
+pub fn escape_quoted(word: &str, escape: fn(&str) -> String) -> String {
+    let mut pattern = String::new();
+    let mut chars = word.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                pattern.push(c);
+                pattern.extend(chars.next());
+            }
+            '\'' | '"' => {
+                let mut quoted = String::from(c);
+                let mut escaped = false;
+
+                for q in chars.by_ref() {
+                    quoted.push(q);
+
+                    if q == c && !escaped {
+                        break;
+                    }
+
+                    escaped = c == '"' && q == '\\' && !escaped;
+                }
+
+                pattern.push_str(&escape(&remove_quotes(&quoted)));
+            }
+            _ => pattern.push(c),
+        }
+    }
+
+    pattern
+}
+
+pub fn regex_escape(s: &str) -> String {
+    let mut escaped = String::new();
+
+    for c in s.chars() {
+        if "\\.[]()*+?{}|^$".contains(c) {
+            escaped.push('\\');
+        }
+
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+/// Number of capture groups in an extended regular expression.
+fn count_groups(pattern: &str) -> usize {
+    let mut count = 0;
+    let mut chars = pattern.chars();
+    let mut in_bracket = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '[' if !in_bracket => in_bracket = true,
+            ']' if in_bracket => in_bracket = false,
+            '(' if !in_bracket => count += 1,
+            _ => {}
+        }
+    }
+
+    count
+}
+
+/// Matches `text` against an extended regular expression with the C library,
+/// the same engine other shells use, and fills `BASH_REMATCH`. The library
+/// works on bytes, so a group may end inside a multi-byte character.
+fn regex_match(shell: &mut Shell, text: &str, pattern: &str) -> Result<bool, String> {
+    let c_pattern = CString::new(pattern).map_err(|_| format!("{}: invalid regex", pattern))?;
+    let c_text = CString::new(text).map_err(|_| format!("{}: invalid string", text))?;
+    let mut regex: libc::regex_t = unsafe { mem::zeroed() };
+
+    if unsafe { libc::regcomp(&mut regex, c_pattern.as_ptr(), libc::REG_EXTENDED) } != 0 {
+        return Err(format!("{}: invalid regular expression", pattern));
+    }
+
+    let mut matches = vec![
+        libc::regmatch_t {
+            rm_so: -1,
+            rm_eo: -1
+        };
+        count_groups(pattern) + 1
+    ];
+
+    let found = unsafe {
+        libc::regexec(
+            &regex,
+            c_text.as_ptr(),
+            matches.len(),
+            matches.as_mut_ptr(),
+            0,
+        )
+    } == 0;
+
+    unsafe { libc::regfree(&mut regex) };
+
+    let groups = if found {
+        matches
+            .iter()
+            .map(|m| match (m.rm_so, m.rm_eo) {
+                (-1, _) => String::new(),
+                (start, end) => {
+                    String::from_utf8_lossy(&text.as_bytes()[start as usize..end as usize])
+                        .into_owned()
+                }
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    shell.set_var("BASH_REMATCH", Variable::Array(groups));
+
+    Ok(found)
+}
+
+#[test]
+fn test_test_by_argument_count() {
+    assert_eq!(test(&[]), Ok(false));
+    assert_eq!(test(&[""]), Ok(false));
+    assert_eq!(test(&["-n"]), Ok(true));
+    assert_eq!(test(&["!", ""]), Ok(true));
+    assert_eq!(test(&["-z", ""]), Ok(true));
+    assert_eq!(test(&["a", "=", "a"]), Ok(true));
+    assert_eq!(test(&["a", "!=", "a"]), Ok(false));
+    assert_eq!(test(&["!", "a", "=", "b"]), Ok(true));
+    assert_eq!(test(&["(", "a", ")"]), Ok(true));
+    assert_eq!(test(&["(", "-z", "a", ")"]), Ok(false));
+}
+
+#[test]
+fn test_test_connectives() {
+    assert_eq!(test(&["a", "-a", "b", "-o", ""]), Ok(true));
+    assert_eq!(test(&["", "-o", "b", "-a", ""]), Ok(false));
+    assert_eq!(test(&["!", "(", "1", "-eq", "2", ")", "-a", "x"]), Ok(true));
+}
+
+#[test]
+fn test_test_integers() {
+    assert_eq!(test(&["10", "-gt", "9"]), Ok(true));
+    assert_eq!(test(&[" 3 ", "-le", "-3"]), Ok(false));
+    assert_eq!(
+        test(&["a", "-eq", "1"]),
+        Err("a: integer expression expected".to_string())
+    );
+}
+
+#[test]
+fn test_test_files() {
+    assert_eq!(test(&["-d", "src"]), Ok(true));
+    assert_eq!(test(&["-f", "src"]), Ok(false));
+    assert_eq!(test(&["-e", "src/condition.rs"]), Ok(true));
+    assert_eq!(test(&["-s", "/nonexistent"]), Ok(false));
+    assert_eq!(test(&["src", "-ef", "./src"]), Ok(true));
+}
+
+#[test]
+fn test_bracket_needs_closing() {
+    use crate::builtins::capture;
+
+    let mut shell = Shell::new();
+
+    assert_eq!(capture(&mut shell, &["[", "a", "]"]).0, 0);
+    assert_eq!(
+        capture(&mut shell, &["[", "a"]),
+        (2, String::new(), "[: missing `]'\n".to_string())
+    );
+    assert_eq!(
+        capture(&mut shell, &["test", "a", "="]),
+        (
+            2,
+            String::new(),
+            "test: a: unary operator expected\n".to_string()
+        )
+    );
+}
+
+#[test]
+fn test_glob_match() {
+    assert!(glob_match("foo*", "foobar"));
+    assert!(glob_match("*bar", "foobar"));
+    assert!(glob_match("f?o*r", "foobar"));
+    assert!(glob_match("*", ""));
+    assert!(!glob_match("foo", "foobar"));
+    assert!(glob_match("[a-c]x", "bx"));
+    assert!(!glob_match("[!a-c]x", "bx"));
+    assert!(glob_match("[[:digit:]]*", "1abc"));
+    assert!(glob_match("\\*", "*"));
+    assert!(!glob_match("\\*", "a"));
+    assert!(glob_match("[", "["));
+}
+
+#[test]
+fn test_count_groups() {
+    assert_eq!(count_groups("a(b)(c(d))"), 3);
+    assert_eq!(count_groups("\\(a[(]"), 0);
+}
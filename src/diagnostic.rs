@@ -0,0 +1,127 @@
+use crate::parser::{ParseError, Span};
+use crate::tree::GrammarError;
+use std::error::Error;
+use std::fmt;
+
+/// A syntax error together with the part of the source it points at.
+#[derive(Debug, PartialEq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: {}",
+            self.span.line, self.span.column, self.message
+        )
+    }
+}
+
+impl Error for Diagnostic {}
+
+impl From<ParseError> for Diagnostic {
+    fn from(e: ParseError) -> Diagnostic {
+        Diagnostic {
+            message: e.kind.to_string(),
+            span: e.span,
+        }
+    }
+}
+
+impl From<GrammarError> for Diagnostic {
+    fn from(e: GrammarError) -> Diagnostic {
+        Diagnostic {
+            message: e.kind.to_string(),
+            span: e.span,
+        }
+    }
+}
+
+impl Diagnostic {
+    /// Shows the error the way rustc does: the file, line and column, then
+    /// the offending line of `source` with the span underlined. The span's
+    /// byte range must point into `source`.
+    pub fn render(&self, file: &str, source: &str) -> String {
+        let start = self.span.start.min(source.len());
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..]
+            .find('\n')
+            .map_or(source.len(), |i| start + i);
+        let text = source[line_start..line_end].trim_end_matches('\r');
+        let end = self.span.end.clamp(start, line_start + text.len());
+
+        // tabs are kept so the marker lines up with the text above it
+        let padding: String = source[line_start..start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let width = source[start..end].chars().count().max(1);
+        let number = self.span.line.to_string();
+        let gutter = " ".repeat(number.len());
+
+        format!(
+            "error: {message}\n\
+             {gutter}--> {file}:{line}:{column}\n\
+             {gutter} |\n\
+             {number} | {text}\n\
+             {gutter} | {padding}^{underline}\n",
+            message = self.message,
+            gutter = gutter,
+            file = file,
+            line = self.span.line,
+            column = self.span.column,
+            number = number,
+            text = text,
+            padding = padding,
+            underline = "~".repeat(width - 1),
+        )
+    }
+}
+
+#[test]
+fn test_render() {
+    let source = "true\necho \"hola\tmundo\n";
+    let diagnostic = Diagnostic {
+        message: "unterminated quote".to_string(),
+        span: Span {
+            start: 10,
+            end: 22,
+            line: 2,
+            column: 6,
+        },
+    };
+
+    assert_eq!(
+        diagnostic.render("script.sh", source),
+        "error: unterminated quote\n \
+         --> script.sh:2:6\n  \
+         |\n\
+         2 | echo \"hola\tmundo\n  \
+         |      ^~~~~~~~~~~\n"
+    );
+}
+
+#[test]
+fn test_render_at_end_of_line() {
+    let diagnostic = Diagnostic {
+        message: "missing redirection target".to_string(),
+        span: Span {
+            start: 3,
+            end: 3,
+            line: 1,
+            column: 4,
+        },
+    };
+
+    assert_eq!(
+        diagnostic.render("<stdin>", "ls >"),
+        "error: missing redirection target\n \
+         --> <stdin>:1:4\n  \
+         |\n\
+         1 | ls >\n  \
+         |    ^\n"
+    );
+}
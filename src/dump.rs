@@ -0,0 +1,469 @@
+use crate::parser::{Span, Token, TokenType};
+use crate::tree::{
+    AndOrExpr, AndOrExprOptions, CallExpr, CallExprOptions, Comment, CondExpr, CondExprOptions,
+    Expr, PipeExpr, Redirection, SemicolonExpr, SemicolonExprOptions,
+};
+
+/// How tokens and trees are printed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DumpFormat {
+    /// S-expressions meant for people: `(kind @line:column start..end :field
+    /// value…)` for every node and `[…]` for lists, broken over indented
+    /// lines when they get long.
+    SExpr,
+    /// JSON meant for tools. Every node is an object with a `type`, a `span`
+    /// with `start`, `end`, `line` and `column`, and its fields, always in the
+    /// same order.
+    Json,
+}
+
+/// Every token with its kind, its text and where it is.
+pub fn dump_tokens(tokens: &[Token], format: DumpFormat) -> String {
+    render(&Node::List(tokens.iter().map(token).collect()), format)
+}
+
+/// The trees of a script, one per complete command.
+pub fn dump_exprs(exprs: &[Expr], format: DumpFormat) -> String {
+    render(&Node::List(exprs.iter().map(expr).collect()), format)
+}
+
+/// What both formats are written from.
+enum Node {
+    Null,
+    Bool(bool),
+    Number(i64),
+    Text(String),
+    List(Vec<Node>),
+    Object {
+        kind: &'static str,
+        span: Span,
+        fields: Vec<(&'static str, Node)>,
+    },
+}
+
+/// Lines longer than this are broken in the S-expression format.
+const MAX_WIDTH: usize = 80;
+
+fn render(node: &Node, format: DumpFormat) -> String {
+    let mut output = match format {
+        DumpFormat::SExpr => sexpr(node, 0, 0),
+        DumpFormat::Json => json(node, 0),
+    };
+
+    output.push('\n');
+    output
+}
+
+fn object(kind: &'static str, span: Span, fields: Vec<(&'static str, Node)>) -> Node {
+    Node::Object { kind, span, fields }
+}
+
+fn token_kind(kind: &TokenType) -> (&'static str, &str) {
+    match kind {
+        TokenType::Word(s) => ("word", s),
+        TokenType::DoubleQuotedString(s) => ("double_quoted_string", s),
+        TokenType::SingleQuotedString(s) => ("single_quoted_string", s),
+        TokenType::Assignment(s) => ("assignment", s),
+        TokenType::IoNumber(s) => ("io_number", s),
+        TokenType::Comment(s) => ("comment", s),
+        TokenType::Or => ("or", "||"),
+        TokenType::And => ("and", "&&"),
+        TokenType::Ampersand => ("ampersand", "&"),
+        TokenType::Parenthesis('(') => ("parenthesis", "("),
+        TokenType::Parenthesis(_) => ("parenthesis", ")"),
+        TokenType::Pipe => ("pipe", "|"),
+        TokenType::Semicolon => ("semicolon", ";"),
+        TokenType::DoubleSemicolon => ("double_semicolon", ";;"),
+        TokenType::Newline => ("newline", "\n"),
+        TokenType::Less => ("less", "<"),
+        TokenType::Great => ("great", ">"),
+        TokenType::DoubleGreat => ("double_great", ">>"),
+        TokenType::DoubleLess => ("double_less", "<<"),
+        TokenType::DoubleLessDash => ("double_less_dash", "<<-"),
+        TokenType::LessAnd => ("less_and", "<&"),
+        TokenType::GreatAnd => ("great_and", ">&"),
+        TokenType::LessGreat => ("less_great", "<>"),
+        TokenType::Clobber => ("clobber", ">|"),
+    }
+}
+
+fn token(token: &Token) -> Node {
+    let (kind, text) = token_kind(&token.kind);
+
+    object(
+        kind,
+        token.span,
+        vec![("text", Node::Text(text.to_string()))],
+    )
+}
+
+fn expr(expr: &Expr) -> Node {
+    object(
+        "program",
+        expr.span,
+        vec![
+            ("body", list(&expr.value)),
+            (
+                "comments",
+                Node::List(expr.comments.iter().map(comment).collect()),
+            ),
+        ],
+    )
+}
+
+fn comment(comment: &Comment) -> Node {
+    object(
+        "comment",
+        comment.span,
+        vec![
+            ("text", Node::Text(comment.text.clone())),
+            ("own_line", Node::Bool(comment.own_line)),
+        ],
+    )
+}
+
+/// A list of commands, flattened: `a; b; c` is one list of three.
+fn list(expr: &SemicolonExpr) -> Node {
+    let mut commands = Vec::new();
+    let mut rest = expr;
+
+    loop {
+        match &rest.value {
+            SemicolonExprOptions::SingleExpr(and_or_expr) => {
+                commands.push(and_or(and_or_expr));
+                break;
+            }
+            SemicolonExprOptions::Semicolon(and_or_expr, next) => {
+                commands.push(and_or(and_or_expr));
+                rest = next;
+            }
+        }
+    }
+
+    object("list", expr.span, vec![("commands", Node::List(commands))])
+}
+
+fn and_or(expr: &AndOrExpr) -> Node {
+    let (kind, left, right) = match &expr.value {
+        AndOrExprOptions::SingleExpr(pipe_expr) => return pipe(pipe_expr),
+        AndOrExprOptions::And(left, right) => ("and", left, right),
+        AndOrExprOptions::Or(left, right) => ("or", left, right),
+    };
+
+    object(
+        kind,
+        expr.span,
+        vec![("left", and_or(left)), ("right", pipe(right))],
+    )
+}
+
+fn pipe(expr: &PipeExpr) -> Node {
+    object(
+        "pipeline",
+        expr.span,
+        vec![
+            ("negated", Node::Bool(expr.negated)),
+            (
+                "commands",
+                Node::List(expr.commands.iter().map(call).collect()),
+            ),
+        ],
+    )
+}
+
+fn call(expr: &CallExpr) -> Node {
+    let (kind, mut fields) = match &expr.value {
+        CallExprOptions::ProgCall(name, args) => (
+            "command",
+            vec![
+                ("name", token(name)),
+                ("args", Node::List(args.iter().map(token).collect())),
+            ],
+        ),
+        CallExprOptions::Parenthesis(inner) => ("subshell", vec![("body", list(&inner.value))]),
+        CallExprOptions::Braces(inner) => ("group", vec![("body", list(&inner.value))]),
+        CallExprOptions::Conditional(cond_expr) => {
+            ("conditional", vec![("expr", condition(cond_expr))])
+        }
+        CallExprOptions::Empty => ("empty", vec![]),
+    };
+
+    fields.push((
+        "redirections",
+        Node::List(expr.redirections.iter().map(redirection).collect()),
+    ));
+
+    object(kind, expr.span, fields)
+}
+
+fn redirection(redirection: &Redirection) -> Node {
+    object(
+        "redirection",
+        redirection.span,
+        vec![
+            (
+                "fd",
+                redirection
+                    .fd
+                    .map_or(Node::Null, |fd| Node::Number(fd.into())),
+            ),
+            (
+                "operator",
+                Node::Text(token_kind(&redirection.operator).1.to_string()),
+            ),
+            ("target", token(&redirection.target)),
+        ],
+    )
+}
+
+fn condition(expr: &CondExpr) -> Node {
+    let text = |s: &str| Node::Text(s.to_string());
+    let (kind, fields) = match &expr.value {
+        CondExprOptions::Word(word) => ("test_word", vec![("word", token(word))]),
+        CondExprOptions::Unary(operator, operand) => (
+            "test_unary",
+            vec![("operator", text(operator)), ("operand", token(operand))],
+        ),
+        CondExprOptions::Binary(left, operator, right) => (
+            "test_binary",
+            vec![
+                ("left", token(left)),
+                ("operator", text(operator)),
+                ("right", token(right)),
+            ],
+        ),
+        CondExprOptions::Not(inner) => ("test_not", vec![("expr", condition(inner))]),
+        CondExprOptions::And(left, right) => (
+            "test_and",
+            vec![("left", condition(left)), ("right", condition(right))],
+        ),
+        CondExprOptions::Or(left, right) => (
+            "test_or",
+            vec![("left", condition(left)), ("right", condition(right))],
+        ),
+    };
+
+    object(kind, expr.span, fields)
+}
+
+/// A string literal both formats can read, as JSON writes them.
+fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+fn sexpr_flat(node: &Node) -> String {
+    match node {
+        Node::Null => "nil".to_string(),
+        Node::Bool(b) => b.to_string(),
+        Node::Number(n) => n.to_string(),
+        Node::Text(s) => quote(s),
+        Node::List(items) => format!(
+            "[{}]",
+            items.iter().map(sexpr_flat).collect::<Vec<_>>().join(" ")
+        ),
+        Node::Object { kind, span, fields } => {
+            let mut text = format!("({}{}", kind, sexpr_span(span));
+
+            for (name, value) in fields {
+                text.push_str(&format!(" :{} {}", name, sexpr_flat(value)));
+            }
+
+            text.push(')');
+            text
+        }
+    }
+}
+
+fn sexpr_span(span: &Span) -> String {
+    format!(
+        " @{}:{} {}..{}",
+        span.line, span.column, span.start, span.end
+    )
+}
+
+/// `node` on one line if it fits when it starts at `column`, otherwise with
+/// a line for each field or item, indented two columns past `indent`.
+fn sexpr(node: &Node, indent: usize, column: usize) -> String {
+    let flat = sexpr_flat(node);
+
+    if column + flat.chars().count() <= MAX_WIDTH {
+        return flat;
+    }
+
+    let padding = " ".repeat(indent + 2);
+
+    match node {
+        Node::List(items) => {
+            let mut text = String::from("[");
+
+            for item in items {
+                text.push_str(&format!(
+                    "\n{}{}",
+                    padding,
+                    sexpr(item, indent + 2, indent + 2)
+                ));
+            }
+
+            text.push(']');
+            text
+        }
+        Node::Object { kind, span, fields } => {
+            let mut text = format!("({}{}", kind, sexpr_span(span));
+
+            for (name, value) in fields {
+                let prefix = format!(":{} ", name);
+                let value = sexpr(value, indent + 2, indent + 2 + prefix.len());
+
+                text.push_str(&format!("\n{}{}{}", padding, prefix, value));
+            }
+
+            text.push(')');
+            text
+        }
+        _ => flat,
+    }
+}
+
+fn json(node: &Node, indent: usize) -> String {
+    let inner = " ".repeat(indent + 2);
+    let outer = " ".repeat(indent);
+
+    match node {
+        Node::Null => "null".to_string(),
+        Node::Bool(b) => b.to_string(),
+        Node::Number(n) => n.to_string(),
+        Node::Text(s) => quote(s),
+        Node::List(items) if items.is_empty() => "[]".to_string(),
+        Node::List(items) => {
+            let items: Vec<String> = items
+                .iter()
+                .map(|item| format!("{}{}", inner, json(item, indent + 2)))
+                .collect();
+
+            format!("[\n{}\n{}]", items.join(",\n"), outer)
+        }
+        Node::Object { kind, span, fields } => {
+            let mut members = vec![
+                format!("{}\"type\": {}", inner, quote(kind)),
+                format!(
+                    "{}\"span\": {{\"start\": {}, \"end\": {}, \"line\": {}, \"column\": {}}}",
+                    inner, span.start, span.end, span.line, span.column
+                ),
+            ];
+
+            for (name, value) in fields {
+                members.push(format!(
+                    "{}{}: {}",
+                    inner,
+                    quote(name),
+                    json(value, indent + 2)
+                ));
+            }
+
+            format!("{{\n{}\n{}}}", members.join(",\n"), outer)
+        }
+    }
+}
+
+#[test]
+fn test_dump_tokens() {
+    use crate::parser::parse;
+
+    assert_eq!(
+        dump_tokens(&parse("ls 2>&1 # \"x\"").unwrap(), DumpFormat::SExpr),
+        "[
+  (word @1:1 0..2 :text \"ls\")
+  (io_number @1:4 3..4 :text \"2\")
+  (great_and @1:5 4..6 :text \">&\")
+  (word @1:7 6..7 :text \"1\")
+  (comment @1:9 8..13 :text \"# \\\"x\\\"\")]
+"
+    );
+    assert_eq!(
+        dump_tokens(&parse("a\n").unwrap(), DumpFormat::Json),
+        r#"[
+  {
+    "type": "word",
+    "span": {"start": 0, "end": 1, "line": 1, "column": 1},
+    "text": "a"
+  },
+  {
+    "type": "newline",
+    "span": {"start": 1, "end": 2, "line": 1, "column": 2},
+    "text": "\n"
+  }
+]
+"#
+    );
+}
+
+#[test]
+fn test_dump_exprs() {
+    use crate::parser::parse;
+    use crate::tree::parse_program;
+
+    let expr = parse_program(&parse("! a >&2 || b").unwrap()).unwrap();
+
+    assert_eq!(
+        dump_exprs(&[expr], DumpFormat::SExpr),
+        r#"[
+  (program @1:1 0..12
+    :body (list @1:1 0..12
+      :commands [
+        (or @1:1 0..12
+          :left (pipeline @1:1 0..7
+            :negated true
+            :commands [
+              (command @1:3 2..7
+                :name (word @1:3 2..3 :text "a")
+                :args []
+                :redirections [
+                  (redirection @1:5 4..7
+                    :fd nil
+                    :operator ">&"
+                    :target (word @1:7 6..7 :text "2"))])])
+          :right (pipeline @1:12 11..12
+            :negated false
+            :commands [
+              (command @1:12 11..12
+                :name (word @1:12 11..12 :text "b")
+                :args []
+                :redirections [])]))])
+    :comments [])]
+"#
+    );
+}
+
+#[test]
+fn test_dump_word_spans() {
+    use crate::parser::parse;
+    use crate::tree::parse_program;
+
+    let expr = parse_program(&parse("echo  hi").unwrap()).unwrap();
+    let json = dump_exprs(&[expr], DumpFormat::Json);
+
+    assert!(json.contains(
+        r#""args": [
+                {
+                  "type": "word",
+                  "span": {"start": 6, "end": 8, "line": 1, "column": 7},
+                  "text": "hi"
+                }
+              ]"#
+    ));
+}
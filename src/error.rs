@@ -0,0 +1,196 @@
+use crate::diagnostic::Diagnostic;
+use crate::parser::ParseError;
+use crate::process::os_error_message;
+use crate::shell::ReadonlyVariable;
+use crate::tree::GrammarError;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Everything that can go wrong between reading a line and running it. The
+/// shell reports these and goes on with the next command, using `status` as
+/// the exit status of the one that failed.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ShellError {
+    /// The line could not be split into tokens or turned into a tree.
+    Syntax(Diagnostic),
+    /// A word could not be expanded or assigned.
+    Expansion(String),
+    /// A redirection target could not be opened.
+    Redirection { target: String, source: io::Error },
+    /// No builtin or program in `PATH` goes by that name.
+    CommandNotFound(String),
+    /// A program could not be started.
+    Spawn { command: String, source: io::Error },
+    /// The interpreter named in a script's `#!` line could not be started.
+    BadInterpreter {
+        command: String,
+        interpreter: String,
+        source: io::Error,
+    },
+    /// A builtin or keyword failed in a way it does not report itself.
+    Builtin {
+        name: String,
+        message: String,
+        status: i32,
+    },
+    /// The shell itself could not read, write, fork or wait.
+    Io {
+        context: &'static str,
+        source: io::Error,
+    },
+}
+
+impl ShellError {
+    /// The conventional exit status for the error: 2 for misuse such as a
+    /// syntax error, 127 for a command that does not exist, 126 for one that
+    /// exists but cannot run and 1 for anything else.
+    pub fn status(&self) -> i32 {
+        match self {
+            ShellError::Syntax(_) => 2,
+            ShellError::CommandNotFound(_) => 127,
+            ShellError::Spawn { source, .. } if source.kind() == io::ErrorKind::NotFound => 127,
+            ShellError::Spawn { .. } | ShellError::BadInterpreter { .. } => 126,
+            ShellError::Builtin { status, .. } => *status,
+            ShellError::Expansion(_) | ShellError::Redirection { .. } | ShellError::Io { .. } => 1,
+        }
+    }
+
+    /// Prints the error to stderr and returns its exit status.
+    pub fn report(&self) -> i32 {
+        eprintln!("rust_shell: {}", self);
+        self.status()
+    }
+}
+
+impl fmt::Display for ShellError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShellError::Syntax(diagnostic) => write!(f, "{}", diagnostic),
+            ShellError::Expansion(message) => f.write_str(message),
+            ShellError::Redirection { target, source } => {
+                write!(f, "{}: {}", target, os_error_message(source))
+            }
+            ShellError::CommandNotFound(command) => write!(f, "{}: command not found", command),
+            ShellError::Spawn { command, source } => {
+                write!(f, "{}: {}", command, os_error_message(source))
+            }
+            ShellError::BadInterpreter {
+                command,
+                interpreter,
+                source,
+            } => write!(
+                f,
+                "{}: {}: bad interpreter: {}",
+                command,
+                interpreter,
+                os_error_message(source)
+            ),
+            ShellError::Builtin { name, message, .. } => write!(f, "{}: {}", name, message),
+            ShellError::Io { context, source } => {
+                write!(f, "{}: {}", context, os_error_message(source))
+            }
+        }
+    }
+}
+
+impl Error for ShellError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ShellError::Syntax(diagnostic) => Some(diagnostic),
+            ShellError::Redirection { source, .. }
+            | ShellError::Spawn { source, .. }
+            | ShellError::BadInterpreter { source, .. }
+            | ShellError::Io { source, .. } => Some(source),
+            ShellError::Expansion(_)
+            | ShellError::CommandNotFound(_)
+            | ShellError::Builtin { .. } => None,
+        }
+    }
+}
+
+impl From<Diagnostic> for ShellError {
+    fn from(diagnostic: Diagnostic) -> ShellError {
+        ShellError::Syntax(diagnostic)
+    }
+}
+
+impl From<ParseError> for ShellError {
+    fn from(e: ParseError) -> ShellError {
+        ShellError::Syntax(e.into())
+    }
+}
+
+impl From<GrammarError> for ShellError {
+    fn from(e: GrammarError) -> ShellError {
+        ShellError::Syntax(e.into())
+    }
+}
+
+impl From<ReadonlyVariable> for ShellError {
+    fn from(e: ReadonlyVariable) -> ShellError {
+        ShellError::Expansion(e.to_string())
+    }
+}
+
+#[test]
+fn test_status_and_message() {
+    use crate::parser::{ParseErrorKind, Span};
+
+    let spawn = |code: i32| ShellError::Spawn {
+        command: "./foo".to_string(),
+        source: io::Error::from_raw_os_error(code),
+    };
+
+    let syntax = ShellError::from(ParseError {
+        kind: ParseErrorKind::UnterminatedQuote,
+        span: Span {
+            start: 5,
+            end: 9,
+            line: 3,
+            column: 6,
+        },
+    });
+
+    assert_eq!(syntax.status(), 2);
+    assert_eq!(syntax.to_string(), "3:6: unterminated quote");
+    assert!(syntax.source().is_some());
+
+    let not_found = ShellError::CommandNotFound("foo".to_string());
+
+    assert_eq!(not_found.status(), 127);
+    assert_eq!(not_found.to_string(), "foo: command not found");
+    assert_eq!(spawn(libc::ENOENT).status(), 127);
+    assert_eq!(
+        spawn(libc::ENOENT).to_string(),
+        "./foo: No such file or directory"
+    );
+    assert_eq!(spawn(libc::EACCES).status(), 126);
+    assert_eq!(spawn(libc::EACCES).to_string(), "./foo: Permission denied");
+    assert_eq!(spawn(libc::E2BIG).status(), 126);
+
+    let interpreter = ShellError::BadInterpreter {
+        command: "./foo".to_string(),
+        interpreter: "/bin/nope".to_string(),
+        source: io::Error::from_raw_os_error(libc::ENOENT),
+    };
+
+    assert_eq!(interpreter.status(), 126);
+    assert_eq!(
+        interpreter.to_string(),
+        "./foo: /bin/nope: bad interpreter: No such file or directory"
+    );
+
+    let redirection = ShellError::Redirection {
+        target: "out".to_string(),
+        source: io::Error::from_raw_os_error(libc::EACCES),
+    };
+
+    assert_eq!(redirection.status(), 1);
+    assert_eq!(redirection.to_string(), "out: Permission denied");
+    assert_eq!(
+        ShellError::from(ReadonlyVariable("A".to_string())).to_string(),
+        "A: readonly variable"
+    );
+}
@@ -0,0 +1,518 @@
+use crate::diagnostic::Diagnostic;
+use crate::parser::{Token, TokenType};
+use crate::source;
+use crate::tree::{
+    AndOrExpr, AndOrExprOptions, CallExpr, CallExprOptions, Comment, CondExpr, CondExprOptions,
+    Expr, PipeExpr, Redirection, SemicolonExpr, SemicolonExprOptions,
+};
+
+/// Which side of a line break `&&`, `||` and `|` go on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OperatorPosition {
+    /// The operator ends the first line: `a &&`, then `b`.
+    EndOfLine,
+    /// The first line ends with a backslash and the operator starts the
+    /// next one: `a \`, then `&& b`.
+    StartOfLine,
+}
+
+/// How the formatter lays out the source.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct FormatOptions {
+    /// Spaces per level of indentation.
+    pub indent: usize,
+    /// Lines longer than this are broken at operators and inside groups.
+    pub max_width: usize,
+    pub operators: OperatorPosition,
+}
+
+impl Default for FormatOptions {
+    fn default() -> FormatOptions {
+        FormatOptions {
+            indent: 4,
+            max_width: 80,
+            operators: OperatorPosition::EndOfLine,
+        }
+    }
+}
+
+/// Writes `script` back with consistent spacing and indentation. Comments
+/// stay where they were, runs of blank lines between commands become one and
+/// aliases are left alone.
+pub fn format_script(script: &str, options: &FormatOptions) -> Result<String, Diagnostic> {
+    let mut output = String::new();
+    let mut last_line = None;
+
+    for command in source::read_commands(script, true, None, true)? {
+        if last_line.is_some_and(|line| command.lines.start() - line > 1) {
+            output.push('\n');
+        }
+
+        output.push_str(&format_expr(&command.expr, options));
+        output.push('\n');
+        last_line = Some(*command.lines.end());
+    }
+
+    Ok(output)
+}
+
+/// Writes a tree back as source the shell parses into the same tree, without
+/// a final newline. Lists that do not fit on one line get a line per
+/// command, groups get their contents indented.
+pub fn format_expr(expr: &Expr, options: &FormatOptions) -> String {
+    let mut formatter = Formatter {
+        options,
+        comments: &expr.comments,
+        written: 0,
+    };
+
+    formatter.list(&expr.value, 0, usize::MAX)
+}
+
+struct Formatter<'a> {
+    options: &'a FormatOptions,
+    comments: &'a [Comment],
+    /// How many of `comments` have been written.
+    written: usize,
+}
+
+impl<'a> Formatter<'a> {
+    fn indent(&self, level: usize) -> String {
+        " ".repeat(level * self.options.indent)
+    }
+
+    fn fits(&self, level: usize, text: &str) -> bool {
+        level * self.options.indent + text.chars().count() <= self.options.max_width
+    }
+
+    /// The next comment to write if it starts before byte `end`.
+    fn comment_before(&self, end: usize) -> Option<&'a Comment> {
+        self.comments
+            .get(self.written)
+            .filter(|comment| comment.span.start < end)
+    }
+
+    /// Writes every comment before byte `end`, each on a line of its own.
+    fn comment_lines(&mut self, end: usize, level: usize, lines: &mut Vec<String>) {
+        while let Some(comment) = self.comment_before(end) {
+            lines.push(format!("{}{}", self.indent(level), comment.text));
+            self.written += 1;
+        }
+    }
+
+    /// The comment on the same line as whatever ended before it, if it starts
+    /// before byte `end`.
+    fn trailing_comment(&mut self, end: usize) -> Option<&'a str> {
+        let comment = self
+            .comment_before(end)
+            .filter(|comment| !comment.own_line)?;
+
+        self.written += 1;
+
+        Some(&comment.text)
+    }
+
+    /// A list of commands, on one line if it fits and has no comments inside,
+    /// otherwise one command per line. Comments before byte `end` belong to
+    /// it. The first line is not indented, the others are.
+    fn list(&mut self, expr: &SemicolonExpr, level: usize, end: usize) -> String {
+        let items = list_items(expr);
+        let last_end = items.last().map_or(0, |item| item.span.end);
+
+        if self.comment_before(last_end).is_none() {
+            let flat = flat_list(expr);
+            let trailing = self.comments[self.written..]
+                .iter()
+                .take_while(|comment| comment.span.start < end)
+                .collect::<Vec<_>>();
+
+            match trailing[..] {
+                [] if self.fits(level, &flat) => return flat,
+                [comment] if !comment.own_line => {
+                    let line = format!("{} {}", flat, comment.text);
+
+                    if self.fits(level, &line) {
+                        self.written += 1;
+                        return line;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut lines = Vec::new();
+
+        for (i, item) in items.iter().enumerate() {
+            self.comment_lines(item.span.start, level, &mut lines);
+
+            let mut line = format!("{}{}", self.indent(level), self.and_or(item, level));
+            let next = items.get(i + 1).map_or(end, |next| next.span.start);
+
+            if let Some(comment) = self.trailing_comment(next) {
+                line.push(' ');
+                line.push_str(comment);
+            }
+
+            // a line with nothing but comments
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            lines.push(line);
+        }
+
+        self.comment_lines(end, level, &mut lines);
+
+        lines.join("\n").split_off(self.indent(level).len())
+    }
+
+    /// Pipelines joined by `&&` and `||`, broken after each operator when the
+    /// whole does not fit.
+    fn and_or(&mut self, expr: &AndOrExpr, level: usize) -> String {
+        let flat = flat_and_or(expr);
+
+        if self.comment_before(expr.span.end).is_none() && self.fits(level, &flat) {
+            return flat;
+        }
+
+        let mut pipelines = Vec::new();
+        let mut rest = expr;
+
+        loop {
+            match &rest.value {
+                AndOrExprOptions::SingleExpr(pipe_expr) => {
+                    pipelines.push(("", pipe_expr));
+                    break;
+                }
+                AndOrExprOptions::And(left, right) => {
+                    pipelines.push(("&&", right));
+                    rest = left;
+                }
+                AndOrExprOptions::Or(left, right) => {
+                    pipelines.push(("||", right));
+                    rest = left;
+                }
+            }
+        }
+
+        pipelines.reverse();
+
+        let mut text = self.pipe(pipelines[0].1, level);
+
+        for &(operator, pipe_expr) in &pipelines[1..] {
+            let next = self.line_break(operator, pipe_expr.span.start, level);
+            text.push_str(&next);
+            text.push_str(&self.pipe(pipe_expr, level + 1));
+        }
+
+        text
+    }
+
+    /// Commands joined by `|`, broken after each `|` when the whole does not
+    /// fit.
+    fn pipe(&mut self, expr: &PipeExpr, level: usize) -> String {
+        let flat = flat_pipe(expr);
+
+        if self.comment_before(expr.span.end).is_none() && self.fits(level, &flat) {
+            return flat;
+        }
+
+        let mut text = if expr.negated {
+            "! ".to_string()
+        } else {
+            String::new()
+        };
+
+        text.push_str(&self.call(&expr.commands[0], level));
+
+        for command in &expr.commands[1..] {
+            let next = self.line_break("|", command.span.start, level);
+            text.push_str(&next);
+            text.push_str(&self.call(command, level + 1));
+        }
+
+        text
+    }
+
+    /// What goes between two operands broken over two lines, `operator`
+    /// included, with the comments in between. The operand that follows is
+    /// at byte `next`.
+    fn line_break(&mut self, operator: &str, next: usize, level: usize) -> String {
+        let indent = self.indent(level + 1);
+
+        if self.comment_before(next).is_none()
+            && self.options.operators == OperatorPosition::StartOfLine
+        {
+            return format!(" \\\n{}{} ", indent, operator);
+        }
+
+        // a backslash cannot come after a comment, so those keep the operator
+        // at the end of the line
+        let mut text = format!(" {}", operator);
+
+        if let Some(comment) = self.trailing_comment(next) {
+            text.push(' ');
+            text.push_str(comment);
+        }
+
+        let mut lines = Vec::new();
+
+        self.comment_lines(next, level + 1, &mut lines);
+
+        for line in lines {
+            text.push('\n');
+            text.push_str(&line);
+        }
+
+        text.push('\n');
+        text.push_str(&indent);
+        text
+    }
+
+    /// A single command. Groups that do not fit on one line get their
+    /// contents on lines of their own, indented one level more.
+    fn call(&mut self, expr: &CallExpr, level: usize) -> String {
+        let (opening, inner, closing) = match &expr.value {
+            CallExprOptions::Braces(inner) => ("{", inner, "}"),
+            CallExprOptions::Parenthesis(inner) => ("(", inner, ")"),
+            _ => return flat_call(expr),
+        };
+
+        let flat = flat_call(expr);
+
+        if self.comment_before(expr.span.end).is_none() && self.fits(level, &flat) {
+            return flat;
+        }
+
+        let body = self.list(&inner.value, level + 1, expr.span.end);
+
+        format!(
+            "{}\n{}{}\n{}{}{}",
+            opening,
+            self.indent(level + 1),
+            body,
+            self.indent(level),
+            closing,
+            flat_redirections(&expr.redirections)
+        )
+    }
+}
+
+fn list_items(expr: &SemicolonExpr) -> Vec<&AndOrExpr> {
+    match &expr.value {
+        SemicolonExprOptions::SingleExpr(and_or_expr) => vec![and_or_expr],
+        SemicolonExprOptions::Semicolon(and_or_expr, rest) => {
+            let mut items = vec![and_or_expr];
+
+            items.extend(list_items(rest));
+            items
+        }
+    }
+}
+
+/// A word as it was written.
+fn word(token: &Token) -> &str {
+    match &token.kind {
+        TokenType::Word(s)
+        | TokenType::DoubleQuotedString(s)
+        | TokenType::SingleQuotedString(s)
+        | TokenType::Assignment(s)
+        | TokenType::IoNumber(s) => s,
+        _ => unreachable!(),
+    }
+}
+
+fn flat_list(expr: &SemicolonExpr) -> String {
+    list_items(expr)
+        .into_iter()
+        .map(flat_and_or)
+        .filter(|item| !item.is_empty())
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+fn flat_and_or(expr: &AndOrExpr) -> String {
+    match &expr.value {
+        AndOrExprOptions::SingleExpr(pipe_expr) => flat_pipe(pipe_expr),
+        AndOrExprOptions::And(left, right) => {
+            format!("{} && {}", flat_and_or(left), flat_pipe(right))
+        }
+        AndOrExprOptions::Or(left, right) => {
+            format!("{} || {}", flat_and_or(left), flat_pipe(right))
+        }
+    }
+}
+
+fn flat_pipe(expr: &PipeExpr) -> String {
+    let commands = expr
+        .commands
+        .iter()
+        .map(flat_call)
+        .collect::<Vec<_>>()
+        .join(" | ");
+
+    if expr.negated {
+        format!("! {}", commands)
+    } else {
+        commands
+    }
+}
+
+fn flat_call(expr: &CallExpr) -> String {
+    let command = match &expr.value {
+        CallExprOptions::ProgCall(name, args) => {
+            let mut words = vec![word(name)];
+
+            words.extend(args.iter().map(word));
+            words.join(" ")
+        }
+        CallExprOptions::Parenthesis(inner) => format!("({})", flat_list(&inner.value)),
+        CallExprOptions::Braces(inner) => format!("{{ {}; }}", flat_list(&inner.value)),
+        CallExprOptions::Conditional(cond_expr) => format!("[[ {} ]]", flat_cond(cond_expr)),
+        CallExprOptions::Empty => String::new(),
+    };
+
+    let redirections = flat_redirections(&expr.redirections);
+
+    // a command of nothing but redirections
+    if command.is_empty() {
+        return redirections.trim_start().to_string();
+    }
+
+    command + &redirections
+}
+
+fn flat_redirections(redirections: &[Redirection]) -> String {
+    redirections
+        .iter()
+        .map(|redirection| {
+            let operator = match redirection.operator {
+                TokenType::Less => "<",
+                TokenType::Great => ">",
+                TokenType::DoubleGreat => ">>",
+                TokenType::Clobber => ">|",
+                TokenType::LessGreat => "<>",
+                TokenType::LessAnd => "<&",
+                TokenType::GreatAnd => ">&",
+                _ => unreachable!(),
+            };
+            let fd = redirection.fd.map_or(String::new(), |fd| fd.to_string());
+
+            format!(" {}{}{}", fd, operator, word(&redirection.target))
+        })
+        .collect()
+}
+
+/// A conditional expression, with parentheses where `&&` binding tighter
+/// than `||` would otherwise change its meaning.
+fn flat_cond(expr: &CondExpr) -> String {
+    let operand = |expr: &CondExpr, grouped: bool| {
+        if grouped {
+            format!("( {} )", flat_cond(expr))
+        } else {
+            flat_cond(expr)
+        }
+    };
+    let is_or = |expr: &CondExpr| matches!(expr.value, CondExprOptions::Or(..));
+    let is_list = |expr: &CondExpr| {
+        matches!(
+            expr.value,
+            CondExprOptions::And(..) | CondExprOptions::Or(..)
+        )
+    };
+
+    match &expr.value {
+        CondExprOptions::Word(token) => word(token).to_string(),
+        CondExprOptions::Unary(operator, token) => format!("{} {}", operator, word(token)),
+        CondExprOptions::Binary(left, operator, right) => {
+            format!("{} {} {}", word(left), operator, word(right))
+        }
+        CondExprOptions::Not(inner) => format!("! {}", operand(inner, is_list(inner))),
+        CondExprOptions::And(left, right) => format!(
+            "{} && {}",
+            operand(left, is_or(left)),
+            operand(right, is_list(right))
+        ),
+        CondExprOptions::Or(left, right) => {
+            format!("{} || {}", flat_cond(left), operand(right, is_or(right)))
+        }
+    }
+}
+
+#[cfg(test)]
+fn options(indent: usize, max_width: usize, operators: OperatorPosition) -> FormatOptions {
+    FormatOptions {
+        indent,
+        max_width,
+        operators,
+    }
+}
+
+#[test]
+fn test_format_spacing() {
+    let options = FormatOptions::default();
+
+    assert_eq!(
+        format_script(
+            "A=1   ls   -l>out 2>&1\n{ echo a;echo b;}|cat\n(cd / ; ls) <in\n! [[ -f a&&( b == c||d ) ]]\n",
+            &options
+        )
+        .unwrap(),
+        "A=1 ls -l >out 2>&1\n\
+         { echo a; echo b; } | cat\n\
+         (cd /; ls) <in\n\
+         ! [[ -f a && ( b == c || d ) ]]\n"
+    );
+    assert_eq!(
+        format_script("2>/dev/null   ls\n>  out\n", &options).unwrap(),
+        "ls 2>/dev/null\n>out\n"
+    );
+}
+
+#[test]
+fn test_format_breaks_long_lines() {
+    let script = "aaaa bbbb && cccc dddd || eeee | ffff\n{ aaaa; bbbb; }\n";
+
+    assert_eq!(
+        format_script(script, &options(2, 12, OperatorPosition::EndOfLine)).unwrap(),
+        "aaaa bbbb &&\n  cccc dddd ||\n  eeee |\n    ffff\n{\n  aaaa; bbbb\n}\n"
+    );
+    assert_eq!(
+        format_script(script, &options(4, 12, OperatorPosition::StartOfLine)).unwrap(),
+        "aaaa bbbb \\\n    && cccc dddd \\\n    || eeee \\\n        | ffff\n{\n    aaaa\n    bbbb\n}\n"
+    );
+}
+
+#[test]
+fn test_format_keeps_comments() {
+    let script = "#!/bin/sh\n\n\n# setup\nls # list\ntrue &&   # then\n  false\n{\necho a # first\n  # more\n}\n";
+
+    assert_eq!(
+        format_script(script, &FormatOptions::default()).unwrap(),
+        "#!/bin/sh\n\n\
+         # setup\n\
+         ls # list\n\
+         true && # then\n    false\n\
+         {\n    echo a # first\n    # more\n}\n"
+    );
+}
+
+#[test]
+fn test_format_is_stable() {
+    let script = "cd /tmp &&\n  ls |\n  # count\n  wc -l\n(\n  cd / ; { ls; pwd; } >out\n) && echo 'a\nb' \\\n  c\n";
+
+    for operators in &[OperatorPosition::EndOfLine, OperatorPosition::StartOfLine] {
+        let options = options(2, 20, *operators);
+        let formatted = format_script(script, &options).unwrap();
+
+        assert_eq!(format_script(&formatted, &options).unwrap(), formatted);
+    }
+
+    assert_eq!(
+        format_script("ls &&\n", &FormatOptions::default())
+            .unwrap_err()
+            .message,
+        "expected a command"
+    );
+}
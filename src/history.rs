@@ -0,0 +1,67 @@
+use crate::shell::Shell;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// How many lines are kept in the history file.
+const HISTORY_SIZE: usize = 500;
+
+/// Where the history is kept between sessions: `HISTFILE`, or
+/// `~/.rust_shell_history` if it is not set.
+pub fn history_file(shell: &Shell) -> Option<PathBuf> {
+    match shell.var("HISTFILE") {
+        Some(file) if file.is_empty() => None,
+        Some(file) => Some(PathBuf::from(file)),
+        None => dirs::home_dir().map(|home| home.join(".rust_shell_history")),
+    }
+}
+
+/// Reads the history saved by a previous session, if there is one.
+pub fn load(shell: &mut Shell) {
+    if let Some(contents) = history_file(shell).and_then(|file| fs::read_to_string(file).ok()) {
+        shell.history = contents.lines().map(String::from).collect();
+    }
+}
+
+/// Writes the last `HISTORY_SIZE` lines of history to the history file.
+pub fn save(shell: &Shell) -> io::Result<()> {
+    let file = match history_file(shell) {
+        Some(file) => file,
+        None => return Ok(()),
+    };
+
+    let start = shell.history.len().saturating_sub(HISTORY_SIZE);
+    let mut contents = shell.history[start..].join("\n");
+
+    if !contents.is_empty() {
+        contents.push('\n');
+    }
+
+    fs::write(file, contents)
+}
+
+#[test]
+fn test_save_and_load() {
+    use crate::shell::Variable;
+
+    let path = std::env::temp_dir().join("rust_shell_test_history");
+    let mut shell = Shell::default();
+
+    shell.set_var("HISTFILE", Variable::Scalar(path.display().to_string()));
+    shell.history = (0..HISTORY_SIZE + 2)
+        .map(|i| format!("echo {}", i))
+        .collect();
+
+    save(&shell).unwrap();
+    shell.history.clear();
+    load(&mut shell);
+
+    assert_eq!(shell.history.len(), HISTORY_SIZE);
+    assert_eq!(shell.history[0], "echo 2");
+
+    fs::remove_file(&path).unwrap();
+
+    shell.set_var("HISTFILE", Variable::Scalar(String::new()));
+
+    assert_eq!(history_file(&shell), None);
+}
@@ -0,0 +1,54 @@
+//! A small POSIX-like shell: a lexer, a parser that builds a syntax tree and
+//! an evaluator that runs it.
+//!
+//! Each stage can be used on its own. [`parser`] turns source text into
+//! tokens that know where they came from, [`tree`] builds the syntax tree
+//! for a line of tokens and [`evaluate`] runs a tree in a [`Shell`], whose
+//! [`Builtins`] can be extended or replaced. [`parse_script`] goes from text
+//! to trees in one step, expanding aliases on the way.
+//!
+//! ```
+//! use rust_shell::{evaluate, parse_script, Shell, Stdio};
+//! use std::io::Write;
+//!
+//! let mut shell = Shell::new();
+//!
+//! shell.builtins.register(
+//!     "greet",
+//!     |_: &mut Shell, argv: &[&str], stdio: &mut Stdio| {
+//!         writeln!(stdio.stdout, "hello {}", argv[1]).ok();
+//!         0
+//!     },
+//! );
+//!
+//! for expr in parse_script(&shell, "greet world && false\n").unwrap() {
+//!     assert_eq!(evaluate(&mut shell, &expr), Ok(1));
+//! }
+//! ```
+
+mod alias;
+mod builtins;
+mod condition;
+mod diagnostic;
+pub mod dump;
+mod error;
+pub mod format;
+pub mod history;
+mod lookup;
+pub mod parser;
+mod printf;
+mod process;
+mod read;
+mod shell;
+mod source;
+mod trap;
+pub mod tree;
+mod variables;
+
+pub use crate::builtins::{Builtin, Builtins, FdReader, Stdio};
+pub use crate::diagnostic::Diagnostic;
+pub use crate::error::ShellError;
+pub use crate::process::{evaluate, evaluate_with_io, Exit, Io};
+pub use crate::shell::{Options, ReadonlyVariable, Shell, Variable};
+pub use crate::source::{needs_more_input, parse_interactive, parse_script};
+pub use crate::trap::run_exit;
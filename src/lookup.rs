@@ -0,0 +1,402 @@
+use crate::alias::quote;
+use crate::builtins::Stdio;
+use crate::process;
+use crate::shell::Shell;
+use crate::tree::RESERVED_WORDS;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// What a command name refers to, in the order the shell looks for it.
+#[derive(Debug, PartialEq)]
+enum Kind {
+    Alias(String),
+    Keyword,
+    Builtin,
+    File(PathBuf),
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+/// Where programs are looked for when `PATH` is not set at all.
+const DEFAULT_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+
+fn search_path(shell: &Shell) -> String {
+    shell
+        .var("PATH")
+        .unwrap_or_else(|| DEFAULT_PATH.to_string())
+}
+
+/// Every executable called `name` in the directories of `path`, in order. An
+/// empty entry stands for the current directory.
+pub fn find_in_path(path: &str, name: &str) -> Vec<PathBuf> {
+    path.split(':')
+        .map(|dir| Path::new(if dir.is_empty() { "." } else { dir }).join(name))
+        .filter(|candidate| is_executable(candidate))
+        .collect()
+}
+
+/// The program a command name runs: names with a `/` are paths already,
+/// anything else comes from the hash table or from the first match in
+/// `PATH`, which is then remembered.
+pub fn resolve(shell: &mut Shell, name: &str) -> Option<PathBuf> {
+    if name.contains('/') {
+        return Some(PathBuf::from(name));
+    }
+
+    if let Some(path) = shell.hash.get(name) {
+        if is_executable(path) {
+            return Some(path.clone());
+        }
+    }
+
+    let path = find_in_path(&search_path(shell), name).into_iter().next()?;
+
+    shell.hash.insert(name.to_string(), path.clone());
+
+    Some(path)
+}
+
+/// What `name` refers to. With `all` every match is returned, otherwise only
+/// the one that would run.
+fn classify(shell: &Shell, name: &str, all: bool) -> Vec<Kind> {
+    let mut kinds = Vec::new();
+
+    if let Some(value) = shell.aliases.get(name) {
+        kinds.push(Kind::Alias(value.clone()));
+    }
+
+    if RESERVED_WORDS.contains(&name) {
+        kinds.push(Kind::Keyword);
+    }
+
+    if shell.builtins.get(name).is_some() {
+        kinds.push(Kind::Builtin);
+    }
+
+    if name.contains('/') {
+        if is_executable(Path::new(name)) {
+            kinds.push(Kind::File(PathBuf::from(name)));
+        }
+    } else if all {
+        let path = search_path(shell);
+
+        kinds.extend(find_in_path(&path, name).into_iter().map(Kind::File));
+    } else if kinds.is_empty() {
+        let hashed = shell.hash.get(name).filter(|path| is_executable(path));
+
+        match hashed {
+            Some(path) => kinds.push(Kind::File(path.clone())),
+            None => {
+                let path = search_path(shell);
+
+                kinds.extend(
+                    find_in_path(&path, name)
+                        .into_iter()
+                        .take(1)
+                        .map(Kind::File),
+                );
+            }
+        }
+    }
+
+    if !all {
+        kinds.truncate(1);
+    }
+
+    kinds
+}
+
+/// The sentence `type` uses to describe a match.
+fn describe(shell: &Shell, name: &str, kind: &Kind, all: bool) -> String {
+    match kind {
+        Kind::Alias(value) => format!("{} is aliased to `{}'", name, value),
+        Kind::Keyword => format!("{} is a shell keyword", name),
+        Kind::Builtin => format!("{} is a shell builtin", name),
+        Kind::File(path) if !all && shell.hash.get(name) == Some(path) => {
+            format!("{} is hashed ({})", name, path.display())
+        }
+        Kind::File(path) => format!("{} is {}", name, path.display()),
+    }
+}
+
+/// `type [-a|-t] name…` tells what each name would run. `-t` prints only one
+/// of `alias`, `keyword`, `builtin` or `file`, and `-a` shows every match
+/// instead of only the first.
+pub fn execute_type(shell: &mut Shell, argv: &[&str], stdio: &mut Stdio) -> i32 {
+    let mut all = false;
+    let mut terse = false;
+    let mut names = &argv[1..];
+
+    while let Some(flag) = names.first().filter(|flag| flag.starts_with('-')) {
+        match *flag {
+            "--" => {
+                names = &names[1..];
+                break;
+            }
+            "-a" => all = true,
+            "-t" => terse = true,
+            _ => {
+                writeln!(stdio.stderr, "type: {}: invalid option", flag).ok();
+                return 2;
+            }
+        }
+
+        names = &names[1..];
+    }
+
+    let mut status = 0;
+
+    for name in names {
+        let kinds = classify(shell, name, all);
+
+        if kinds.is_empty() {
+            if !terse {
+                writeln!(stdio.stderr, "type: {}: not found", name).ok();
+            }
+
+            status = 1;
+        }
+
+        for kind in kinds {
+            let line = if terse {
+                match kind {
+                    Kind::Alias(_) => "alias".to_string(),
+                    Kind::Keyword => "keyword".to_string(),
+                    Kind::Builtin => "builtin".to_string(),
+                    Kind::File(_) => "file".to_string(),
+                }
+            } else {
+                describe(shell, name, &kind, all)
+            };
+
+            writeln!(stdio.stdout, "{}", line).ok();
+        }
+    }
+
+    status
+}
+
+/// `command -v name` prints how the shell would run `name`, `command -V name`
+/// describes it like `type` and `command name args…` runs a builtin or a
+/// program by that name, never anything else.
+pub fn execute_command(shell: &mut Shell, argv: &[&str], stdio: &mut Stdio) -> i32 {
+    match argv.get(1) {
+        Some(&"-v") | Some(&"-V") => {
+            let verbose = argv[1] == "-V";
+            let mut status = 0;
+
+            for name in &argv[2..] {
+                match classify(shell, name, false).first() {
+                    Some(kind) if verbose => {
+                        writeln!(stdio.stdout, "{}", describe(shell, name, kind, false)).ok();
+                    }
+                    Some(Kind::Alias(value)) => {
+                        writeln!(stdio.stdout, "alias {}={}", name, quote(value)).ok();
+                    }
+                    Some(Kind::File(path)) => {
+                        writeln!(stdio.stdout, "{}", path.display()).ok();
+                    }
+                    Some(_) => {
+                        writeln!(stdio.stdout, "{}", name).ok();
+                    }
+                    None => {
+                        if verbose {
+                            writeln!(stdio.stderr, "command: {}: not found", name).ok();
+                        }
+
+                        status = 1;
+                    }
+                }
+            }
+
+            status
+        }
+        Some(_) => {
+            let argv = if argv[1] == "--" {
+                &argv[2..]
+            } else {
+                &argv[1..]
+            };
+
+            match argv.first().and_then(|name| shell.builtins.get(name)) {
+                Some(builtin) => builtin.run(shell, argv, stdio),
+                None if argv.is_empty() => 0,
+                None => process::execute_program(shell, argv, &[]),
+            }
+        }
+        None => 0,
+    }
+}
+
+/// `hash` lists the remembered program paths, `hash name…` looks them up
+/// ahead of time, `hash -d name` forgets one and `hash -r` forgets them all,
+/// which is needed after `PATH` changes.
+pub fn execute_hash(shell: &mut Shell, argv: &[&str], stdio: &mut Stdio) -> i32 {
+    match &argv[1..] {
+        [] => {
+            if shell.hash.is_empty() {
+                writeln!(stdio.stdout, "hash: hash table empty").ok();
+            }
+
+            for (name, path) in &shell.hash {
+                writeln!(
+                    stdio.stdout,
+                    "hash -p {} {}",
+                    quote(&path.display().to_string()),
+                    name
+                )
+                .ok();
+            }
+
+            0
+        }
+        ["-r"] => {
+            shell.hash.clear();
+            0
+        }
+        ["-p", path, name] => {
+            shell.hash.insert(name.to_string(), PathBuf::from(path));
+            0
+        }
+        ["-d", names @ ..] => {
+            let mut status = 0;
+
+            for name in names {
+                if shell.hash.remove(*name).is_none() {
+                    writeln!(stdio.stderr, "hash: {}: not found", name).ok();
+                    status = 1;
+                }
+            }
+
+            status
+        }
+        [flag, ..] if flag.starts_with('-') => {
+            writeln!(stdio.stderr, "hash: {}: invalid option", flag).ok();
+            2
+        }
+        names => {
+            let mut status = 0;
+
+            for name in names {
+                if shell.builtins.get(name).is_some() {
+                    continue;
+                }
+
+                if resolve(shell, name).is_none() {
+                    writeln!(stdio.stderr, "hash: {}: not found", name).ok();
+                    status = 1;
+                }
+            }
+
+            status
+        }
+    }
+}
+
+#[cfg(test)]
+fn test_shell() -> (Shell, PathBuf) {
+    use crate::shell::Variable;
+    use std::env;
+
+    let dir = env::temp_dir().join("rust_shell_test_lookup");
+    let tool = dir.join("tool");
+
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(&tool, "#!/bin/sh\n").unwrap();
+    fs::set_permissions(&tool, fs::Permissions::from_mode(0o755)).unwrap();
+    fs::write(dir.join("data"), "").unwrap();
+
+    let mut shell = Shell::default();
+
+    shell.set_var(
+        "PATH",
+        Variable::Scalar(format!("/nonexistent:{}", dir.display())),
+    );
+
+    (shell, tool)
+}
+
+#[test]
+fn test_resolve_and_hash() {
+    use crate::builtins::capture;
+
+    let (mut shell, tool) = test_shell();
+
+    assert_eq!(capture(&mut shell, &["hash"]).1, "hash: hash table empty\n");
+    assert_eq!(resolve(&mut shell, "tool"), Some(tool.clone()));
+    assert_eq!(resolve(&mut shell, "data"), None);
+    assert_eq!(resolve(&mut shell, "./x"), Some(PathBuf::from("./x")));
+    assert_eq!(
+        capture(&mut shell, &["hash"]).1,
+        format!("hash -p '{}' tool\n", tool.display())
+    );
+    assert_eq!(capture(&mut shell, &["hash", "-r"]).0, 0);
+    assert!(shell.hash.is_empty());
+    assert_eq!(capture(&mut shell, &["hash", "tool", "nope"]).0, 1);
+    assert!(shell.hash.contains_key("tool"));
+}
+
+#[test]
+fn test_type() {
+    use crate::builtins::capture;
+
+    let (mut shell, tool) = test_shell();
+
+    shell.aliases.insert("cd".to_string(), "cd -P".to_string());
+
+    assert_eq!(
+        capture(&mut shell, &["type", "cd", "[[", "tool", "nope"]),
+        (
+            1,
+            format!(
+                "cd is aliased to `cd -P'\n[[ is a shell keyword\ntool is {}\n",
+                tool.display()
+            ),
+            "type: nope: not found\n".to_string()
+        )
+    );
+    assert_eq!(
+        capture(&mut shell, &["type", "-a", "cd"]).1,
+        "cd is aliased to `cd -P'\ncd is a shell builtin\n"
+    );
+    assert_eq!(
+        capture(&mut shell, &["type", "-t", "cd", "pwd", "tool", "nope"]),
+        (1, "alias\nbuiltin\nfile\n".to_string(), String::new())
+    );
+
+    resolve(&mut shell, "tool");
+
+    assert_eq!(
+        capture(&mut shell, &["type", "tool"]).1,
+        format!("tool is hashed ({})\n", tool.display())
+    );
+}
+
+#[test]
+fn test_command() {
+    use crate::builtins::capture;
+
+    let (mut shell, tool) = test_shell();
+
+    shell.aliases.insert("ll".to_string(), "ls -l".to_string());
+
+    assert_eq!(
+        capture(&mut shell, &["command", "-v", "ll", "cd", "tool", "nope"]),
+        (
+            1,
+            format!("alias ll='ls -l'\ncd\n{}\n", tool.display()),
+            String::new()
+        )
+    );
+    assert_eq!(
+        capture(&mut shell, &["command", "-V", "cd"]).1,
+        "cd is a shell builtin\n"
+    );
+    assert_eq!(
+        capture(&mut shell, &["command", "pwd", "-x"]),
+        (2, String::new(), "pwd: -x: invalid option\n".to_string())
+    );
+}
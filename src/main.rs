@@ -1,33 +1,285 @@
-use std::env::current_dir;
-use std::io::{self, BufRead, Write};
+use rust_shell::dump::{self, DumpFormat};
+use rust_shell::format::{self, FormatOptions, OperatorPosition};
+use rust_shell::{history, parser, Diagnostic, FdReader, Shell, ShellError};
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::mem;
 
-mod parser;
-mod process;
-mod tree;
+/// Leaves the shell with the status of the last command, after running the
+/// `EXIT` trap and saving the history.
+fn quit(shell: &mut Shell) -> ! {
+    rust_shell::run_exit(shell);
+
+    if let Err(e) = history::save(shell) {
+        ShellError::Io {
+            context: "could not save history",
+            source: e,
+        }
+        .report();
+    }
+
+    io::stdout().flush().ok();
+    std::process::exit(shell.status);
+}
+
+/// Reads a line from stdin one byte at a time, so that the input after it
+/// is left for the commands on the line, as `read` expects. An empty result
+/// means the end of the input.
+fn read_line(bytes: &mut Vec<u8>) -> io::Result<()> {
+    let mut stdin = FdReader(0);
+    let mut byte = [0];
+
+    while stdin.read(&mut byte)? == 1 {
+        bytes.push(byte[0]);
+
+        if byte[0] == b'\n' {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// How many reads in a row may fail before the shell stops waiting for input
+/// that will never come, as happens once the terminal is gone.
+const MAX_READ_ERRORS: usize = 10;
+
+/// `--format [--check] [--indent n] [--max-width n] [--operators-first]
+/// file…` prints each script formatted, or with `--check` fails if any of
+/// them would change. Returns the exit status.
+fn format_files(args: &[String]) -> i32 {
+    let mut options = FormatOptions::default();
+    let mut check = false;
+    let mut files = Vec::new();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let number = match arg.as_str() {
+            "--check" => {
+                check = true;
+                continue;
+            }
+            "--operators-first" => {
+                options.operators = OperatorPosition::StartOfLine;
+                continue;
+            }
+            "--indent" => &mut options.indent,
+            "--max-width" => &mut options.max_width,
+            _ if arg.starts_with("--") => {
+                eprintln!("rust_shell: {}: invalid option", arg);
+                return 2;
+            }
+            _ => {
+                files.push(arg);
+                continue;
+            }
+        };
+
+        match args.next().and_then(|n| n.parse().ok()) {
+            Some(n) => *number = n,
+            None => {
+                eprintln!("rust_shell: {}: number required", arg);
+                return 2;
+            }
+        }
+    }
+
+    if files.is_empty() {
+        eprintln!("rust_shell: --format: file required");
+        return 2;
+    }
+
+    let mut status = 0;
+
+    for file in files {
+        let script = match fs::read_to_string(file) {
+            Ok(script) => script,
+            Err(e) => {
+                eprintln!("rust_shell: {}: {}", file, e);
+                status = 2;
+                continue;
+            }
+        };
+
+        match format::format_script(&script, &options) {
+            Ok(formatted) if check => {
+                if formatted != script {
+                    eprintln!("rust_shell: {}: not formatted", file);
+                    status = status.max(1);
+                }
+            }
+            Ok(formatted) => print!("{}", formatted),
+            Err(e) => {
+                eprint!("{}", e.render(file, &script));
+                status = 2;
+            }
+        }
+    }
+
+    status
+}
+
+/// `--dump-tokens` or `--dump-ast`, then `[--json] (-c string | file)`,
+/// prints the tokens or the trees the shell reads from the source. Returns
+/// the exit status.
+fn dump_source(what: &str, args: &[String]) -> i32 {
+    let mut format = DumpFormat::SExpr;
+    let mut source = None;
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => format = DumpFormat::Json,
+            "-c" => match args.next() {
+                Some(text) => source = Some(("-c".to_string(), text.clone())),
+                None => {
+                    eprintln!("rust_shell: -c: option requires an argument");
+                    return 2;
+                }
+            },
+            _ if arg.starts_with('-') => {
+                eprintln!("rust_shell: {}: invalid option", arg);
+                return 2;
+            }
+            file => match fs::read_to_string(file) {
+                Ok(text) => source = Some((file.to_string(), text)),
+                Err(e) => {
+                    eprintln!("rust_shell: {}: {}", file, e);
+                    return 2;
+                }
+            },
+        }
+    }
+
+    let (name, text) = match source {
+        Some(source) => source,
+        None => {
+            eprintln!("rust_shell: {}: -c or a file required", what);
+            return 2;
+        }
+    };
+
+    let output = if what == "--dump-tokens" {
+        parser::parse_at(&text, 0, 1, true)
+            .map(|tokens| dump::dump_tokens(&tokens, format))
+            .map_err(Diagnostic::from)
+    } else {
+        rust_shell::parse_script(&Shell::default(), &text)
+            .map(|exprs| dump::dump_exprs(&exprs, format))
+    };
+
+    match output {
+        Ok(output) => {
+            print!("{}", output);
+            0
+        }
+        Err(e) => {
+            eprint!("{}", e.render(&name, &text));
+            2
+        }
+    }
+}
 
 fn main() {
-    let stdin = io::stdin();
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    match args.first().map(String::as_str) {
+        Some("--format") => std::process::exit(format_files(&args[1..])),
+        Some(what @ "--dump-tokens") | Some(what @ "--dump-ast") => {
+            std::process::exit(dump_source(what, &args[1..]))
+        }
+        _ => {}
+    }
+
+    let mut shell = Shell::new();
+    let mut read_errors = 0;
+
+    history::load(&mut shell);
+
+    // what was typed so far of a command that goes on in the next line
+    let mut input = String::new();
 
     loop {
-        if let Ok(s) = current_dir() {
-            print!("{}$ ", s.display());
+        let prompt = if !input.is_empty() {
+            "> ".to_string()
         } else {
-            print!("# ");
+            match shell.var("PWD") {
+                Some(s) => format!("{}$ ", s),
+                None => "# ".to_string(),
+            }
+        };
+
+        let mut stdout = io::stdout();
+
+        if let Err(e) = stdout
+            .write_all(prompt.as_bytes())
+            .and_then(|_| stdout.flush())
+        {
+            ShellError::Io {
+                context: "write error",
+                source: e,
+            }
+            .report();
         }
-        io::stdout().flush().ok().expect("Could not flush stdout");
 
-        let mut buf = String::new();
+        let mut bytes = Vec::new();
 
-        stdin
-            .lock()
-            .read_line(&mut buf)
-            .expect("Could not read from stdin");
+        match read_line(&mut bytes) {
+            Ok(_) => read_errors = 0,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                input.clear();
+                continue;
+            }
+            Err(e) => {
+                ShellError::Io {
+                    context: "read error",
+                    source: e,
+                }
+                .report();
 
-        if buf.len() == 0 {
-            println!();
-            break;
+                read_errors += 1;
+
+                if read_errors == MAX_READ_ERRORS {
+                    quit(&mut shell);
+                }
+
+                continue;
+            }
+        }
+
+        if bytes.is_empty() && input.is_empty() {
+            writeln!(stdout).ok();
+            quit(&mut shell);
         }
 
-        process::receive_command(&mut buf.split_whitespace().collect());
+        // stray bytes that are not UTF-8 should not cost the whole line
+        input.extend(parser::Utf8Chars::new(bytes.iter().copied()));
+
+        // at the end of the input what there is runs, or fails, as it is
+        if !bytes.is_empty() && rust_shell::needs_more_input(&shell, &input) {
+            continue;
+        }
+
+        let buf = mem::take(&mut input);
+
+        if !buf.trim().is_empty() {
+            shell.history.push(buf.trim_end().to_string());
+        }
+
+        let exprs = match rust_shell::parse_interactive(&shell, &buf) {
+            Ok(exprs) => exprs,
+            Err(e) => {
+                eprint!("{}", e.render("<stdin>", &buf));
+                shell.status = ShellError::from(e).status();
+                continue;
+            }
+        };
+
+        for expr in &exprs {
+            if rust_shell::evaluate(&mut shell, expr).is_err() {
+                quit(&mut shell);
+            }
+        }
     }
 }
@@ -1,145 +1,590 @@
-use std::iter::Iterator;
+use std::error::Error;
+use std::fmt;
 use std::iter::Peekable;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
+#[non_exhaustive]
 pub enum TokenType {
+    /// A word as written, quotes and all. Words made of nothing but a single
+    /// quoted string get one of the two kinds below instead.
     Word(String),
     DoubleQuotedString(String),
     SingleQuotedString(String),
+    /// A `NAME=value` word before the command name.
+    Assignment(String),
+    /// The digits right before a redirection operator, naming the file
+    /// descriptor it applies to.
+    IoNumber(String),
+    /// A `#` comment, up to but not including the newline. The grammar
+    /// skips these, they are only kept for tools that write the source back.
+    Comment(String),
     Or,
     And,
+    Ampersand,
     Parenthesis(char),
     Pipe,
     Semicolon,
+    DoubleSemicolon,
+    Newline,
+    Less,
+    Great,
+    DoubleGreat,
+    DoubleLess,
+    DoubleLessDash,
+    LessAnd,
+    GreatAnd,
+    LessGreat,
+    Clobber,
+}
+
+/// Where something is in the source: a range of bytes, and the line and
+/// column it starts at, both counting from 1.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    /// From the start of this span to the end of `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end: other.end,
+            ..self
+        }
+    }
+
+    /// An empty span where this one starts.
+    pub fn empty(self) -> Span {
+        Span {
+            end: self.start,
+            ..self
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Token {
+    pub kind: TokenType,
+    pub span: Span,
+    /// Where the source is after the token, which is not always on the line
+    /// it starts on.
+    pub(crate) end: Span,
+}
+
+impl Token {
+    /// An empty span right after the token.
+    pub fn end(&self) -> Span {
+        self.end
+    }
+}
+
+impl From<TokenType> for Token {
+    fn from(kind: TokenType) -> Token {
+        Token {
+            kind,
+            span: Span::default(),
+            end: Span::default(),
+        }
+    }
+}
+
+/// Tokens compare equal to their kind alone, which is all most callers care
+/// about.
+impl PartialEq<TokenType> for Token {
+    fn eq(&self, other: &TokenType) -> bool {
+        self.kind == *other
+    }
 }
 
 #[derive(Debug, PartialEq)]
-pub enum ParseError {
+#[non_exhaustive]
+pub enum ParseErrorKind {
     UnterminatedQuote,
-    IncompleteAnd,
+    UnterminatedExpansion,
     InvalidCharacter,
 }
 
-fn is_word(c: char) -> bool {
-    return c.is_alphanumeric() || c == '-';
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ParseErrorKind::UnterminatedQuote => "unterminated quote",
+            ParseErrorKind::UnterminatedExpansion => "unterminated expansion",
+            ParseErrorKind::InvalidCharacter => "invalid character",
+        })
+    }
 }
 
-fn get_word<T: Iterator<Item = char>>(iter: &mut Peekable<T>) -> String {
-    let mut result = String::new();
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub span: Span,
+}
 
-    while let Some(&c) = iter.peek() {
-        if !is_word(c) {
-            break;
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.span.line, self.span.column, self.kind)
+    }
+}
+
+impl Error for ParseError {}
+
+/// Characters that start an operator and so end any word before them.
+fn is_operator_start(c: char) -> bool {
+    "&|;<>()\n".contains(c)
+}
+
+fn is_blank(c: char) -> bool {
+    c != '\n' && c.is_whitespace()
+}
+
+/// Whether `word` has the form `NAME=value`.
+fn is_assignment(word: &str) -> bool {
+    match word.find('=') {
+        Some(i) => {
+            let name = &word[..i];
+
+            name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
         }
+        None => false,
+    }
+}
+
+/// Decodes UTF-8 from a stream of bytes, so the lexer can read straight from
+/// one. Invalid sequences come out as U+FFFD.
+pub struct Utf8Chars<B: Iterator<Item = u8>> {
+    bytes: Peekable<B>,
+}
 
-        result.push(c);
-        iter.next();
+impl<B: Iterator<Item = u8>> Utf8Chars<B> {
+    pub fn new(bytes: B) -> Utf8Chars<B> {
+        Utf8Chars {
+            bytes: bytes.peekable(),
+        }
     }
+}
+
+impl<B: Iterator<Item = u8>> Iterator for Utf8Chars<B> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let first = self.bytes.next()?;
+        let len = match first {
+            0x00..=0x7f => return Some(first as char),
+            0xc2..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf4 => 4,
+            _ => return Some(char::REPLACEMENT_CHARACTER),
+        };
+        let mut buf = [first, 0, 0, 0];
 
-    result
+        for byte in buf.iter_mut().take(len).skip(1) {
+            match self.bytes.peek() {
+                Some(&b) if b & 0xc0 == 0x80 => {
+                    *byte = b;
+                    self.bytes.next();
+                }
+                _ => return Some(char::REPLACEMENT_CHARACTER),
+            }
+        }
+
+        Some(
+            std::str::from_utf8(&buf[..len])
+                .ok()
+                .and_then(|s| s.chars().next())
+                .unwrap_or(char::REPLACEMENT_CHARACTER),
+        )
+    }
 }
 
-fn get_double_quoted_string<T: Iterator<Item = char>>(
-    iter: &mut Peekable<T>,
-) -> Result<String, ParseError> {
-    let mut result = String::from("\"");
-    iter.next();
+/// The characters of the source along with the position of the next one.
+struct Cursor<I: Iterator<Item = char>> {
+    chars: Peekable<I>,
+    offset: usize,
+    line: usize,
+    column: usize,
+}
+
+impl<I: Iterator<Item = char>> Cursor<I> {
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+
+        self.offset += c.len_utf8();
+
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+
+        Some(c)
+    }
 
-    while let Some(&c) = iter.peek() {
-        if c == '"' {
-            result.push(c);
-            iter.next();
-            return Ok(result);
+    /// Takes the next character if it is `c`.
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.next();
+            true
+        } else {
+            false
         }
+    }
 
-        result.push(c);
-        iter.next();
+    /// An empty span at the next character.
+    fn here(&self) -> Span {
+        Span {
+            start: self.offset,
+            end: self.offset,
+            line: self.line,
+            column: self.column,
+        }
     }
 
-    Err(ParseError::UnterminatedQuote)
+    /// From `start` to the next character.
+    fn since(&self, start: Span) -> Span {
+        Span {
+            end: self.offset,
+            ..start
+        }
+    }
+
+    fn error(&self, kind: ParseErrorKind, start: Span) -> ParseError {
+        ParseError {
+            kind,
+            span: self.since(start),
+        }
+    }
 }
 
-fn get_single_quoted_string<T: Iterator<Item = char>>(
-    iter: &mut Peekable<T>,
-) -> Result<String, ParseError> {
-    let mut result = String::from("\'");
-    iter.next();
+/// Splits a source into tokens as it reads it, following the token
+/// recognition rules of POSIX: operators are taken by longest match and
+/// everything else up to an unquoted blank or operator is a word. Quotes,
+/// backslashes and `$(…)`, `${…}` and `` `…` `` expansions are kept in the
+/// word as written.
+pub struct Lexer<I: Iterator<Item = char>> {
+    cursor: Cursor<I>,
+    /// Whether a word read now would come before the command name, where
+    /// `NAME=value` is an assignment.
+    command_prefix: bool,
+    /// Whether the last token was a redirection operator, whose target does
+    /// not end the command prefix.
+    after_redirection: bool,
+    /// Whether a `#` starting a word starts a comment.
+    comments: bool,
+    failed: bool,
+}
+
+impl<I: Iterator<Item = char>> Lexer<I> {
+    pub fn new(chars: I) -> Lexer<I> {
+        Lexer::at(chars, 0, 1)
+    }
 
-    while let Some(&c) = iter.peek() {
-        if c == '\'' {
-            result.push(c);
-            iter.next();
-            return Ok(result);
+    /// A lexer for text found at byte `offset` and line `line` of a longer
+    /// source, so the spans point into that source.
+    pub fn at(chars: I, offset: usize, line: usize) -> Lexer<I> {
+        Lexer {
+            cursor: Cursor {
+                chars: chars.peekable(),
+                offset,
+                line,
+                column: 1,
+            },
+            command_prefix: true,
+            after_redirection: false,
+            comments: true,
+            failed: false,
         }
+    }
 
-        result.push(c);
-        iter.next();
+    /// Whether `#` starts a comment, as it does unless an interactive shell
+    /// has `interactive_comments` off.
+    pub fn comments(mut self, enabled: bool) -> Lexer<I> {
+        self.comments = enabled;
+        self
     }
 
-    Err(ParseError::UnterminatedQuote)
+    fn get_operator(&mut self, c: char) -> TokenType {
+        let it = &mut self.cursor;
+        it.next();
+
+        match c {
+            '&' if it.eat('&') => TokenType::And,
+            '&' => TokenType::Ampersand,
+            '|' if it.eat('|') => TokenType::Or,
+            '|' => TokenType::Pipe,
+            ';' if it.eat(';') => TokenType::DoubleSemicolon,
+            ';' => TokenType::Semicolon,
+            '<' if it.eat('<') => {
+                if it.eat('-') {
+                    TokenType::DoubleLessDash
+                } else {
+                    TokenType::DoubleLess
+                }
+            }
+            '<' if it.eat('&') => TokenType::LessAnd,
+            '<' if it.eat('>') => TokenType::LessGreat,
+            '<' => TokenType::Less,
+            '>' if it.eat('>') => TokenType::DoubleGreat,
+            '>' if it.eat('&') => TokenType::GreatAnd,
+            '>' if it.eat('|') => TokenType::Clobber,
+            '>' => TokenType::Great,
+            '\n' => TokenType::Newline,
+            _ => TokenType::Parenthesis(c),
+        }
+    }
+
+    fn get_word(&mut self) -> Result<TokenType, ParseError> {
+        let mut word = String::new();
+        // where the word ends if it is a single quoted string
+        let mut quoted_until = None;
+
+        while let Some(c) = self.cursor.peek() {
+            if is_blank(c) || is_operator_start(c) {
+                break;
+            }
+
+            self.cursor.next();
+
+            match c {
+                '\\' => match self.cursor.next() {
+                    // an escaped newline joins the lines
+                    Some('\n') => {}
+                    Some(escaped) => {
+                        word.push('\\');
+                        word.push(escaped);
+                    }
+                    None => word.push('\\'),
+                },
+                '\'' | '"' => {
+                    let start = word.is_empty();
+
+                    word.push(c);
+                    get_quoted(&mut self.cursor, &mut word, c)?;
+
+                    if start {
+                        quoted_until = Some(word.len());
+                    }
+                }
+                '$' | '`' => {
+                    word.push(c);
+                    get_expansion(&mut self.cursor, &mut word, c)?;
+                }
+                '\0' => {
+                    let mut start = self.cursor.here();
+                    start.start -= 1;
+                    start.column -= 1;
+
+                    return Err(self.cursor.error(ParseErrorKind::InvalidCharacter, start));
+                }
+                _ => word.push(c),
+            }
+        }
+
+        let is_io_number = !word.is_empty()
+            && word.chars().all(|c| c.is_ascii_digit())
+            && matches!(self.cursor.peek(), Some('<') | Some('>'))
+            && word.parse::<i32>().is_ok();
+
+        Ok(if quoted_until == Some(word.len()) {
+            if word.starts_with('"') {
+                TokenType::DoubleQuotedString(word)
+            } else {
+                TokenType::SingleQuotedString(word)
+            }
+        } else if is_io_number {
+            TokenType::IoNumber(word)
+        } else if self.command_prefix && !self.after_redirection && is_assignment(&word) {
+            TokenType::Assignment(word)
+        } else {
+            TokenType::Word(word)
+        })
+    }
+
+    /// Keeps track of whether the next word could still be an assignment.
+    fn advance_state(&mut self, kind: &TokenType) {
+        let after_redirection = self.after_redirection;
+
+        self.after_redirection = false;
+
+        match kind {
+            TokenType::Word(w) if !after_redirection => {
+                self.command_prefix = self.command_prefix && (w == "{" || w == "!");
+            }
+            TokenType::DoubleQuotedString(_) | TokenType::SingleQuotedString(_)
+                if !after_redirection =>
+            {
+                self.command_prefix = false;
+            }
+            TokenType::Word(_)
+            | TokenType::DoubleQuotedString(_)
+            | TokenType::SingleQuotedString(_)
+            | TokenType::Assignment(_) => {}
+            TokenType::IoNumber(_) => {}
+            TokenType::Less
+            | TokenType::Great
+            | TokenType::DoubleGreat
+            | TokenType::DoubleLess
+            | TokenType::DoubleLessDash
+            | TokenType::LessAnd
+            | TokenType::GreatAnd
+            | TokenType::LessGreat
+            | TokenType::Clobber => self.after_redirection = true,
+            TokenType::Parenthesis(')') => self.command_prefix = false,
+            _ => self.command_prefix = true,
+        }
+    }
 }
 
-fn get_and<T: Iterator<Item = char>>(iter: &mut Peekable<T>) -> Result<TokenType, ParseError> {
-    iter.next();
+impl<I: Iterator<Item = char>> Iterator for Lexer<I> {
+    type Item = Result<Token, ParseError>;
+
+    fn next(&mut self) -> Option<Result<Token, ParseError>> {
+        if self.failed {
+            return None;
+        }
 
-    match iter.peek() {
-        Some(&'&') => {
-            iter.next();
-            Ok(TokenType::And)
+        while self.cursor.peek().is_some_and(is_blank) {
+            self.cursor.next();
         }
-        _ => Err(ParseError::IncompleteAnd),
+
+        let c = self.cursor.peek()?;
+        let start = self.cursor.here();
+
+        let kind = if self.comments && c == '#' {
+            // a comment runs up to the newline, which is a token of its own
+            let mut text = String::new();
+
+            while let Some(c) = self.cursor.peek().filter(|&c| c != '\n') {
+                self.cursor.next();
+                text.push(c);
+            }
+
+            TokenType::Comment(text)
+        } else if is_operator_start(c) {
+            self.get_operator(c)
+        } else {
+            match self.get_word() {
+                // nothing but escaped newlines, which only join lines
+                Ok(TokenType::Word(w)) if w.is_empty() => return self.next(),
+                Ok(kind) => kind,
+                Err(e) => {
+                    self.failed = true;
+                    return Some(Err(e));
+                }
+            }
+        };
+
+        self.advance_state(&kind);
+
+        Some(Ok(Token {
+            kind,
+            span: self.cursor.since(start),
+            end: self.cursor.here(),
+        }))
     }
 }
 
-fn get_or_pipe<T: Iterator<Item = char>>(iter: &mut Peekable<T>) -> TokenType {
-    iter.next();
+/// Reads the rest of a quoted string into `word`, the opening quote already
+/// in it. Inside double quotes a backslash escapes the next character and
+/// expansions are read whole.
+fn get_quoted<I: Iterator<Item = char>>(
+    it: &mut Cursor<I>,
+    word: &mut String,
+    quote: char,
+) -> Result<(), ParseError> {
+    let mut start = it.here();
+    start.start -= 1;
+    start.column -= 1;
 
-    match iter.peek() {
-        Some(&'|') => {
-            iter.next();
-            TokenType::Or
+    while let Some(c) = it.next() {
+        word.push(c);
+
+        match c {
+            _ if c == quote => return Ok(()),
+            '\\' if quote == '"' => {
+                if let Some(escaped) = it.next() {
+                    word.push(escaped);
+                }
+            }
+            '$' | '`' if quote == '"' => get_expansion(it, word, c)?,
+            _ => {}
         }
-        _ => TokenType::Pipe,
     }
+
+    Err(it.error(ParseErrorKind::UnterminatedQuote, start))
 }
 
-pub fn parse(line: &String) -> Result<Vec<TokenType>, ParseError> {
-    let mut tokens = Vec::new();
-    let mut it = line.chars().peekable();
-
-    while let Some(&c) = it.peek() {
-        if c == '"' {
-            let q = get_double_quoted_string(&mut it)?;
-            tokens.push(TokenType::DoubleQuotedString(q));
-        } else if c == '\'' {
-            let q = get_single_quoted_string(&mut it)?;
-            tokens.push(TokenType::SingleQuotedString(q));
-        } else if c == '&' {
-            let q = get_and(&mut it)?;
-            tokens.push(q);
-        } else if c == '|' {
-            let q = get_or_pipe(&mut it);
-            tokens.push(q);
-        } else if c == ';' {
-            tokens.push(TokenType::Semicolon);
-            it.next();
-        } else if c == '(' {
-            tokens.push(TokenType::Parenthesis(c));
-            it.next();
-        } else if c == ')' {
-            tokens.push(TokenType::Parenthesis(c));
-            it.next();
-        } else if is_word(c) {
-            let q = get_word(&mut it);
-            tokens.push(TokenType::Word(q));
-        } else if c.is_whitespace() {
-            it.next();
-        } else {
-            return Err(ParseError::InvalidCharacter);
+/// Reads an expansion into `word` after its `$` or `` ` ``: `$(…)`, `$((…))`
+/// and `${…}` up to the matching closer, `` `…` `` up to the next unescaped
+/// backquote. A `$` followed by anything else is read as is.
+fn get_expansion<I: Iterator<Item = char>>(
+    it: &mut Cursor<I>,
+    word: &mut String,
+    opening: char,
+) -> Result<(), ParseError> {
+    let mut start = it.here();
+    start.start -= 1;
+    start.column -= 1;
+
+    let closer = match opening {
+        '`' => '`',
+        _ if it.eat('(') => {
+            word.push('(');
+            ')'
+        }
+        _ if it.eat('{') => {
+            word.push('{');
+            '}'
+        }
+        _ => return Ok(()),
+    };
+
+    let mut depth = 0;
+
+    while let Some(c) = it.next() {
+        word.push(c);
+
+        match c {
+            _ if c == closer && depth == 0 => return Ok(()),
+            _ if c == closer => depth -= 1,
+            '(' if closer == ')' => depth += 1,
+            '\\' => {
+                if let Some(escaped) = it.next() {
+                    word.push(escaped);
+                }
+            }
+            '\'' | '"' if closer != '`' => get_quoted(it, word, c)?,
+            '$' | '`' if closer != '`' => get_expansion(it, word, c)?,
+            _ => {}
         }
     }
 
-    Ok(tokens)
+    Err(it.error(ParseErrorKind::UnterminatedExpansion, start))
+}
+
+pub fn parse(line: &str) -> Result<Vec<Token>, ParseError> {
+    Lexer::new(line.chars()).collect()
+}
+
+/// Splits `text` into tokens, where `text` is found at byte `offset` and line
+/// `line` of a longer source, so the spans point into that source.
+pub fn parse_at(
+    text: &str,
+    offset: usize,
+    line: usize,
+    comments: bool,
+) -> Result<Vec<Token>, ParseError> {
+    Lexer::at(text.chars(), offset, line)
+        .comments(comments)
+        .collect()
 }
 
 #[test]
@@ -248,16 +693,332 @@ fn test_parse_parenthesis() {
     );
 }
 
+#[test]
+fn test_parse_redirections() {
+    let line = String::from("cat < in > out >> log");
+
+    assert_eq!(
+        parse(&line).unwrap(),
+        vec![
+            TokenType::Word("cat".to_string()),
+            TokenType::Less,
+            TokenType::Word("in".to_string()),
+            TokenType::Great,
+            TokenType::Word("out".to_string()),
+            TokenType::DoubleGreat,
+            TokenType::Word("log".to_string())
+        ]
+    );
+}
+
+#[test]
+fn test_parse_braces_are_words() {
+    let line = String::from("{ echo {a,b}; }");
+
+    assert_eq!(
+        parse(&line).unwrap(),
+        vec![
+            TokenType::Word("{".to_string()),
+            TokenType::Word("echo".to_string()),
+            TokenType::Word("{a,b}".to_string()),
+            TokenType::Semicolon,
+            TokenType::Word("}".to_string())
+        ]
+    );
+}
+
 #[test]
 fn test_parse_unterminated_double_quote() {
     let line = String::from("echo \"hola mundo");
 
-    assert_eq!(parse(&line).unwrap_err(), ParseError::UnterminatedQuote);
+    assert_eq!(
+        parse(&line).unwrap_err().kind,
+        ParseErrorKind::UnterminatedQuote
+    );
 }
 
 #[test]
 fn test_parse_unterminated_single_quote() {
     let line = String::from("echo \'hola mundo");
 
-    assert_eq!(parse(&line).unwrap_err(), ParseError::UnterminatedQuote);
+    assert_eq!(
+        parse(&line).unwrap_err().kind,
+        ParseErrorKind::UnterminatedQuote
+    );
+}
+
+#[test]
+fn test_parse_spans() {
+    let tokens = parse("ñu >>\n  'a b'").unwrap();
+
+    assert_eq!(
+        tokens.iter().map(|token| token.span).collect::<Vec<_>>(),
+        vec![
+            Span {
+                start: 0,
+                end: 3,
+                line: 1,
+                column: 1
+            },
+            Span {
+                start: 4,
+                end: 6,
+                line: 1,
+                column: 4
+            },
+            Span {
+                start: 6,
+                end: 7,
+                line: 1,
+                column: 6
+            },
+            Span {
+                start: 9,
+                end: 14,
+                line: 2,
+                column: 3
+            },
+        ]
+    );
+    assert_eq!(
+        tokens[0].end(),
+        Span {
+            start: 3,
+            end: 3,
+            line: 1,
+            column: 3
+        }
+    );
+    assert_eq!(parse_at("ls", 10, 4, true).unwrap()[0].span.line, 4);
+
+    // the span covers the joined lines, the word's text does not
+    let tokens = parse("echo ab\\\ncd").unwrap();
+
+    assert_eq!(tokens[1].kind, TokenType::Word("abcd".to_string()));
+    assert_eq!(
+        tokens[1].span,
+        Span {
+            start: 5,
+            end: 11,
+            line: 1,
+            column: 6
+        }
+    );
+    assert_eq!(
+        tokens[1].end(),
+        Span {
+            start: 11,
+            end: 11,
+            line: 2,
+            column: 3
+        }
+    );
+}
+
+#[test]
+fn test_parse_error_spans() {
+    assert_eq!(
+        parse("echo 'hola mundo").unwrap_err().span,
+        Span {
+            start: 5,
+            end: 16,
+            line: 1,
+            column: 6
+        }
+    );
+    assert_eq!(
+        parse("echo $(date").unwrap_err(),
+        ParseError {
+            kind: ParseErrorKind::UnterminatedExpansion,
+            span: Span {
+                start: 5,
+                end: 11,
+                line: 1,
+                column: 6
+            }
+        }
+    );
+    assert_eq!(parse("ls a\0b").unwrap_err().span.column, 5);
+}
+
+#[cfg(test)]
+fn kinds(line: &str) -> Vec<TokenType> {
+    parse(line)
+        .unwrap()
+        .into_iter()
+        .map(|token| token.kind)
+        .collect()
+}
+
+#[cfg(test)]
+fn word(s: &str) -> TokenType {
+    TokenType::Word(s.to_string())
+}
+
+#[test]
+fn test_parse_posix_words() {
+    assert_eq!(
+        kinds("cat /tmp/file.txt ../a_b @x x#y %z:1"),
+        vec![
+            word("cat"),
+            word("/tmp/file.txt"),
+            word("../a_b"),
+            word("@x"),
+            word("x#y"),
+            word("%z:1")
+        ]
+    );
+    assert_eq!(
+        kinds("echo a\"b c\"d 'e'\\ f \\; $(ls -l | wc) ${x:-a b} `pwd` \"$(a \")\")\""),
+        vec![
+            word("echo"),
+            word("a\"b c\"d"),
+            word("'e'\\ f"),
+            word("\\;"),
+            word("$(ls -l | wc)"),
+            word("${x:-a b}"),
+            word("`pwd`"),
+            TokenType::DoubleQuotedString("\"$(a \")\")\"".to_string())
+        ]
+    );
+    assert_eq!(kinds("echo a\\\nb"), vec![word("echo"), word("ab")]);
+    assert_eq!(
+        kinds("echo a \\\n  \\\n  && b"),
+        vec![word("echo"), word("a"), TokenType::And, word("b")]
+    );
+}
+
+#[test]
+fn test_parse_operators() {
+    assert_eq!(
+        kinds("a&b&&c|d||e;f;;g\n<<-<<<&<>< >>>&>|>"),
+        vec![
+            word("a"),
+            TokenType::Ampersand,
+            word("b"),
+            TokenType::And,
+            word("c"),
+            TokenType::Pipe,
+            word("d"),
+            TokenType::Or,
+            word("e"),
+            TokenType::Semicolon,
+            word("f"),
+            TokenType::DoubleSemicolon,
+            word("g"),
+            TokenType::Newline,
+            TokenType::DoubleLessDash,
+            TokenType::DoubleLess,
+            TokenType::LessAnd,
+            TokenType::LessGreat,
+            TokenType::Less,
+            TokenType::DoubleGreat,
+            TokenType::GreatAnd,
+            TokenType::Clobber,
+            TokenType::Great,
+        ]
+    );
+}
+
+#[test]
+fn test_parse_io_numbers() {
+    assert_eq!(
+        kinds("cmd 2>&1 10<in 2 >out a2>b"),
+        vec![
+            word("cmd"),
+            TokenType::IoNumber("2".to_string()),
+            TokenType::GreatAnd,
+            word("1"),
+            TokenType::IoNumber("10".to_string()),
+            TokenType::Less,
+            word("in"),
+            word("2"),
+            TokenType::Great,
+            word("out"),
+            word("a2"),
+            TokenType::Great,
+            word("b")
+        ]
+    );
+}
+
+#[test]
+fn test_parse_assignments() {
+    let assignment = |s: &str| TokenType::Assignment(s.to_string());
+
+    assert_eq!(
+        kinds("A=1 >out _b=2 cmd C=3; { D=4; } | ! E=5 && x=y=z"),
+        vec![
+            assignment("A=1"),
+            TokenType::Great,
+            word("out"),
+            assignment("_b=2"),
+            word("cmd"),
+            word("C=3"),
+            TokenType::Semicolon,
+            word("{"),
+            assignment("D=4"),
+            TokenType::Semicolon,
+            word("}"),
+            TokenType::Pipe,
+            word("!"),
+            assignment("E=5"),
+            TokenType::And,
+            assignment("x=y=z")
+        ]
+    );
+    assert_eq!(
+        kinds("1A=b 'A'=b =c"),
+        vec![word("1A=b"), word("'A'=b"), word("=c")]
+    );
+}
+
+#[test]
+fn test_lex_bytes() {
+    let bytes = b"echo \xc3\xb1u \xff|cat".to_vec();
+    let tokens: Vec<TokenType> = Lexer::new(Utf8Chars::new(bytes.into_iter()))
+        .map(|token| token.unwrap().kind)
+        .collect();
+
+    assert_eq!(
+        tokens,
+        vec![
+            word("echo"),
+            word("ñu"),
+            word("\u{fffd}"),
+            TokenType::Pipe,
+            word("cat")
+        ]
+    );
+}
+
+#[test]
+fn test_parse_comments() {
+    assert_eq!(
+        kinds("#!/bin/sh\necho a#b '#c' \"#d\" $# #e ) f\n# g"),
+        vec![
+            TokenType::Comment("#!/bin/sh".to_string()),
+            TokenType::Newline,
+            word("echo"),
+            word("a#b"),
+            TokenType::SingleQuotedString("'#c'".to_string()),
+            TokenType::DoubleQuotedString("\"#d\"".to_string()),
+            word("$#"),
+            TokenType::Comment("#e ) f".to_string()),
+            TokenType::Newline,
+            TokenType::Comment("# g".to_string())
+        ]
+    );
+    assert_eq!(
+        parse_at("ls #x", 0, 1, false).unwrap(),
+        vec![word("ls"), word("#x")]
+    );
+    assert_eq!(
+        parse("ls;#x").unwrap(),
+        vec![
+            word("ls"),
+            TokenType::Semicolon,
+            TokenType::Comment("#x".to_string())
+        ]
+    );
 }
@@ -0,0 +1,775 @@
+use crate::alias::quote;
+use crate::builtins::Stdio;
+use crate::shell::{Shell, Variable};
+use crate::variables::is_valid_name;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Reads up to `max` digits in `radix` and returns their value, if there was
+/// at least one.
+fn take_digits(chars: &mut Peekable<Chars>, radix: u32, max: usize) -> Option<u32> {
+    let mut value: Option<u32> = None;
+
+    for _ in 0..max {
+        match chars.peek().and_then(|c| c.to_digit(radix)) {
+            Some(digit) => {
+                value = Some(
+                    value
+                        .unwrap_or(0)
+                        .saturating_mul(radix)
+                        .saturating_add(digit),
+                );
+                chars.next();
+            }
+            None => break,
+        }
+    }
+
+    value
+}
+
+/// Decodes backslash escapes. In a `printf` format octal escapes are `\NNN`,
+/// for `echo -e` and `%b` they are `\0NNN` and `\c` stops all output, which is
+/// the flag returned along with the bytes.
+fn expand_escapes(s: &str, in_format: bool) -> (Vec<u8>, bool) {
+    let mut bytes = Vec::new();
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+
+            bytes.extend(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+
+        let escaped = match chars.peek() {
+            Some(&c) => c,
+            None => {
+                bytes.push(b'\\');
+                break;
+            }
+        };
+
+        let byte = match escaped {
+            'a' => Some(0x07),
+            'b' => Some(0x08),
+            'e' | 'E' => Some(0x1b),
+            'f' => Some(0x0c),
+            'n' => Some(b'\n'),
+            'r' => Some(b'\r'),
+            't' => Some(b'\t'),
+            'v' => Some(0x0b),
+            '\\' => Some(b'\\'),
+            'c' if !in_format => return (bytes, true),
+            _ => None,
+        };
+
+        if let Some(byte) = byte {
+            chars.next();
+            bytes.push(byte);
+            continue;
+        }
+
+        match escaped {
+            '0'..='7' if in_format => {
+                bytes.push(take_digits(&mut chars, 8, 3).unwrap_or(0) as u8);
+            }
+            '0' => {
+                chars.next();
+                bytes.push(take_digits(&mut chars, 8, 3).unwrap_or(0) as u8);
+            }
+            'x' | 'u' | 'U' => {
+                chars.next();
+
+                let max = match escaped {
+                    'x' => 2,
+                    'u' => 4,
+                    _ => 8,
+                };
+
+                match take_digits(&mut chars, 16, max) {
+                    Some(value) if escaped == 'x' => bytes.push(value as u8),
+                    Some(value) => {
+                        let c = char::from_u32(value).unwrap_or(char::REPLACEMENT_CHARACTER);
+                        let mut buf = [0; 4];
+
+                        bytes.extend(c.encode_utf8(&mut buf).as_bytes());
+                    }
+                    None => {
+                        bytes.push(b'\\');
+                        bytes.push(escaped as u8);
+                    }
+                }
+            }
+            _ => bytes.push(b'\\'),
+        }
+    }
+
+    (bytes, false)
+}
+
+/// `echo [-neE] args…` prints its arguments separated by spaces. `-n` leaves
+/// out the final newline and `-e` decodes backslash escapes, `-E` turns that
+/// off again.
+pub fn execute_echo(_shell: &mut Shell, argv: &[&str], stdio: &mut Stdio) -> i32 {
+    let mut newline = true;
+    let mut escapes = false;
+    let mut arguments = &argv[1..];
+
+    while let Some(flags) = arguments.first().and_then(|a| a.strip_prefix('-')) {
+        if flags.is_empty() || !flags.chars().all(|c| "neE".contains(c)) {
+            break;
+        }
+
+        for flag in flags.chars() {
+            match flag {
+                'n' => newline = false,
+                'e' => escapes = true,
+                _ => escapes = false,
+            }
+        }
+
+        arguments = &arguments[1..];
+    }
+
+    let mut output = Vec::new();
+
+    for (i, argument) in arguments.iter().enumerate() {
+        if i > 0 {
+            output.push(b' ');
+        }
+
+        if escapes {
+            let (bytes, stop) = expand_escapes(argument, false);
+
+            output.extend(bytes);
+
+            if stop {
+                stdio.stdout.write_all(&output).ok();
+                return 0;
+            }
+        } else {
+            output.extend(argument.as_bytes());
+        }
+    }
+
+    if newline {
+        output.push(b'\n');
+    }
+
+    stdio.stdout.write_all(&output).ok();
+
+    0
+}
+
+/// One `%` conversion of a format.
+#[derive(Debug, Default)]
+struct Spec {
+    left: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    zero: bool,
+    width: Option<usize>,
+    precision: Option<usize>,
+}
+
+impl Spec {
+    /// Pads `body`, which comes after `prefix` (a sign or `0x`), to the width.
+    fn pad(&self, prefix: &str, body: &str) -> String {
+        let len = prefix.chars().count() + body.chars().count();
+        let fill = self.width.unwrap_or(0).saturating_sub(len);
+
+        if self.left {
+            format!("{}{}{}", prefix, body, " ".repeat(fill))
+        } else if self.zero {
+            format!("{}{}{}", prefix, "0".repeat(fill), body)
+        } else {
+            format!("{}{}{}", " ".repeat(fill), prefix, body)
+        }
+    }
+
+    fn sign(&self, negative: bool) -> &'static str {
+        if negative {
+            "-"
+        } else if self.plus {
+            "+"
+        } else if self.space {
+            " "
+        } else {
+            ""
+        }
+    }
+}
+
+/// The arguments of `printf`, handed out in order. Conversions past the last
+/// one get an empty string or zero.
+struct Arguments<'a> {
+    values: &'a [&'a str],
+    next: usize,
+    status: i32,
+    warnings: Vec<String>,
+}
+
+impl<'a> Arguments<'a> {
+    fn next_str(&mut self) -> &'a str {
+        let value = self.values.get(self.next).copied().unwrap_or("");
+
+        self.next += 1;
+        value
+    }
+
+    fn warn(&mut self, value: &str, message: &str) {
+        self.warnings
+            .push(format!("printf: {}: {}", value, message));
+        self.status = 1;
+    }
+
+    /// The numeric value of a leading quote is the code of the character
+    /// after it, as in `'A`.
+    fn char_value(value: &str) -> Option<u32> {
+        value
+            .strip_prefix(['\'', '"'])
+            .map(|rest| rest.chars().next().map_or(0, |c| c as u32))
+    }
+
+    fn next_integer(&mut self) -> i128 {
+        let value = self.next_str().trim();
+
+        if value.is_empty() {
+            return 0;
+        }
+
+        if let Some(c) = Arguments::char_value(value) {
+            return c as i128;
+        }
+
+        let (negative, digits) = match value.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, value.strip_prefix('+').unwrap_or(value)),
+        };
+
+        let parsed = if let Some(hex) = digits
+            .strip_prefix("0x")
+            .or_else(|| digits.strip_prefix("0X"))
+        {
+            i128::from_str_radix(hex, 16)
+        } else if digits.len() > 1 && digits.starts_with('0') {
+            i128::from_str_radix(&digits[1..], 8)
+        } else {
+            digits.parse()
+        };
+
+        match parsed {
+            Ok(n) if negative => -n,
+            Ok(n) => n,
+            Err(_) => {
+                self.warn(value, "invalid number");
+                0
+            }
+        }
+    }
+
+    fn next_float(&mut self) -> f64 {
+        let value = self.next_str().trim();
+
+        if value.is_empty() {
+            return 0.0;
+        }
+
+        if let Some(c) = Arguments::char_value(value) {
+            return c as f64;
+        }
+
+        match value.parse() {
+            Ok(n) => n,
+            Err(_) => {
+                self.warn(value, "invalid number");
+                0.0
+            }
+        }
+    }
+}
+
+/// `%e` the way C prints it: `1.500000e+00`.
+fn format_exponent(value: f64, precision: usize) -> String {
+    let formatted = format!("{:.*e}", precision, value);
+    let (mantissa, exponent) = formatted.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+
+    format!(
+        "{}e{}{:02}",
+        mantissa,
+        if exponent < 0 { '-' } else { '+' },
+        exponent.abs()
+    )
+}
+
+/// `%g`: `%e` for very small or large numbers, `%f` otherwise, without
+/// trailing zeros unless `#` asks for them.
+fn format_general(value: f64, precision: usize, alternate: bool) -> String {
+    let precision = precision.max(1);
+
+    let exponent = if value == 0.0 {
+        0
+    } else {
+        let formatted = format!("{:.*e}", precision - 1, value);
+
+        formatted.split_once('e').unwrap().1.parse::<i32>().unwrap()
+    };
+
+    let formatted = if exponent < -4 || exponent >= precision as i32 {
+        format_exponent(value, precision - 1)
+    } else {
+        format!("{:.*}", (precision as i32 - 1 - exponent) as usize, value)
+    };
+
+    if alternate {
+        return formatted;
+    }
+
+    let (number, exponent) = match formatted.find('e') {
+        Some(i) => formatted.split_at(i),
+        None => (formatted.as_str(), ""),
+    };
+
+    let number = if number.contains('.') {
+        number.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        number
+    };
+
+    format!("{}{}", number, exponent)
+}
+
+/// Formats a floating point conversion, `%e`, `%f` or `%g` in either case.
+fn format_float(spec: &Spec, conversion: char, value: f64) -> String {
+    let precision = spec.precision.unwrap_or(6);
+    let magnitude = value.abs();
+
+    let body = if value.is_nan() {
+        "nan".to_string()
+    } else if value.is_infinite() {
+        "inf".to_string()
+    } else {
+        match conversion.to_ascii_lowercase() {
+            'e' => format_exponent(magnitude, precision),
+            'f' => format!("{:.*}", precision, magnitude),
+            _ => format_general(magnitude, precision, spec.alternate),
+        }
+    };
+
+    let body = if conversion.is_ascii_uppercase() {
+        body.to_uppercase()
+    } else {
+        body
+    };
+
+    let spec = Spec {
+        zero: spec.zero && value.is_finite(),
+        ..*spec
+    };
+
+    spec.pad(
+        spec.sign(value.is_sign_negative() && !value.is_nan()),
+        &body,
+    )
+}
+
+/// Formats an integer conversion, `%d`, `%i`, `%u`, `%x`, `%X` or `%o`.
+fn format_integer(spec: &Spec, conversion: char, value: i128) -> String {
+    let (negative, magnitude) = match conversion {
+        'd' | 'i' => (value < 0, value.unsigned_abs()),
+        // like C, the other conversions see negative numbers as unsigned
+        _ => (false, (value as i64 as u64) as u128),
+    };
+
+    let mut digits = match conversion {
+        'x' => format!("{:x}", magnitude),
+        'X' => format!("{:X}", magnitude),
+        'o' => format!("{:o}", magnitude),
+        _ => magnitude.to_string(),
+    };
+
+    if let Some(precision) = spec.precision {
+        if precision == 0 && magnitude == 0 {
+            digits.clear();
+        }
+
+        digits = format!("{:0>1$}", digits, precision);
+    }
+
+    let prefix = match conversion {
+        'x' if spec.alternate && magnitude != 0 => "0x",
+        'X' if spec.alternate && magnitude != 0 => "0X",
+        'o' if spec.alternate && !digits.starts_with('0') => "0",
+        'd' | 'i' => spec.sign(negative),
+        _ => "",
+    };
+
+    let spec = Spec {
+        zero: spec.zero && spec.precision.is_none(),
+        ..*spec
+    };
+
+    spec.pad(prefix, &digits)
+}
+
+/// `%q`: the argument quoted so the shell reads it back as one word.
+fn shell_quote(value: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_@%+=:,./-".contains(c);
+
+    if !value.is_empty() && value.chars().all(safe) {
+        value.to_string()
+    } else {
+        quote(value)
+    }
+}
+
+/// The largest width or precision accepted. Anything bigger would only make
+/// the shell run out of memory padding the output.
+const MAX_WIDTH: usize = 1 << 20;
+
+/// Reads a width or precision, which may be `*` to take it from the
+/// arguments. A value over `MAX_WIDTH` is returned as an error, as text.
+fn take_number(
+    chars: &mut Peekable<Chars>,
+    arguments: &mut Arguments,
+) -> Result<Option<usize>, String> {
+    if chars.peek() == Some(&'*') {
+        chars.next();
+
+        return match arguments.next_integer().max(0) {
+            n if n as u128 > MAX_WIDTH as u128 => Err(n.to_string()),
+            n => Ok(Some(n as usize)),
+        };
+    }
+
+    let mut digits = String::new();
+
+    while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+        digits.push(c);
+        chars.next();
+    }
+
+    if digits.is_empty() {
+        return Ok(None);
+    }
+
+    match digits.parse() {
+        Ok(n) if n <= MAX_WIDTH => Ok(Some(n)),
+        _ => Err(digits),
+    }
+}
+
+/// Runs through the format once, appending to `output`. Returns `false` if
+/// the output has to stop there, after `\c` or a bad conversion.
+fn format_once(format: &str, arguments: &mut Arguments, output: &mut Vec<u8>) -> bool {
+    let mut chars = format.chars().peekable();
+    let mut literal = String::new();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            literal.push(c);
+
+            // a lone backslash at the end stays one, so take pairs together
+            if c == '\\' {
+                literal.extend(chars.next());
+            }
+
+            continue;
+        }
+
+        output.extend(expand_escapes(&literal, true).0);
+        literal.clear();
+
+        let mut spec = Spec::default();
+
+        while let Some(&flag) = chars.peek() {
+            match flag {
+                '-' => spec.left = true,
+                '+' => spec.plus = true,
+                ' ' => spec.space = true,
+                '#' => spec.alternate = true,
+                '0' => spec.zero = true,
+                _ => break,
+            }
+
+            chars.next();
+        }
+
+        spec.width = match take_number(&mut chars, arguments) {
+            Ok(width) => width,
+            Err(value) => {
+                arguments.warn(&value, "invalid field width");
+                return false;
+            }
+        };
+
+        if chars.peek() == Some(&'.') {
+            chars.next();
+            spec.precision = match take_number(&mut chars, arguments) {
+                Ok(precision) => Some(precision.unwrap_or(0)),
+                Err(value) => {
+                    arguments.warn(&value, "invalid precision");
+                    return false;
+                }
+            };
+        }
+
+        let conversion = match chars.next() {
+            Some(conversion) => conversion,
+            None => {
+                arguments.warn("%", "missing format character");
+                return false;
+            }
+        };
+
+        let formatted = match conversion {
+            '%' => "%".to_string(),
+            's' => {
+                let value = arguments.next_str();
+                let value: String = match spec.precision {
+                    Some(precision) => value.chars().take(precision).collect(),
+                    None => value.to_string(),
+                };
+
+                spec.pad("", &value)
+            }
+            'q' => spec.pad("", &shell_quote(arguments.next_str())),
+            'c' => spec.pad(
+                "",
+                &arguments.next_str().chars().take(1).collect::<String>(),
+            ),
+            'b' => {
+                let (bytes, stop) = expand_escapes(arguments.next_str(), false);
+                let value = String::from_utf8_lossy(&bytes);
+
+                output.extend(spec.pad("", &value).as_bytes());
+
+                if stop {
+                    return false;
+                }
+
+                continue;
+            }
+            'd' | 'i' | 'u' | 'x' | 'X' | 'o' => {
+                let value = arguments.next_integer();
+
+                format_integer(&spec, conversion, value)
+            }
+            'e' | 'E' | 'f' | 'F' | 'g' | 'G' => {
+                let value = arguments.next_float();
+
+                format_float(&spec, conversion, value)
+            }
+            _ => {
+                arguments.warn(&format!("%{}", conversion), "invalid format character");
+                return false;
+            }
+        };
+
+        output.extend(formatted.as_bytes());
+    }
+
+    output.extend(expand_escapes(&literal, true).0);
+
+    true
+}
+
+/// `printf [-v var] format [args…]` prints the arguments as the format says,
+/// going through the format again while arguments remain. With `-v` the
+/// result goes to a variable instead.
+pub fn execute_printf(shell: &mut Shell, argv: &[&str], stdio: &mut Stdio) -> i32 {
+    let (variable, rest) = match argv.get(1) {
+        Some(&"-v") => match argv.get(2) {
+            Some(name) if is_valid_name(name) => (Some(*name), &argv[3..]),
+            Some(name) => {
+                writeln!(stdio.stderr, "printf: `{}': not a valid identifier", name).ok();
+                return 2;
+            }
+            None => {
+                writeln!(stdio.stderr, "printf: -v: option requires an argument").ok();
+                return 2;
+            }
+        },
+        Some(&"--") => (None, &argv[2..]),
+        _ => (None, &argv[1..]),
+    };
+
+    let (format, values) = match rest.split_first() {
+        Some(split) => split,
+        None => {
+            writeln!(
+                stdio.stderr,
+                "printf: usage: printf [-v var] format [arguments]"
+            )
+            .ok();
+            return 2;
+        }
+    };
+
+    let mut arguments = Arguments {
+        values,
+        next: 0,
+        status: 0,
+        warnings: Vec::new(),
+    };
+    let mut output = Vec::new();
+
+    loop {
+        let start = arguments.next;
+
+        if !format_once(format, &mut arguments, &mut output) {
+            break;
+        }
+
+        // again only while the format takes arguments and some are left
+        if arguments.next == start || arguments.next >= values.len() {
+            break;
+        }
+    }
+
+    for warning in &arguments.warnings {
+        writeln!(stdio.stderr, "{}", warning).ok();
+    }
+
+    match variable {
+        Some(name) => {
+            let value = String::from_utf8_lossy(&output).into_owned();
+
+            if let Err(e) = shell.assign(name, Variable::Scalar(value)) {
+                writeln!(stdio.stderr, "printf: {}", e).ok();
+                return 1;
+            }
+        }
+        None => {
+            stdio.stdout.write_all(&output).ok();
+        }
+    }
+
+    arguments.status
+}
+
+#[cfg(test)]
+fn printf(argv: &[&str]) -> (i32, String, String) {
+    let mut argv = argv.to_vec();
+
+    argv.insert(0, "printf");
+
+    crate::builtins::capture(&mut Shell::default(), &argv)
+}
+
+#[test]
+fn test_echo() {
+    use crate::builtins::capture;
+
+    let mut shell = Shell::default();
+
+    assert_eq!(capture(&mut shell, &["echo", "a", "b"]).1, "a b\n");
+    assert_eq!(capture(&mut shell, &["echo", "-n", "a"]).1, "a");
+    assert_eq!(capture(&mut shell, &["echo", "a\\tb"]).1, "a\\tb\n");
+    assert_eq!(
+        capture(&mut shell, &["echo", "-e", "a\\tb\\x41\\0101"]).1,
+        "a\tbAA\n"
+    );
+    assert_eq!(capture(&mut shell, &["echo", "-eE", "a\\n"]).1, "a\\n\n");
+    assert_eq!(capture(&mut shell, &["echo", "-e", "a\\cb", "c"]).1, "a");
+    assert_eq!(capture(&mut shell, &["echo", "-x", "--"]).1, "-x --\n");
+}
+
+#[test]
+fn test_printf_strings() {
+    assert_eq!(
+        printf(&["%s|%5s|%-5s|%.2s\\n", "a", "b", "c", "def"]).1,
+        "a|    b|c    |de\n"
+    );
+    assert_eq!(printf(&["%s %s\\n", "a", "b", "c"]).1, "a b\nc \n");
+    assert_eq!(printf(&["no args\\n", "x"]).1, "no args\n");
+    assert_eq!(printf(&["%c%c", "abc", "d"]).1, "ad");
+    assert_eq!(printf(&["%b|", "a\\tb", "c\\cd", "e"]).1, "a\tb|c");
+    assert_eq!(
+        printf(&["%q %q %q", "plain", "it's", ""]).1,
+        "plain 'it'\\''s' ''"
+    );
+    assert_eq!(printf(&["100%% \\101\\x42"]).1, "100% AB");
+}
+
+#[test]
+fn test_printf_integers() {
+    assert_eq!(
+        printf(&["%d %i %5d %-4d| %05d %+d", "1", "-2", "3", "4", "-5", "6"]).1,
+        "1 -2     3 4   | -0005 +6"
+    );
+    assert_eq!(
+        printf(&["%x %X %#x %o %#o", "255", "255", "255", "8", "8"]).1,
+        "ff FF 0xff 10 010"
+    );
+    assert_eq!(printf(&["%u", "-1"]).1, "18446744073709551615");
+    assert_eq!(printf(&["%.3d %d %d", "7", "0x10", "010"]).1, "007 16 8");
+    assert_eq!(printf(&["%d", "'A"]).1, "65");
+    assert_eq!(printf(&["%*d|%-*d|", "4", "1", "3", "2"]).1, "   1|2  |");
+}
+
+#[test]
+fn test_printf_floats() {
+    assert_eq!(
+        printf(&["%f %.2f %8.3f", "1.5", "2.005", "-3.14159"]).1,
+        "1.500000 2.00   -3.142"
+    );
+    assert_eq!(
+        printf(&["%e %E", "1234.5", "0.00012"]).1,
+        "1.234500e+03 1.200000E-04"
+    );
+    assert_eq!(
+        printf(&["%g %g %g %g", "100000", "1000000", "0.0001", "0.00001"]).1,
+        "100000 1e+06 0.0001 1e-05"
+    );
+    assert_eq!(
+        printf(&["%g %.3g %#g", "1.5", "3.14159", "2"]).1,
+        "1.5 3.14 2.00000"
+    );
+    assert_eq!(printf(&["%f %F", "inf", "-inf"]).1, "inf -INF");
+}
+
+#[test]
+fn test_printf_warnings_and_variable() {
+    assert_eq!(
+        printf(&["%d-%f\\n", "abc", "x"]),
+        (
+            1,
+            "0-0.000000\n".to_string(),
+            "printf: abc: invalid number\nprintf: x: invalid number\n".to_string()
+        )
+    );
+    assert_eq!(
+        printf(&["%z", "1"]).2,
+        "printf: %z: invalid format character\n"
+    );
+    assert_eq!(printf(&["%d %s"]).1, "0 ");
+    assert_eq!(
+        printf(&["a%99999999999d", "1"]),
+        (
+            1,
+            "a".to_string(),
+            "printf: 99999999999: invalid field width\n".to_string()
+        )
+    );
+    assert_eq!(
+        printf(&["%.99999999999999s", "ab"]).2,
+        "printf: 99999999999999: invalid precision\n"
+    );
+    assert_eq!(
+        printf(&["%*d", "99999999999999", "1"]).2,
+        "printf: 99999999999999: invalid field width\n"
+    );
+
+    let mut shell = Shell::default();
+
+    assert_eq!(
+        crate::builtins::capture(&mut shell, &["printf", "-v", "out", "%03d", "7"]),
+        (0, String::new(), String::new())
+    );
+    assert_eq!(shell.var("out"), Some("007".to_string()));
+}
@@ -1,71 +1,2194 @@
+use crate::builtins::{Builtin, FdReader, Stdio};
+use crate::condition;
+use crate::error::ShellError;
+use crate::lookup;
+use crate::parser::TokenType;
+use crate::shell::{is_current_dir, Shell, Variable};
+use crate::source;
+use crate::trap;
+use crate::tree::{
+    AndOrExpr, AndOrExprOptions, CallExpr, CallExprOptions, Expr, PipeExpr, Redirection,
+    SemicolonExpr, SemicolonExprOptions,
+};
 use std::env;
-use std::path::Path;
-use std::process::{Command, Stdio};
-use dirs;
+use std::ffi::{CStr, CString};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, Read, Write};
+use std::iter;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Component, Path, PathBuf};
+use std::process::{self, ExitStatus};
+use std::ptr;
 
 #[derive(Debug)]
 pub struct Process<'a> {
-    command: &'a String,
+    /// Where the program is, which is what `execve` runs.
+    command: PathBuf,
+    /// Everything the program gets as `argv`, starting with its name as it
+    /// was typed.
     arguments: Vec<&'a str>,
+    environment: Vec<(String, String)>,
+    ignored_signals: Vec<libc::c_int>,
 }
 
-pub fn receive_command(vector: &mut Vec<&str>) -> Result<(), ()> {
+/// Runs a builtin or a program. `assignments` are the `NAME=value` words that
+/// came before the command, they only go to the program's environment.
+pub fn receive_command(
+    shell: &mut Shell,
+    vector: &mut Vec<&str>,
+    assignments: &[(String, String)],
+) -> Result<i32, ()> {
+    let command = match vector.first() {
+        Some(v) => v.to_string(),
+        None => {
+            return Err(());
+        }
+    };
+
+    if let Some(builtin) = shell.builtins.get(&command) {
+        let status = execute_builtin(shell, &*builtin, vector);
+
+        if shell.exiting {
+            shell.exiting = false;
+            return Err(());
+        }
+
+        return Ok(status);
+    }
+
+    Ok(execute_program(shell, vector, assignments))
+}
+
+/// Runs the program called `argv[0]`, found through the hash table or
+/// `PATH`, and waits for it. An executable text file without a `#!` line is
+/// run as a shell script.
+pub fn execute_program(shell: &mut Shell, argv: &[&str], assignments: &[(String, String)]) -> i32 {
+    let mut environment = shell.environment();
+
+    environment.retain(|(name, _)| assignments.iter().all(|(n, _)| n != name));
+    environment.extend(assignments.iter().cloned());
+
+    execute_program_in(shell, argv, environment)
+}
+
+/// Like `execute_program`, with `environment` as all the program gets.
+pub fn execute_program_in(
+    shell: &mut Shell,
+    argv: &[&str],
+    environment: Vec<(String, String)>,
+) -> i32 {
+    let path = match lookup::resolve(shell, argv[0]) {
+        Some(path) => path,
+        None => return ShellError::CommandNotFound(argv[0].to_string()).report(),
+    };
+
     let process = Process {
-        command: &match vector.get(0) {
-            Some(v) => v,
-            None => {
-                return Err(());
+        command: path.clone(),
+        arguments: argv.to_vec(),
+        environment: environment.clone(),
+        ignored_signals: trap::ignored_signals(shell),
+    };
+
+    match execute_process(process) {
+        Ok(status) => status,
+        Err(e) if e.raw_os_error() == Some(libc::ENOEXEC) && !is_binary(&path) => {
+            execute_script(shell, &path, argv, &environment)
+        }
+        Err(e) => spawn_error(&path, e).report(),
+    }
+}
+
+/// Tells why `path` could not be started, looking closer at the file when
+/// the error alone is misleading.
+fn spawn_error(path: &Path, source: io::Error) -> ShellError {
+    let command = path.display().to_string();
+
+    match source.raw_os_error() {
+        // the file is there, so what is missing is its interpreter
+        Some(libc::ENOENT) if path.is_file() => match interpreter(path) {
+            Some(interpreter) => ShellError::BadInterpreter {
+                command,
+                interpreter,
+                source,
             },
-        }.to_string(),
-        arguments: vector.drain(1..).collect(),
+            None => ShellError::Spawn { command, source },
+        },
+        Some(libc::EACCES) if path.is_dir() => ShellError::Spawn {
+            command,
+            source: io::Error::from_raw_os_error(libc::EISDIR),
+        },
+        _ => ShellError::Spawn { command, source },
+    }
+}
+
+/// The program named in the `#!` line of a script.
+fn interpreter(path: &Path) -> Option<String> {
+    let mut line = String::new();
+
+    io::BufReader::new(File::open(path).ok()?)
+        .read_line(&mut line)
+        .ok()?;
+
+    line.strip_prefix("#!")?
+        .split_whitespace()
+        .next()
+        .map(String::from)
+}
+
+/// Whether a file looks like a binary rather than text, which is the case
+/// when its first bytes hold a NUL.
+fn is_binary(path: &Path) -> bool {
+    let mut head = [0; 80];
+
+    match File::open(path).and_then(|mut file| file.read(&mut head)) {
+        Ok(n) => head[..n].contains(&0),
+        Err(_) => true,
+    }
+}
+
+/// Runs a script the way POSIX asks for files the system cannot execute: in
+/// a new copy of the shell whose only variables are the exported ones of
+/// `environment`, with the arguments as positional parameters.
+fn execute_script(
+    shell: &mut Shell,
+    path: &Path,
+    argv: &[&str],
+    environment: &[(String, String)],
+) -> i32 {
+    let command = path.display().to_string();
+
+    let script = match fs::read_to_string(path) {
+        Ok(script) => script,
+        Err(source) => return ShellError::Spawn { command, source }.report(),
+    };
+
+    io::stdout().flush().ok();
+
+    match unsafe { libc::fork() } {
+        -1 => ShellError::Io {
+            context: "fork",
+            source: io::Error::last_os_error(),
+        }
+        .report(),
+        0 => {
+            trap::reset_for_subshell(shell);
+
+            shell.vars.clear();
+            shell.exported.clear();
+            shell.readonly.clear();
+            shell.aliases.clear();
+
+            for (name, value) in environment {
+                shell.set_var(name, Variable::Scalar(value.clone()));
+                shell.exported.insert(name.clone());
+            }
+
+            shell.positional = argv[1..].iter().map(|arg| arg.to_string()).collect();
+            shell.status = 0;
+
+            let result = source::read_script(&script).and_then(|commands| {
+                for command in &commands {
+                    match execute(shell, &command.expand(shell)?) {
+                        Ok(status) => shell.status = status,
+                        Err(()) => break,
+                    }
+                }
+
+                Ok(())
+            });
+
+            if let Err(e) = result {
+                eprint!("{}", e.render(&command, &script));
+                shell.status = ShellError::from(e).status();
+            }
+
+            trap::run_exit(shell);
+
+            io::stdout().flush().ok();
+            process::exit(shell.status);
+        }
+        pid => wait_for(pid),
+    }
+}
+
+/// Runs a builtin attached to the shell's own standard file descriptors.
+fn execute_builtin(shell: &mut Shell, builtin: &dyn Builtin, argv: &[&str]) -> i32 {
+    let mut stdout = io::stdout();
+    let mut stderr = io::stderr();
+
+    let status = builtin.run(
+        shell,
+        argv,
+        &mut Stdio {
+            stdin: &mut FdReader(0),
+            stdout: &mut stdout,
+            stderr: &mut stderr,
+        },
+    );
+
+    if let Err(e) = stdout.flush() {
+        return ShellError::Builtin {
+            name: argv[0].to_string(),
+            message: format!("write error: {}", os_error_message(&e)),
+            status: 1,
+        }
+        .report();
+    }
+
+    status
+}
+
+/// Spawns the program and waits for it, giving back its exit status or why
+/// it could not be started. The program is run with `execve` itself, since
+/// `execvp` would hand files it cannot execute to `/bin/sh` on its own.
+pub fn execute_process(process: Process) -> io::Result<i32> {
+    let program = CString::new(process.command.as_os_str().as_bytes())?;
+    let arguments = process
+        .arguments
+        .iter()
+        .map(|arg| CString::new(*arg))
+        .collect::<Result<Vec<_>, _>>()?;
+    let environment = process
+        .environment
+        .iter()
+        .map(|(name, value)| CString::new(format!("{}={}", name, value)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let argv: Vec<*const libc::c_char> = arguments
+        .iter()
+        .map(|arg| arg.as_ptr())
+        .chain(iter::once(ptr::null()))
+        .collect();
+    let envp: Vec<*const libc::c_char> = environment
+        .iter()
+        .map(|var| var.as_ptr())
+        .chain(iter::once(ptr::null()))
+        .collect();
+
+    // the child writes the error of `execve` here, a successful one closes it
+    let mut fds = [0; 2];
+
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    io::stdout().flush().ok();
+
+    match unsafe { libc::fork() } {
+        -1 => {
+            let error = io::Error::last_os_error();
+
+            close_fds(&[Some(fds[0]), Some(fds[1])]);
+
+            Err(error)
+        }
+        0 => unsafe {
+            libc::close(fds[0]);
+
+            // signals ignored by the shell stay ignored in what it runs
+            libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+
+            for &signal in &process.ignored_signals {
+                libc::signal(signal, libc::SIG_IGN);
+            }
+
+            libc::execve(program.as_ptr(), argv.as_ptr(), envp.as_ptr());
+
+            let errno = io::Error::last_os_error().raw_os_error().unwrap_or(0);
+
+            libc::write(
+                fds[1],
+                &errno as *const i32 as *const libc::c_void,
+                mem::size_of::<i32>(),
+            );
+            libc::_exit(127);
+        },
+        pid => {
+            close_fds(&[Some(fds[1])]);
+
+            let mut errno = [0; mem::size_of::<i32>()];
+            let mut file = unsafe { File::from_raw_fd(fds[0]) };
+            let failed = file.read_exact(&mut errno).is_ok();
+            let status = wait_for(pid);
+
+            if failed {
+                Err(io::Error::from_raw_os_error(i32::from_ne_bytes(errno)))
+            } else {
+                Ok(status)
+            }
+        }
+    }
+}
+
+/// `cd [-L|-P] [dir]`. Without `-P` the path is followed logically, so
+/// `cd link/..` returns to where it started instead of the link's parent.
+pub fn execute_cd(shell: &mut Shell, argv: &[&str], stdio: &mut Stdio) -> i32 {
+    let mut physical = false;
+    let mut arguments = &argv[1..];
+
+    while let Some(&flag) = arguments.first() {
+        match flag {
+            "-L" => physical = false,
+            "-P" => physical = true,
+            "--" => {
+                arguments = &arguments[1..];
+                break;
+            }
+            _ => break,
+        }
+
+        arguments = &arguments[1..];
+    }
+
+    // `cd -` and directories found through CDPATH print where they went
+    let mut print = false;
+
+    let dir = match arguments {
+        [] => match shell
+            .var("HOME")
+            .or_else(|| dirs::home_dir().map(|d| d.to_string_lossy().into_owned()))
+        {
+            Some(home) => home,
+            None => {
+                writeln!(stdio.stderr, "cd: HOME not set").ok();
+                return 1;
+            }
+        },
+        ["-"] => match shell.var("OLDPWD") {
+            Some(oldpwd) => {
+                print = true;
+                oldpwd
+            }
+            None => {
+                writeln!(stdio.stderr, "cd: OLDPWD not set").ok();
+                return 1;
+            }
+        },
+        [dir] => dir.to_string(),
+        _ => {
+            writeln!(stdio.stderr, "cd: too many arguments").ok();
+            return 1;
+        }
+    };
+
+    let target = match search_cdpath(shell.var("CDPATH"), &dir) {
+        Some((found, from_cdpath)) => {
+            print = print || from_cdpath;
+            found
+        }
+        None => PathBuf::from(&dir),
+    };
+
+    match change_directory(shell, &target, physical) {
+        Ok(pwd) => {
+            if print {
+                writeln!(stdio.stdout, "{}", pwd.display()).ok();
+            }
+
+            0
+        }
+        Err(e) => {
+            writeln!(stdio.stderr, "cd: {}: {}", dir, os_error_message(&e)).ok();
+            1
+        }
+    }
+}
+
+/// Looks for `dir` in each entry of `CDPATH`. Returns the first directory
+/// found and whether it came from a non-empty entry, which `cd` announces.
+fn search_cdpath(cdpath: Option<String>, dir: &str) -> Option<(PathBuf, bool)> {
+    let relative_to_cwd = dir == "." || dir == ".." || dir.starts_with("./");
+
+    if dir.starts_with('/') || relative_to_cwd || dir.starts_with("../") {
+        return None;
+    }
+
+    for entry in cdpath?.split(':') {
+        let candidate = Path::new(if entry.is_empty() { "." } else { entry }).join(dir);
+
+        if candidate.is_dir() {
+            return Some((candidate, !entry.is_empty()));
+        }
+    }
+
+    None
+}
+
+/// Changes directory and keeps `PWD` and `OLDPWD` up to date. Returns the new
+/// `PWD`.
+fn change_directory(shell: &mut Shell, target: &Path, physical: bool) -> io::Result<PathBuf> {
+    let oldpwd = logical_pwd(shell)?;
+
+    let pwd = if physical {
+        env::set_current_dir(target)?;
+        env::current_dir()?
+    } else {
+        let logical = normalize(&oldpwd.join(target));
+
+        match env::set_current_dir(&logical) {
+            Ok(()) => logical,
+            Err(_) => {
+                // the lexical path may go nowhere, as in `cd link/..` after
+                // the link was removed, so retry without it
+                env::set_current_dir(target)?;
+                env::current_dir()?
+            }
+        }
     };
 
-    if process.command == "cd" {
-        if process.arguments.len() == 0 {
-            let current_home = dirs::home_dir();
+    for (name, value) in [("OLDPWD", &oldpwd), ("PWD", &pwd)].iter() {
+        let value = value.to_string_lossy().into_owned();
+
+        shell.set_var(name, Variable::Scalar(value));
+        shell.exported.insert(name.to_string());
+    }
+
+    Ok(pwd)
+}
+
+/// `PWD` if it still names the current directory, the physical path if not.
+fn logical_pwd(shell: &Shell) -> io::Result<PathBuf> {
+    match shell.var("PWD") {
+        Some(pwd) if is_current_dir(Path::new(&pwd)) => Ok(PathBuf::from(pwd)),
+        _ => env::current_dir(),
+    }
+}
+
+/// Removes `.` and `..` components without looking at the filesystem.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            c => normalized.push(c.as_os_str()),
+        }
+    }
+
+    normalized
+}
+
+/// The directory stack as `dirs` shows it: the current directory followed
+/// by the ones saved by `pushd`.
+fn dir_stack(shell: &Shell) -> Vec<String> {
+    let pwd = logical_pwd(shell)
+        .map(|pwd| pwd.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    iter::once(pwd)
+        .chain(shell.dir_stack.iter().cloned())
+        .collect()
+}
+
+/// Position in a stack of `len` entries named by `+n`, counting from the top,
+/// or `-n`, counting from the bottom.
+fn stack_index(argument: &str, len: usize) -> Option<usize> {
+    let n: usize = argument.get(1..)?.parse().ok()?;
+
+    match argument.chars().next()? {
+        '+' if n < len => Some(n),
+        '-' if n < len => Some(len - 1 - n),
+        _ => None,
+    }
+}
 
-            match current_home {
-                Some(d) => {
-                    let root = Path::new(&d);
-                    let _changed_dir = env::set_current_dir(&root).is_ok();
+fn is_stack_index(argument: &str) -> bool {
+    argument.len() > 1
+        && (argument.starts_with('+') || argument.starts_with('-'))
+        && argument[1..].chars().all(|c| c.is_ascii_digit())
+}
+
+/// Replaces the home directory at the start of `dir` with `~`.
+fn abbreviate_home(shell: &Shell, dir: &str) -> String {
+    match shell.var("HOME") {
+        Some(home) if !home.is_empty() && dir == home => "~".to_string(),
+        Some(home) if !home.is_empty() && dir.starts_with(&format!("{}/", home)) => {
+            format!("~{}", &dir[home.len()..])
+        }
+        _ => dir.to_string(),
+    }
+}
+
+fn print_dir_stack(shell: &Shell, stdio: &mut Stdio) {
+    let dirs: Vec<String> = dir_stack(shell)
+        .iter()
+        .map(|dir| abbreviate_home(shell, dir))
+        .collect();
+
+    writeln!(stdio.stdout, "{}", dirs.join(" ")).ok();
+}
+
+/// `pushd dir` saves the current directory and changes to `dir`, `pushd`
+/// swaps the top two entries and `pushd +n` or `pushd -n` rotates the stack
+/// until that entry is on top.
+pub fn execute_pushd(shell: &mut Shell, argv: &[&str], stdio: &mut Stdio) -> i32 {
+    let result = match &argv[1..] {
+        [] => match shell.dir_stack.first().cloned() {
+            Some(top) => change_directory(shell, Path::new(&top), false)
+                .map(|_| shell.dir_stack[0] = shell.var("OLDPWD").unwrap_or_default())
+                .map_err(|e| (top, e)),
+            None => {
+                writeln!(stdio.stderr, "pushd: no other directory").ok();
+                return 1;
+            }
+        },
+        [argument] if is_stack_index(argument) => {
+            let mut stack = dir_stack(shell);
+
+            let index = match stack_index(argument, stack.len()) {
+                Some(index) => index,
+                None => {
+                    writeln!(
+                        stdio.stderr,
+                        "pushd: {}: directory stack index out of range",
+                        argument
+                    )
+                    .ok();
+                    return 1;
                 }
+            };
+
+            stack.rotate_left(index);
+
+            change_directory(shell, Path::new(&stack[0]), false)
+                .map(|_| shell.dir_stack = stack[1..].to_vec())
+                .map_err(|e| (stack[0].clone(), e))
+        }
+        [dir] => change_directory(shell, Path::new(dir), false)
+            .map(|_| {
+                let oldpwd = shell.var("OLDPWD").unwrap_or_default();
+                shell.dir_stack.insert(0, oldpwd);
+            })
+            .map_err(|e| (dir.to_string(), e)),
+        _ => {
+            writeln!(stdio.stderr, "pushd: too many arguments").ok();
+            return 1;
+        }
+    };
+
+    match result {
+        Ok(()) => {
+            print_dir_stack(shell, stdio);
+            0
+        }
+        Err((dir, e)) => {
+            writeln!(stdio.stderr, "pushd: {}: {}", dir, os_error_message(&e)).ok();
+            1
+        }
+    }
+}
+
+/// `popd` drops the top of the stack and changes to the new top, `popd +n`
+/// or `popd -n` only drops that entry.
+pub fn execute_popd(shell: &mut Shell, argv: &[&str], stdio: &mut Stdio) -> i32 {
+    if shell.dir_stack.is_empty() {
+        writeln!(stdio.stderr, "popd: directory stack empty").ok();
+        return 1;
+    }
+
+    let index = match &argv[1..] {
+        [] => 0,
+        [argument] if is_stack_index(argument) => {
+            match stack_index(argument, shell.dir_stack.len() + 1) {
+                Some(index) => index,
                 None => {
-                    println!("No se pudo determinar la carpeta home");
+                    writeln!(
+                        stdio.stderr,
+                        "popd: {}: directory stack index out of range",
+                        argument
+                    )
+                    .ok();
+                    return 1;
                 }
             }
-        } else {
-            let cd = &process.arguments.get(0).unwrap().to_string();
-            execute_cd_process(cd.to_string());
         }
-    } else if process.command == "exit" {
-        return Err(());
+        [argument] => {
+            writeln!(stdio.stderr, "popd: {}: invalid argument", argument).ok();
+            return 2;
+        }
+        _ => {
+            writeln!(stdio.stderr, "popd: too many arguments").ok();
+            return 1;
+        }
+    };
+
+    if index == 0 {
+        let top = shell.dir_stack[0].clone();
+
+        if let Err(e) = change_directory(shell, Path::new(&top), false) {
+            writeln!(stdio.stderr, "popd: {}: {}", top, os_error_message(&e)).ok();
+            return 1;
+        }
+    }
+
+    shell.dir_stack.remove(index.max(1) - 1);
+    print_dir_stack(shell, stdio);
+
+    0
+}
+
+/// `dirs [-clpv] [+n|-n]` shows the directory stack. `-v` numbers the
+/// entries, `-p` puts one per line, `-l` does not abbreviate the home
+/// directory and `-c` empties the stack.
+pub fn execute_dirs(shell: &mut Shell, argv: &[&str], stdio: &mut Stdio) -> i32 {
+    let mut long = false;
+    let mut per_line = false;
+    let mut verbose = false;
+    let mut entry = None;
+
+    for &argument in &argv[1..] {
+        match argument {
+            "-c" => {
+                shell.dir_stack.clear();
+                return 0;
+            }
+            "-l" => long = true,
+            "-p" => per_line = true,
+            "-v" => verbose = true,
+            _ if is_stack_index(argument) => entry = Some(argument),
+            _ => {
+                writeln!(stdio.stderr, "dirs: {}: invalid option", argument).ok();
+                return 2;
+            }
+        }
+    }
+
+    let stack: Vec<String> = dir_stack(shell)
+        .iter()
+        .map(|dir| {
+            if long {
+                dir.clone()
+            } else {
+                abbreviate_home(shell, dir)
+            }
+        })
+        .collect();
+
+    if let Some(argument) = entry {
+        return match stack_index(argument, stack.len()) {
+            Some(index) => {
+                writeln!(stdio.stdout, "{}", stack[index]).ok();
+                0
+            }
+            None => {
+                writeln!(
+                    stdio.stderr,
+                    "dirs: {}: directory stack index out of range",
+                    argument
+                )
+                .ok();
+                1
+            }
+        };
+    }
+
+    if verbose {
+        for (i, dir) in stack.iter().enumerate() {
+            writeln!(stdio.stdout, "{:2}  {}", i, dir).ok();
+        }
+    } else if per_line {
+        for dir in &stack {
+            writeln!(stdio.stdout, "{}", dir).ok();
+        }
     } else {
-        execute_process(process);
+        writeln!(stdio.stdout, "{}", stack.join(" ")).ok();
     }
 
-    Ok(())
+    0
 }
 
-pub fn execute_process(process: Process) {
-    if let Err(_) = Command::new(process.command)
-        .args(process.arguments)
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .output()
-    {
-        eprintln!("Command not found");
+/// `pwd [-L|-P]` prints the logical or the physical working directory.
+pub fn execute_pwd(shell: &mut Shell, argv: &[&str], stdio: &mut Stdio) -> i32 {
+    let mut physical = false;
+
+    for &flag in &argv[1..] {
+        match flag {
+            "-L" => physical = false,
+            "-P" => physical = true,
+            _ => {
+                writeln!(stdio.stderr, "pwd: {}: invalid option", flag).ok();
+                return 2;
+            }
+        }
+    }
+
+    let pwd = if physical {
+        env::current_dir()
+    } else {
+        logical_pwd(shell)
+    };
+
+    match pwd {
+        Ok(pwd) => {
+            writeln!(stdio.stdout, "{}", pwd.display()).ok();
+            0
+        }
+        Err(e) => {
+            writeln!(stdio.stderr, "pwd: {}", os_error_message(&e)).ok();
+            1
+        }
     }
 }
 
-pub fn execute_cd_process(cd: String) {
-    let root = Path::new(&cd);
-    let _changed_dir = env::set_current_dir(&root).is_ok();
-    //println!("The current directory is {}", root.display());
+/// The bare description of an OS error, as in "No such file or directory".
+pub fn os_error_message(error: &io::Error) -> String {
+    match error.raw_os_error() {
+        Some(code) => unsafe { CStr::from_ptr(libc::strerror(code)) }
+            .to_string_lossy()
+            .into_owned(),
+        None => error.to_string(),
+    }
 }
 
-#[test]
-#[ignore]
-fn execute_a_single_command() {
-    let mut process = vec!["echo 'hello'"];
-    assert_eq!(Ok(()), receive_command(&mut process));
+/// Runs a whole expression and returns the exit status of the last command
+/// executed. `Err(())` means that `exit` was requested somewhere inside it.
+pub fn execute(shell: &mut Shell, expr: &Expr) -> Result<i32, ()> {
+    execute_semicolon(shell, &expr.value)
+}
+
+fn execute_semicolon(shell: &mut Shell, expr: &SemicolonExpr) -> Result<i32, ()> {
+    match &expr.value {
+        SemicolonExprOptions::SingleExpr(and_or_expr) => execute_and_or(shell, and_or_expr),
+        SemicolonExprOptions::Semicolon(and_or_expr, rest) => {
+            execute_and_or(shell, and_or_expr)?;
+            execute_semicolon(shell, rest)
+        }
+    }
+}
+
+fn execute_and_or(shell: &mut Shell, expr: &AndOrExpr) -> Result<i32, ()> {
+    match &expr.value {
+        AndOrExprOptions::SingleExpr(pipe_expr) => execute_pipe(shell, pipe_expr),
+        AndOrExprOptions::And(left, right) => {
+            match execute_condition(shell, |shell| execute_and_or(shell, left))? {
+                0 => execute_pipe(shell, right),
+                status => Ok(status),
+            }
+        }
+        AndOrExprOptions::Or(left, right) => {
+            match execute_condition(shell, |shell| execute_and_or(shell, left))? {
+                0 => Ok(0),
+                _ => execute_pipe(shell, right),
+            }
+        }
+    }
+}
+
+/// Runs the left-hand side of `&&` or `||`, whose failure is not an error.
+fn execute_condition<F>(shell: &mut Shell, run: F) -> Result<i32, ()>
+where
+    F: FnOnce(&mut Shell) -> Result<i32, ()>,
+{
+    shell.condition_depth += 1;
+    let result = run(shell);
+    shell.condition_depth -= 1;
+
+    result
+}
+
+/// Runs a pipeline, records the status of each of its stages in `PIPESTATUS`
+/// and returns the status of the whole, as set by `!` and `pipefail`.
+fn execute_pipe(shell: &mut Shell, expr: &PipeExpr) -> Result<i32, ()> {
+    trap::run_pending(shell)?;
+    shell.err_trapped = false;
+
+    let statuses = if expr.commands.len() == 1 {
+        // a lone command runs in the shell itself, so `cd` and friends work
+        vec![execute_call(shell, &expr.commands[0])?]
+    } else {
+        execute_pipeline_stages(shell, &expr.commands)
+    };
+
+    let mut status = *statuses.last().unwrap();
+
+    if shell.options.pipefail {
+        status = statuses
+            .iter()
+            .rev()
+            .cloned()
+            .find(|&s| s != 0)
+            .unwrap_or(0);
+    }
+
+    if expr.negated {
+        status = if status == 0 { 1 } else { 0 };
+    }
+
+    shell.set_var(
+        "PIPESTATUS",
+        Variable::Array(statuses.iter().map(|s| s.to_string()).collect()),
+    );
+    shell.status = status;
+
+    trap::run_pending(shell)?;
+
+    if status != 0 && !expr.negated && shell.condition_depth == 0 && !shell.err_trapped {
+        trap::run(shell, "ERR")?;
+        shell.err_trapped = true;
+    }
+
+    Ok(status)
+}
+
+/// Forks one child per stage with each stdout connected to the next stdin,
+/// then waits for all of them.
+fn execute_pipeline_stages(shell: &mut Shell, commands: &[CallExpr]) -> Vec<i32> {
+    let mut pids = Vec::new();
+    let mut stdin: Option<RawFd> = None;
+
+    io::stdout().flush().ok();
+
+    for (i, command) in commands.iter().enumerate() {
+        let mut stdout: Option<RawFd> = None;
+        let mut next_stdin: Option<RawFd> = None;
+
+        if i + 1 < commands.len() {
+            let mut fds = [0; 2];
+
+            if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+                ShellError::Io {
+                    context: "pipe",
+                    source: io::Error::last_os_error(),
+                }
+                .report();
+                break;
+            }
+
+            next_stdin = Some(fds[0]);
+            stdout = Some(fds[1]);
+        }
+
+        match unsafe { libc::fork() } {
+            -1 => {
+                ShellError::Io {
+                    context: "fork",
+                    source: io::Error::last_os_error(),
+                }
+                .report();
+                close_fds(&[next_stdin, stdout]);
+                break;
+            }
+            0 => unsafe {
+                if let Some(fd) = stdin {
+                    libc::dup2(fd, 0);
+                }
+
+                if let Some(fd) = stdout {
+                    libc::dup2(fd, 1);
+                }
+
+                close_fds(&[stdin, stdout, next_stdin]);
+                trap::reset_for_subshell(shell);
+
+                let status = execute_call(shell, command).unwrap_or(shell.status);
+
+                io::stdout().flush().ok();
+                process::exit(status);
+            },
+            pid => pids.push(pid),
+        }
+
+        close_fds(&[stdin, stdout]);
+        stdin = next_stdin;
+    }
+
+    close_fds(&[stdin]);
+
+    pids.into_iter().map(wait_for).collect()
+}
+
+fn close_fds(fds: &[Option<RawFd>]) {
+    for fd in fds.iter().flatten() {
+        unsafe {
+            libc::close(*fd);
+        }
+    }
+}
+
+fn execute_call(shell: &mut Shell, expr: &CallExpr) -> Result<i32, ()> {
+    let _saved_fds = match redirect(shell, &expr.redirections) {
+        Ok(saved_fds) => saved_fds,
+        Err(e) => return Ok(e.report()),
+    };
+
+    match &expr.value {
+        CallExprOptions::ProgCall(name, args) => {
+            trap::run(shell, "DEBUG")?;
+
+            let tokens: Vec<&TokenType> = iter::once(name).chain(args).map(|t| &t.kind).collect();
+            let mut assignments = Vec::new();
+
+            for token in tokens.iter().take_while(|token| is_assignment(token)) {
+                let word = expand_word(shell, token);
+                let (name, value) = word.split_at(word.find('=').unwrap());
+
+                assignments.push((name.to_string(), value[1..].to_string()));
+            }
+
+            if assignments.len() == tokens.len() {
+                for (name, value) in assignments {
+                    if let Err(e) = shell.assign(&name, Variable::Scalar(value)) {
+                        return Ok(ShellError::from(e).report());
+                    }
+                }
+
+                return Ok(0);
+            }
+
+            let words: Vec<String> = tokens[assignments.len()..]
+                .iter()
+                .map(|word| expand_word(shell, word))
+                .collect();
+
+            receive_command(
+                shell,
+                &mut words.iter().map(String::as_str).collect(),
+                &assignments,
+            )
+        }
+        CallExprOptions::Parenthesis(inner) => Ok(execute_subshell(shell, inner)),
+        CallExprOptions::Braces(inner) => execute(shell, inner),
+        CallExprOptions::Conditional(cond_expr) => {
+            Ok(condition::execute_conditional(shell, cond_expr))
+        }
+        CallExprOptions::Empty => Ok(0),
+    }
+}
+
+/// File descriptors replaced by a command's redirections, each one along with a
+/// copy of what it pointed to before, or `None` if it was closed. Dropping it
+/// puts everything back.
+struct SavedFds(Vec<(RawFd, Option<RawFd>)>);
+
+impl Drop for SavedFds {
+    fn drop(&mut self) {
+        io::stdout().flush().ok();
+
+        for &(fd, copy) in self.0.iter().rev() {
+            unsafe {
+                match copy {
+                    Some(copy) => {
+                        libc::dup2(copy, fd);
+                        libc::close(copy);
+                    }
+                    None => {
+                        libc::close(fd);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Adds a copy of `fd` to `saved_fds`, kept out of the way of the low
+/// numbers scripts use, or `None` if `fd` is closed.
+fn save_fd(saved_fds: &mut SavedFds, fd: RawFd) -> io::Result<()> {
+    let copy = match unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 10) } {
+        -1 if io::Error::last_os_error().raw_os_error() == Some(libc::EBADF) => None,
+        -1 => return Err(io::Error::last_os_error()),
+        copy => Some(copy),
+    };
+
+    saved_fds.0.push((fd, copy));
+
+    Ok(())
+}
+
+/// Opens the file a redirection points at.
+fn open_redirection(operator: &TokenType, path: &str) -> io::Result<File> {
+    match operator {
+        TokenType::Less => File::open(path),
+        TokenType::Great | TokenType::Clobber => File::create(path),
+        TokenType::DoubleGreat => OpenOptions::new().append(true).create(true).open(path),
+        TokenType::LessGreat => OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path),
+        _ => unreachable!(),
+    }
+}
+
+/// Points the shell's own file descriptors at the redirection targets, so
+/// that builtins, brace groups and spawned programs all see them alike.
+fn redirect(shell: &Shell, redirections: &[Redirection]) -> Result<SavedFds, ShellError> {
+    let mut saved_fds = SavedFds(Vec::new());
+
+    for redirection in redirections {
+        let word = expand_word(shell, &redirection.target.kind);
+        let failed = |source| ShellError::Redirection {
+            target: word.clone(),
+            source,
+        };
+
+        let fd = redirection.fd.unwrap_or(match redirection.operator {
+            TokenType::Less | TokenType::LessGreat | TokenType::LessAnd => 0,
+            _ => 1,
+        });
+
+        io::stdout().flush().ok();
+
+        // saved before the target is opened, which could otherwise take the
+        // number of a closed `fd`
+        save_fd(&mut saved_fds, fd).map_err(failed)?;
+
+        let result = match redirection.operator {
+            TokenType::LessAnd | TokenType::GreatAnd => match word.parse::<RawFd>() {
+                _ if word == "-" => unsafe { libc::close(fd) }.max(0),
+                Ok(source) if source == fd => unsafe { libc::fcntl(fd, libc::F_GETFD) },
+                Ok(source) => unsafe { libc::dup2(source, fd) },
+                Err(_) => return Err(failed(io::Error::from_raw_os_error(libc::EBADF))),
+            },
+            ref operator => {
+                let file = open_redirection(operator, &word).map_err(failed)?;
+
+                if file.as_raw_fd() == fd {
+                    // the file got the very number, it only has to outlive exec
+                    unsafe { libc::fcntl(file.into_raw_fd(), libc::F_SETFD, 0) }
+                } else {
+                    unsafe { libc::dup2(file.as_raw_fd(), fd) }
+                }
+            }
+        };
+
+        if result == -1 {
+            return Err(failed(io::Error::last_os_error()));
+        }
+    }
+
+    Ok(saved_fds)
+}
+
+/// The file descriptors a caller of `evaluate_with_io` wants the shell to use
+/// as its stdin, stdout and stderr.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Io {
+    pub stdin: RawFd,
+    pub stdout: RawFd,
+    pub stderr: RawFd,
+}
+
+impl Default for Io {
+    /// The shell's own standard streams.
+    fn default() -> Io {
+        Io {
+            stdin: 0,
+            stdout: 1,
+            stderr: 2,
+        }
+    }
+}
+
+/// Points file descriptors 0, 1 and 2 at the ones in `io`. Every source is
+/// copied before any of them is replaced, so `io` may swap them around.
+fn attach(io: Io) -> Result<SavedFds, ShellError> {
+    let failed = |source| ShellError::Io {
+        context: "could not attach standard streams",
+        source,
+    };
+    let targets = [(0, io.stdin), (1, io.stdout), (2, io.stderr)];
+    let mut saved_fds = SavedFds(Vec::new());
+    let mut copies = Vec::new();
+
+    io::stdout().flush().ok();
+
+    for &(fd, source) in targets.iter().filter(|(fd, source)| fd != source) {
+        match unsafe { libc::fcntl(source, libc::F_DUPFD_CLOEXEC, 10) } {
+            -1 => {
+                let error = io::Error::last_os_error();
+
+                for &(_, copy) in &copies {
+                    unsafe { libc::close(copy) };
+                }
+
+                return Err(failed(error));
+            }
+            copy => copies.push((fd, copy)),
+        }
+    }
+
+    let mut result = Ok(());
+
+    for &(fd, copy) in &copies {
+        if result.is_ok() {
+            result = save_fd(&mut saved_fds, fd);
+        }
+
+        if result.is_ok() && unsafe { libc::dup2(copy, fd) } == -1 {
+            result = Err(io::Error::last_os_error());
+        }
+
+        unsafe { libc::close(copy) };
+    }
+
+    result.map(|_| saved_fds).map_err(failed)
+}
+
+/// What the evaluator returns when `exit` ran: the status the shell should
+/// leave with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Exit(pub i32);
+
+/// Runs a whole expression and returns the exit status of the last command
+/// executed, or `Exit` if the shell was asked to leave.
+pub fn evaluate(shell: &mut Shell, expr: &Expr) -> Result<i32, Exit> {
+    execute(shell, expr).map_err(|()| Exit(shell.status))
+}
+
+/// Like `evaluate`, with the shell's standard streams pointed at `io` while
+/// `expr` runs. The file descriptors in `io` still belong to the caller,
+/// and the shell's own are put back afterwards.
+pub fn evaluate_with_io(shell: &mut Shell, expr: &Expr, io: Io) -> Result<i32, Exit> {
+    let _saved_fds = match attach(io) {
+        Ok(saved_fds) => saved_fds,
+        Err(e) => return Ok(e.report()),
+    };
+
+    evaluate(shell, expr)
+}
+
+/// Runs `expr` in a forked copy of the shell, so that whatever it does to the
+/// working directory or to the shell state stays in the child. Only the exit
+/// status makes it back.
+fn execute_subshell(shell: &mut Shell, expr: &Expr) -> i32 {
+    // anything still buffered would otherwise be printed by both processes
+    io::stdout().flush().ok();
+
+    match unsafe { libc::fork() } {
+        -1 => ShellError::Io {
+            context: "fork",
+            source: io::Error::last_os_error(),
+        }
+        .report(),
+        0 => {
+            trap::reset_for_subshell(shell);
+
+            shell.status = execute(shell, expr).unwrap_or(shell.status);
+            trap::run_exit(shell);
+
+            io::stdout().flush().ok();
+            process::exit(shell.status);
+        }
+        pid => wait_for(pid),
+    }
+}
+
+fn wait_for(pid: libc::pid_t) -> i32 {
+    let mut status = 0;
+
+    while unsafe { libc::waitpid(pid, &mut status, 0) } == -1 {
+        let error = io::Error::last_os_error();
+
+        if error.kind() != io::ErrorKind::Interrupted {
+            return ShellError::Io {
+                context: "wait",
+                source: error,
+            }
+            .report();
+        }
+    }
+
+    status_code(ExitStatus::from_raw(status))
+}
+
+/// Exit status as the shell reports it: the exit code, or 128 plus the number
+/// of the signal that killed the process.
+fn status_code(status: ExitStatus) -> i32 {
+    match status.code() {
+        Some(code) => code,
+        None => 128 + status.signal().unwrap_or(0),
+    }
+}
+
+/// Whether a word is a `NAME=value` assignment rather than an argument,
+/// which the lexer tells from where the word is.
+fn is_assignment(token: &TokenType) -> bool {
+    matches!(token, TokenType::Assignment(_))
+}
+
+/// The value of a word after tilde expansion: `~` is `HOME`, `~+` is `PWD`,
+/// `~-` is `OLDPWD` and `~n`, `~+n` and `~-n` are entries of the directory
+/// stack. Quoted words and unknown prefixes are left alone.
+fn expand_word(shell: &Shell, token: &TokenType) -> String {
+    let word = word_value(token);
+
+    match token {
+        TokenType::Word(raw) if raw.starts_with('~') => {
+            let prefix = raw.split('/').next().unwrap_or_default();
+
+            if prefix.contains(|c: char| "\\'\"".contains(c)) {
+                return word;
+            }
+        }
+        _ => return word,
+    }
+
+    let (prefix, rest) = word.split_at(word.find('/').unwrap_or(word.len()));
+
+    let expansion = match &prefix[1..] {
+        "" => shell.var("HOME"),
+        "+" => shell.var("PWD"),
+        "-" => shell.var("OLDPWD"),
+        index => {
+            let stack = dir_stack(shell);
+            let index = if index.starts_with(|c: char| c.is_ascii_digit()) {
+                format!("+{}", index)
+            } else {
+                index.to_string()
+            };
+
+            if is_stack_index(&index) {
+                stack_index(&index, stack.len()).map(|i| stack[i].clone())
+            } else {
+                None
+            }
+        }
+    };
+
+    match expansion {
+        Some(expansion) => expansion + rest,
+        None => word,
+    }
+}
+
+/// The value of a word once its quotes are removed.
+pub fn word_value(token: &TokenType) -> String {
+    match token {
+        TokenType::Word(s)
+        | TokenType::DoubleQuotedString(s)
+        | TokenType::SingleQuotedString(s)
+        | TokenType::Assignment(s) => remove_quotes(s),
+        _ => String::new(),
+    }
+}
+
+/// Drops the quotes and backslashes of a word as written and keeps what they
+/// protect. Inside double quotes a backslash only escapes `$`, `` ` ``, `"`,
+/// `\` and newline.
+pub fn remove_quotes(word: &str) -> String {
+    let mut result = String::new();
+    let mut chars = word.chars();
+    let mut quote = None;
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (None, '\'') | (None, '"') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            (None, '\\') => result.extend(chars.next()),
+            (Some('"'), '\\') => match chars.next() {
+                Some(escaped) if "$`\"\\".contains(escaped) => result.push(escaped),
+                Some('\n') => {}
+                Some(other) => {
+                    result.push('\\');
+                    result.push(other);
+                }
+                None => result.push('\\'),
+            },
+            _ => result.push(c),
+        }
+    }
+
+    result
+}
+
+/// Runs the test `name` again in a process of its own, where nothing else
+/// runs. Commands redirect the file descriptors of the whole process, change
+/// its directory and fork it, which would disturb the other tests and the
+/// harness itself. Returns `true` to the test that asked, which has nothing
+/// left to do, and `false` in the new process, where the test goes on.
+#[cfg(test)]
+pub(crate) fn isolated(name: &str) -> bool {
+    const TEST: &str = "RUST_SHELL_ISOLATED_TEST";
+
+    if env::var_os(TEST).is_some_and(|test| test == name) {
+        return false;
+    }
+
+    let output = process::Command::new(env::current_exe().unwrap())
+        .args([name, "--exact", "--test-threads=1"])
+        .env(TEST, name)
+        .stdin(process::Stdio::null())
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(
+        output.status.success() && stdout.contains("1 passed"),
+        "{}{}",
+        stdout,
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    true
+}
+
+#[cfg(test)]
+fn run_in(shell: &mut Shell, line: &str) -> Result<i32, ()> {
+    use crate::parser::parse;
+    use crate::tree::parse_program;
+
+    let tokens = crate::alias::expand_aliases(&shell.aliases, parse(line).unwrap()).unwrap();
+
+    execute(shell, &parse_program(&tokens).unwrap())
+}
+
+#[cfg(test)]
+fn run(line: &str) -> Result<i32, ()> {
+    run_in(&mut Shell::new(), line)
+}
+
+#[test]
+fn test_builtin_in_pipeline() {
+    if isolated("process::test_builtin_in_pipeline") {
+        return;
+    }
+
+    let path = env::temp_dir().join("rust_shell_test_builtin_pipeline");
+    let mut shell = Shell::new();
+
+    shell
+        .builtins
+        .register("greet", |_: &mut Shell, _: &[&str], stdio: &mut Stdio| {
+            writeln!(stdio.stdout, "hola").ok();
+            0
+        });
+
+    assert_eq!(
+        run_in(
+            &mut shell,
+            &format!(
+                "greet | tr a-z A-Z > \"{}\"; greet >> \"{}\"",
+                path.display(),
+                path.display()
+            )
+        ),
+        Ok(0)
+    );
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "HOLA\nhola\n");
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+#[ignore]
+fn execute_a_single_command() {
+    let mut process = vec!["echo 'hello'"];
+    assert_eq!(Ok(0), receive_command(&mut Shell::new(), &mut process, &[]));
+}
+
+#[test]
+fn test_subshell_returns_status() {
+    if isolated("process::test_subshell_returns_status") {
+        return;
+    }
+
+    assert_eq!(run("(true)"), Ok(0));
+    assert_eq!(run("(false)"), Ok(1));
+    assert_eq!(run("(true; false)"), Ok(1));
+}
+
+#[test]
+fn test_subshell_exit_does_not_exit_parent() {
+    if isolated("process::test_subshell_exit_does_not_exit_parent") {
+        return;
+    }
+
+    assert_eq!(run("(exit)"), Ok(0));
+}
+
+#[test]
+fn test_subshell_keeps_cwd() {
+    if isolated("process::test_subshell_keeps_cwd") {
+        return;
+    }
+
+    let before = env::current_dir().unwrap();
+
+    assert_eq!(run("(cd)"), Ok(0));
+    assert_eq!(env::current_dir().unwrap(), before);
+}
+
+#[test]
+fn test_subshell_followed_by_operator() {
+    if isolated("process::test_subshell_followed_by_operator") {
+        return;
+    }
+
+    assert_eq!(run("(false) || (true)"), Ok(0));
+    assert_eq!(run("(true) && (false)"), Ok(1));
+}
+
+#[test]
+fn test_braces_share_redirection() {
+    if isolated("process::test_braces_share_redirection") {
+        return;
+    }
+
+    let path = env::temp_dir().join("rust_shell_test_braces");
+
+    assert_eq!(
+        run(&format!("{{ echo a; echo b; }} > \"{}\"", path.display())),
+        Ok(0)
+    );
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "a\nb\n");
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_posix_words_and_fd_redirections() {
+    if isolated("process::test_posix_words_and_fd_redirections") {
+        return;
+    }
+
+    let path = env::temp_dir().join("rust_shell_test_fd_redirections");
+    let path = path.to_str().unwrap();
+
+    assert_eq!(
+        run(&format!("echo a\"b c\"'d' e\\ f \"\\$x\" A=1 >{}", path)),
+        Ok(0)
+    );
+    assert_eq!(fs::read_to_string(path).unwrap(), "ab cd e f $x A=1\n");
+
+    assert_eq!(run(&format!("echo hi 3>{} >&3", path)), Ok(0));
+    assert_eq!(fs::read_to_string(path).unwrap(), "hi\n");
+
+    assert_eq!(
+        run(&format!(
+            "{{ echo out; cd /rust_shell_nope; }} >{} 2>&1",
+            path
+        )),
+        Ok(1)
+    );
+    assert!(fs::read_to_string(path).unwrap().starts_with("out\ncd: "));
+
+    assert_eq!(run(&format!("echo more >>{} 1<>{}", path, path)), Ok(0));
+    assert_eq!(run("echo x >&9"), Ok(1));
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_and_or_precedence() {
+    if isolated("process::test_and_or_precedence") {
+        return;
+    }
+
+    let path = env::temp_dir().join("rust_shell_test_and_or");
+    let path = path.to_str().unwrap();
+
+    // expected outputs as printed by dash and bash
+    let cases = [
+        ("false && echo a || echo b", "b\n", 0),
+        ("true || echo a && echo b", "b\n", 0),
+        ("true && echo a || echo b", "a\n", 0),
+        ("false || echo a && echo b", "a\nb\n", 0),
+        ("false && echo a && echo b || echo c", "c\n", 0),
+        ("true || false && echo x", "x\n", 0),
+        ("false || false || echo y", "y\n", 0),
+        ("! true && echo x || echo z", "z\n", 0),
+        ("true && false || echo d && echo e", "d\ne\n", 0),
+        ("false || true && false", "", 1),
+        ("true && echo f || false && false", "f\n", 1),
+    ];
+
+    for (line, output, status) in cases.iter() {
+        assert_eq!(
+            run(&format!("{{ {}; }} >{}", line, path)),
+            Ok(*status),
+            "{}",
+            line
+        );
+        assert_eq!(&fs::read_to_string(path).unwrap(), output, "{}", line);
+    }
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_braces_return_last_status() {
+    if isolated("process::test_braces_return_last_status") {
+        return;
+    }
+
+    assert_eq!(run("{ true; false; }"), Ok(1));
+    assert_eq!(run("{ false; } || { true; }"), Ok(0));
+}
+
+#[test]
+fn test_redirection_to_missing_directory() {
+    if isolated("process::test_redirection_to_missing_directory") {
+        return;
+    }
+
+    assert_eq!(run("true > \"/nonexistent/file\""), Ok(1));
+}
+
+#[test]
+fn test_pipeline_connects_stages() {
+    if isolated("process::test_pipeline_connects_stages") {
+        return;
+    }
+
+    let path = env::temp_dir().join("rust_shell_test_pipeline");
+
+    assert_eq!(
+        run(&format!(
+            "echo hola | tr a-z A-Z | (cat; echo mundo) > \"{}\"",
+            path.display()
+        )),
+        Ok(0)
+    );
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "HOLA\nmundo\n");
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_pipestatus() {
+    if isolated("process::test_pipestatus") {
+        return;
+    }
+
+    let mut shell = Shell::new();
+
+    assert_eq!(run_in(&mut shell, "true | false | true"), Ok(0));
+    assert_eq!(
+        shell.vars.get("PIPESTATUS"),
+        Some(&Variable::Array(vec![
+            "0".to_string(),
+            "1".to_string(),
+            "0".to_string()
+        ]))
+    );
+
+    assert_eq!(run_in(&mut shell, "false"), Ok(1));
+    assert_eq!(
+        shell.vars.get("PIPESTATUS"),
+        Some(&Variable::Array(vec!["1".to_string()]))
+    );
+}
+
+#[test]
+fn test_pipeline_negation() {
+    if isolated("process::test_pipeline_negation") {
+        return;
+    }
+
+    assert_eq!(run("! true"), Ok(1));
+    assert_eq!(run("! false | false"), Ok(0));
+    assert_eq!(run("! false && true"), Ok(0));
+}
+
+#[test]
+fn test_pipefail() {
+    if isolated("process::test_pipefail") {
+        return;
+    }
+
+    let mut shell = Shell::new();
+
+    assert_eq!(run_in(&mut shell, "false | true"), Ok(0));
+    assert_eq!(run_in(&mut shell, "set -o pipefail"), Ok(0));
+    assert_eq!(run_in(&mut shell, "false | true"), Ok(1));
+    assert_eq!(run_in(&mut shell, "true | sh -c \"exit 3\" | true"), Ok(3));
+    assert_eq!(run_in(&mut shell, "set +o pipefail"), Ok(0));
+    assert_eq!(run_in(&mut shell, "false | true"), Ok(0));
+}
+
+#[test]
+fn test_conditional_expression() {
+    if isolated("process::test_conditional_expression") {
+        return;
+    }
+
+    assert_eq!(run("[[ foobar == foo* ]]"), Ok(0));
+    assert_eq!(run("[[ foobar == \"foo*\" ]]"), Ok(1));
+    assert_eq!(run("[[ foo* == \"foo*\" ]]"), Ok(0));
+    assert_eq!(run("[[ a*b == a\"*\"b ]]"), Ok(0));
+    assert_eq!(run("[[ axb == a\"*\"b ]]"), Ok(1));
+    assert_eq!(run("[[ axb != a'*'b ]]"), Ok(0));
+    assert_eq!(run("[[ a*b == a\\*? ]]"), Ok(0));
+    assert_eq!(run("[[ 'a\\b' == a\"\\\\\"? ]]"), Ok(0));
+    assert_eq!(run("[[ -d src && ! -f src ]]"), Ok(0));
+    assert_eq!(run("[[ ( -z x || -n x ) && a < b ]]"), Ok(0));
+    assert_eq!(run("[[ 2 -gt 10 ]] || [[ 2 > 10 ]]"), Ok(0));
+    assert_eq!(run("[[ a -eq 1 ]]"), Ok(2));
+}
+
+#[test]
+fn test_conditional_regex() {
+    if isolated("process::test_conditional_regex") {
+        return;
+    }
+
+    let mut shell = Shell::new();
+
+    assert_eq!(run_in(&mut shell, "[[ foobar =~ ^f(o+)(x|b)a.$ ]]"), Ok(0));
+    assert_eq!(
+        shell.vars.get("BASH_REMATCH"),
+        Some(&Variable::Array(vec![
+            "foobar".to_string(),
+            "oo".to_string(),
+            "b".to_string()
+        ]))
+    );
+
+    assert_eq!(run_in(&mut shell, "[[ foobar =~ \"o.b\" ]]"), Ok(1));
+    assert_eq!(
+        shell.vars.get("BASH_REMATCH"),
+        Some(&Variable::Array(vec![]))
+    );
+    assert_eq!(run_in(&mut shell, "[[ a.b =~ a\".\"b ]]"), Ok(0));
+
+    assert_eq!(run_in(&mut shell, "[[ ñb =~ ^(.)(.) ]]"), Ok(0));
+    assert_eq!(
+        shell.vars.get("BASH_REMATCH"),
+        Some(&Variable::Array(vec![
+            "ñ".to_string(),
+            "\u{fffd}".to_string(),
+            "\u{fffd}".to_string()
+        ]))
+    );
+    assert_eq!(run_in(&mut shell, "[[ xñy =~ x(ñ)y ]]"), Ok(0));
+    assert_eq!(
+        shell.vars.get("BASH_REMATCH"),
+        Some(&Variable::Array(vec!["xñy".to_string(), "ñ".to_string()]))
+    );
+}
+
+#[test]
+fn test_test_builtin() {
+    if isolated("process::test_test_builtin") {
+        return;
+    }
+
+    assert_eq!(run("test -d src"), Ok(0));
+    assert_eq!(run("[ 1 -eq 2 ] || [ a != b ]"), Ok(0));
+    assert_eq!(run("[ a = a"), Ok(2));
+}
+
+#[test]
+fn test_alias_is_expanded_on_next_line() {
+    if isolated("process::test_alias_is_expanded_on_next_line") {
+        return;
+    }
+
+    let mut shell = Shell::new();
+
+    assert_eq!(
+        run_in(&mut shell, "alias \"no=! true\" \"yes=true \""),
+        Ok(0)
+    );
+    assert_eq!(run_in(&mut shell, "no"), Ok(1));
+    assert_eq!(run_in(&mut shell, "yes no"), Ok(0));
+    assert_eq!(run_in(&mut shell, "no || yes"), Ok(0));
+}
+
+#[test]
+fn test_normalize() {
+    assert_eq!(normalize(Path::new("/a/./b/../c/")), PathBuf::from("/a/c"));
+    assert_eq!(normalize(Path::new("/../a/..")), PathBuf::from("/"));
+}
+
+#[test]
+fn test_search_cdpath() {
+    let base = env::temp_dir().join("rust_shell_test_cdpath");
+
+    std::fs::create_dir_all(base.join("sub")).unwrap();
+
+    let cdpath = format!("/nonexistent:{}", base.display());
+
+    assert_eq!(
+        search_cdpath(Some(cdpath.clone()), "sub"),
+        Some((base.join("sub"), true))
+    );
+    assert_eq!(search_cdpath(Some(cdpath.clone()), "./sub"), None);
+    assert_eq!(search_cdpath(Some(cdpath), "nope"), None);
+    assert_eq!(
+        search_cdpath(Some(":".to_string()), "src"),
+        Some((PathBuf::from("./src"), false))
+    );
+}
+
+#[test]
+fn test_cd_reports_errors() {
+    if isolated("process::test_cd_reports_errors") {
+        return;
+    }
+
+    assert_eq!(run("(cd \"/nonexistent\")"), Ok(1));
+    assert_eq!(run("(cd \"/\" \"/\")"), Ok(1));
+}
+
+#[test]
+fn test_cd_dash_and_pwd() {
+    if isolated("process::test_cd_dash_and_pwd") {
+        return;
+    }
+
+    let path = env::temp_dir().join("rust_shell_test_cd_dash");
+
+    assert_eq!(
+        run(&format!(
+            "(cd \"/tmp\" && cd \"/\" && cd - > \"{0}\" && pwd >> \"{0}\")",
+            path.display()
+        )),
+        Ok(0)
+    );
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "/tmp\n/tmp\n");
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_cd_logical_and_physical() {
+    if isolated("process::test_cd_logical_and_physical") {
+        return;
+    }
+
+    let base = env::temp_dir().join("rust_shell_test_cd_links");
+    let link = base.join("link");
+    let output = base.join("output");
+
+    std::fs::create_dir_all(base.join("target")).unwrap();
+    std::os::unix::fs::symlink(base.join("target"), &link).ok();
+
+    assert_eq!(
+        run(&format!(
+            "(cd \"{0}\" && pwd > \"{1}\" && pwd -P >> \"{1}\" && cd .. && pwd >> \"{1}\")",
+            link.display(),
+            output.display()
+        )),
+        Ok(0)
+    );
+    assert_eq!(
+        std::fs::read_to_string(&output).unwrap(),
+        format!(
+            "{}\n{}\n{}\n",
+            link.display(),
+            base.join("target").canonicalize().unwrap().display(),
+            base.display()
+        )
+    );
+
+    assert_eq!(
+        run(&format!(
+            "(cd -P \"{0}\" && pwd > \"{1}\")",
+            link.display(),
+            output.display()
+        )),
+        Ok(0)
+    );
+    assert_eq!(
+        std::fs::read_to_string(&output).unwrap(),
+        format!(
+            "{}\n",
+            base.join("target").canonicalize().unwrap().display()
+        )
+    );
+}
+
+#[test]
+fn test_stack_index() {
+    assert_eq!(stack_index("+0", 3), Some(0));
+    assert_eq!(stack_index("+2", 3), Some(2));
+    assert_eq!(stack_index("-0", 3), Some(2));
+    assert_eq!(stack_index("-2", 3), Some(0));
+    assert_eq!(stack_index("+3", 3), None);
+    assert_eq!(stack_index("2", 3), None);
+}
+
+#[test]
+fn test_pushd_popd_and_dirs() {
+    use crate::builtins::capture;
+
+    if isolated("process::test_pushd_popd_and_dirs") {
+        return;
+    }
+
+    let base = env::temp_dir().join("rust_shell_test_dir_stack");
+
+    for dir in ["a", "b", "c"].iter() {
+        std::fs::create_dir_all(base.join(dir)).unwrap();
+    }
+
+    let a = base.join("a").display().to_string();
+    let b = base.join("b").display().to_string();
+    let c = base.join("c").display().to_string();
+
+    let mut shell = Shell::new();
+    let check = |shell: &mut Shell, argv: &[&str], expected: String| {
+        assert_eq!(
+            capture(shell, argv),
+            (0, expected, String::new()),
+            "{:?}",
+            argv
+        );
+    };
+
+    check(&mut shell, &["cd", &a], String::new());
+    check(&mut shell, &["pushd", &b], format!("{} {}\n", b, a));
+    check(&mut shell, &["pushd", &c], format!("{} {} {}\n", c, b, a));
+    check(&mut shell, &["pushd", "+2"], format!("{} {} {}\n", a, c, b));
+    check(&mut shell, &["pushd", "-0"], format!("{} {} {}\n", b, a, c));
+    check(&mut shell, &["pushd"], format!("{} {} {}\n", a, b, c));
+    check(
+        &mut shell,
+        &["dirs", "-v"],
+        format!(" 0  {}\n 1  {}\n 2  {}\n", a, b, c),
+    );
+    check(&mut shell, &["dirs", "+1"], format!("{}\n", b));
+    assert_eq!(expand_word(&shell, &TokenType::Word("~2".to_string())), c);
+    assert_eq!(expand_word(&shell, &TokenType::Word("~-0".to_string())), c);
+    assert_eq!(expand_word(&shell, &TokenType::Word("~+1".to_string())), b);
+    check(&mut shell, &["popd", "+1"], format!("{} {}\n", a, c));
+    check(&mut shell, &["popd"], format!("{}\n", c));
+    assert_eq!(env::current_dir().unwrap(), base.join("c"));
+    assert_eq!(capture(&mut shell, &["popd"]).0, 1);
+    assert_eq!(capture(&mut shell, &["pushd", "+1"]).0, 1);
+}
+
+#[test]
+fn test_tilde_expansion() {
+    let shell = Shell::new();
+    let home = shell.var("HOME").unwrap();
+
+    assert_eq!(expand_word(&shell, &TokenType::Word("~".to_string())), home);
+    assert_eq!(
+        expand_word(&shell, &TokenType::Word("~/src".to_string())),
+        format!("{}/src", home)
+    );
+    assert_eq!(
+        expand_word(&shell, &TokenType::DoubleQuotedString("\"~\"".to_string())),
+        "~"
+    );
+    assert_eq!(
+        expand_word(&shell, &TokenType::Word("~5".to_string())),
+        "~5"
+    );
+    assert_eq!(
+        expand_word(&shell, &TokenType::Word("~nobody-here".to_string())),
+        "~nobody-here"
+    );
+}
+
+#[test]
+fn test_assignments_and_child_environment() {
+    if isolated("process::test_assignments_and_child_environment") {
+        return;
+    }
+
+    let mut shell = Shell::new();
+    let path = env::temp_dir().join("rust_shell_test_environment");
+
+    assert_eq!(run_in(&mut shell, "A=1 B=2"), Ok(0));
+    assert_eq!(shell.var("B"), Some("2".to_string()));
+    assert_eq!(run_in(&mut shell, "export A"), Ok(0));
+    assert_eq!(
+        run_in(
+            &mut shell,
+            &format!("C=3 printenv A B C > \"{}\"", path.display())
+        ),
+        Ok(1)
+    );
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "1\n3\n");
+    assert_eq!(shell.var("C"), None);
+    assert_eq!(
+        run_in(
+            &mut shell,
+            &format!("env -u A B=4 printenv A B > \"{}\"", path.display())
+        ),
+        Ok(1)
+    );
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "4\n");
+    assert_eq!(
+        run_in(
+            &mut shell,
+            &format!("env -i C=5 env > \"{}\"", path.display())
+        ),
+        Ok(0)
+    );
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "C=5\n");
+    assert_eq!(shell.var("B"), Some("2".to_string()));
+    assert_eq!(run_in(&mut shell, "readonly A; A=2"), Ok(1));
+    assert_eq!(shell.var("A"), Some("1".to_string()));
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_exit_in_subshell_and_pipeline() {
+    if isolated("process::test_exit_in_subshell_and_pipeline") {
+        return;
+    }
+
+    assert_eq!(run("(exit 3)"), Ok(3));
+    assert_eq!(run("(false; exit)"), Ok(1));
+    assert_eq!(run("true | exit 4"), Ok(4));
+    assert_eq!(run("(exit 300)"), Ok(44));
+}
+
+#[test]
+fn test_exit_stops_the_shell() {
+    if isolated("process::test_exit_stops_the_shell") {
+        return;
+    }
+
+    let mut shell = Shell::new();
+
+    assert_eq!(run_in(&mut shell, "exit 5; true"), Err(()));
+    assert_eq!(shell.status, 5);
+    assert_eq!(run_in(&mut shell, "false; exit"), Err(()));
+    assert_eq!(shell.status, 1);
+    assert_eq!(run_in(&mut shell, "exit 1 2"), Ok(1));
+}
+
+#[test]
+fn test_err_debug_and_exit_traps() {
+    if isolated("process::test_err_debug_and_exit_traps") {
+        return;
+    }
+
+    let mut shell = Shell::new();
+    let path = env::temp_dir().join("rust_shell_test_traps");
+    let file = path.display();
+
+    std::fs::remove_file(&path).ok();
+
+    assert_eq!(
+        run_in(&mut shell, &format!("trap \"echo err >> '{}'\" ERR", file)),
+        Ok(0)
+    );
+    assert_eq!(
+        run_in(&mut shell, "false; false || true; ! false; true && false"),
+        Ok(1)
+    );
+    assert_eq!(
+        run_in(
+            &mut shell,
+            &format!("trap - ERR; trap \"echo debug >> '{}'\" DEBUG", file)
+        ),
+        Ok(0)
+    );
+    assert_eq!(run_in(&mut shell, "true; true"), Ok(0));
+    assert_eq!(
+        run_in(
+            &mut shell,
+            &format!(
+                "trap - DEBUG; (trap \"echo exit >> '{}'\" EXIT; exit 3)",
+                file
+            )
+        ),
+        Ok(3)
+    );
+    assert_eq!(shell.status, 3);
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        "err\nerr\ndebug\ndebug\ndebug\nexit\n"
+    );
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_err_trap_runs_once_in_nested_groups() {
+    if isolated("process::test_err_trap_runs_once_in_nested_groups") {
+        return;
+    }
+
+    let mut shell = Shell::new();
+    let path = env::temp_dir().join("rust_shell_test_err_groups");
+    let file = path.display();
+
+    std::fs::remove_file(&path).ok();
+
+    assert_eq!(
+        run_in(&mut shell, &format!("trap \"echo err >> '{}'\" ERR", file)),
+        Ok(0)
+    );
+    assert_eq!(run_in(&mut shell, "{ false; }"), Ok(1));
+    assert_eq!(run_in(&mut shell, "{ { false; }; }"), Ok(1));
+    assert_eq!(run_in(&mut shell, "{ (false); }"), Ok(1));
+    assert_eq!(run_in(&mut shell, "{ true; } < /nonexistent"), Ok(1));
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        "err\nerr\nerr\nerr\n"
+    );
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_traps_reset_in_subshells() {
+    if isolated("process::test_traps_reset_in_subshells") {
+        return;
+    }
+
+    let path = env::temp_dir().join("rust_shell_test_trap_subshell");
+
+    assert_eq!(
+        run(&format!(
+            "trap 'true' INT; trap '' USR2; (trap > \"{}\")",
+            path.display()
+        )),
+        Ok(0)
+    );
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        "trap -- '' SIGUSR2\n"
+    );
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_read_leaves_the_rest_of_the_input() {
+    if isolated("process::test_read_leaves_the_rest_of_the_input") {
+        return;
+    }
+
+    let input = env::temp_dir().join("rust_shell_test_read_input");
+    let output = env::temp_dir().join("rust_shell_test_read_output");
+    let mut shell = Shell::new();
+
+    std::fs::write(&input, "first line\nsecond\nthird\n").unwrap();
+
+    assert_eq!(
+        run_in(
+            &mut shell,
+            &format!(
+                "{{ read A; read B C; cat > \"{}\"; }} < \"{}\"",
+                output.display(),
+                input.display()
+            )
+        ),
+        Ok(0)
+    );
+    assert_eq!(shell.var("A"), Some("first line".to_string()));
+    assert_eq!(shell.var("B"), Some("second".to_string()));
+    assert_eq!(shell.var("C"), Some(String::new()));
+    assert_eq!(std::fs::read_to_string(&output).unwrap(), "third\n");
+
+    std::fs::remove_file(&input).unwrap();
+    std::fs::remove_file(&output).unwrap();
+}
+
+#[test]
+fn test_spawn_failures() {
+    use std::os::unix::fs::PermissionsExt;
+
+    if isolated("process::test_spawn_failures") {
+        return;
+    }
+
+    let dir = env::temp_dir().join("rust_shell_test_spawn");
+    let executable = |name: &str, contents: &str, mode: u32| {
+        let path = dir.join(name);
+
+        fs::write(&path, contents).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+
+        path.display().to_string()
+    };
+
+    fs::create_dir_all(&dir).unwrap();
+
+    let script = executable("script", "A=1\nexit 7\n", 0o755);
+    let plain = executable("plain", "true\n", 0o644);
+    let interpreted = executable("interpreted", "#!/nonexistent/sh\n", 0o755);
+    let binary = executable("binary", "\0\0\0\0", 0o755);
+    let mut shell = Shell::new();
+
+    assert_eq!(
+        run_in(&mut shell, &format!("command \"{}\" a b", script)),
+        Ok(7)
+    );
+    assert_eq!(shell.var("A"), None);
+    assert_eq!(
+        run_in(&mut shell, &format!("command \"{}\"", plain)),
+        Ok(126)
+    );
+    assert_eq!(
+        run_in(&mut shell, &format!("command \"{}\"", interpreted)),
+        Ok(126)
+    );
+    assert_eq!(
+        run_in(&mut shell, &format!("command \"{}\"", binary)),
+        Ok(126)
+    );
+    assert_eq!(
+        run_in(&mut shell, &format!("command \"{}\"", dir.display())),
+        Ok(126)
+    );
+    assert_eq!(run_in(&mut shell, "command \"/nonexistent/cmd\""), Ok(127));
+
+    // the program sees the name it was called by, not where it was found
+    let output = dir.join("argv0");
+
+    assert_eq!(
+        run_in(
+            &mut shell,
+            &format!("sh -c 'echo \"$0\"' > \"{}\"", output.display())
+        ),
+        Ok(0)
+    );
+    assert_eq!(fs::read_to_string(&output).unwrap(), "sh\n");
+    assert_eq!(run_in(&mut shell, "nonexistentcmd"), Ok(127));
+
+    assert_eq!(
+        spawn_error(
+            Path::new(&interpreted),
+            io::Error::from_raw_os_error(libc::ENOENT)
+        )
+        .to_string(),
+        format!(
+            "{}: /nonexistent/sh: bad interpreter: No such file or directory",
+            interpreted
+        )
+    );
+    assert_eq!(
+        spawn_error(&dir, io::Error::from_raw_os_error(libc::EACCES)).to_string(),
+        format!("{}: Is a directory", dir.display())
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_evaluate_with_io() {
+    use crate::parser::parse;
+    use crate::tree::parse_program;
+
+    if isolated("process::test_evaluate_with_io") {
+        return;
+    }
+
+    let path = env::temp_dir().join("rust_shell_test_evaluate_with_io");
+    let file = File::create(&path).unwrap();
+    let mut shell = Shell::new();
+    let io = Io {
+        stdout: 2,
+        stderr: file.as_raw_fd(),
+        ..Io::default()
+    };
+    let expr = parse_program(&parse("echo out >&2; cd /rust_shell_nope").unwrap()).unwrap();
+
+    assert_eq!(evaluate_with_io(&mut shell, &expr, io), Ok(1));
+    assert!(fs::read_to_string(&path)
+        .unwrap()
+        .starts_with("out\ncd: /rust_shell_nope: "));
+
+    let expr = parse_program(&parse("exit 4").unwrap()).unwrap();
+
+    assert_eq!(evaluate(&mut shell, &expr), Err(Exit(4)));
+
+    fs::remove_file(&path).unwrap();
 }
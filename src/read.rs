@@ -0,0 +1,561 @@
+use crate::builtins::Stdio;
+use crate::shell::{Shell, Variable};
+use crate::variables::is_valid_name;
+use std::io::{self, Read};
+use std::mem;
+use std::time::{Duration, Instant};
+
+/// Options of one `read` invocation.
+#[derive(Debug, Default, PartialEq)]
+struct ReadOptions<'a> {
+    raw: bool,
+    silent: bool,
+    prompt: Option<&'a str>,
+    array: Option<&'a str>,
+    delimiter: Option<u8>,
+    count: Option<usize>,
+    timeout: Option<f64>,
+    names: Vec<&'a str>,
+}
+
+fn parse_options<'a>(argv: &[&'a str]) -> Result<ReadOptions<'a>, String> {
+    let mut options = ReadOptions::default();
+    let mut arguments = argv[1..].iter();
+
+    while let Some(&argument) = arguments.next() {
+        if argument == "--" {
+            break;
+        }
+
+        if !argument.starts_with('-') || argument.len() < 2 {
+            options.names.push(argument);
+            break;
+        }
+
+        for (i, flag) in argument[1..].char_indices() {
+            let value = match flag {
+                'r' => {
+                    options.raw = true;
+                    continue;
+                }
+                's' => {
+                    options.silent = true;
+                    continue;
+                }
+                'p' | 'a' | 'd' | 'n' | 't' => {
+                    let rest = &argument[i + 2..];
+
+                    if rest.is_empty() {
+                        *arguments
+                            .next()
+                            .ok_or(format!("-{}: option requires an argument", flag))?
+                    } else {
+                        rest
+                    }
+                }
+                _ => return Err(format!("-{}: invalid option", flag)),
+            };
+
+            match flag {
+                'p' => options.prompt = Some(value),
+                'a' => options.array = Some(value),
+                'd' => options.delimiter = Some(value.bytes().next().unwrap_or(0)),
+                'n' => {
+                    options.count = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("{}: invalid number", value))?,
+                    )
+                }
+                _ => {
+                    options.timeout = Some(
+                        value
+                            .parse()
+                            .ok()
+                            .filter(|t: &f64| *t >= 0.0)
+                            .ok_or(format!("{}: invalid timeout specification", value))?,
+                    )
+                }
+            }
+
+            break;
+        }
+    }
+
+    options.names.extend(arguments);
+
+    Ok(options)
+}
+
+/// How a read came to an end.
+#[derive(Debug, PartialEq)]
+enum Stop {
+    Delimiter,
+    Count,
+    EndOfInput,
+    Timeout,
+}
+
+/// Waits until stdin has something to read, or fails with `TimedOut` once
+/// `deadline` has passed.
+fn wait_for_input(deadline: Instant) -> io::Result<()> {
+    loop {
+        let mut poll = libc::pollfd {
+            fd: 0,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let left = deadline.saturating_duration_since(Instant::now());
+        // rounded up, so that the last fraction of a millisecond is no busy loop
+        let millis = left
+            .as_micros()
+            .div_ceil(1000)
+            .min(libc::c_int::MAX as u128);
+
+        match unsafe { libc::poll(&mut poll, 1, millis as libc::c_int) } {
+            0 => return Err(io::ErrorKind::TimedOut.into()),
+            -1 => {
+                let error = io::Error::last_os_error();
+
+                if error.kind() != io::ErrorKind::Interrupted {
+                    return Err(error);
+                }
+            }
+            _ => return Ok(()),
+        }
+    }
+}
+
+/// Reads one byte, waiting no longer than `deadline` for it. A read
+/// interrupted by a signal is tried again.
+fn read_byte(input: &mut dyn Read, deadline: Option<Instant>) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+
+    loop {
+        if let Some(deadline) = deadline {
+            wait_for_input(deadline)?;
+        }
+
+        match input.read(&mut byte) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(byte[0])),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Reads one character, as the bytes that encode it, one byte at a time so
+/// that nothing past it is taken from the input.
+fn read_char(input: &mut dyn Read, deadline: Option<Instant>) -> io::Result<Option<Vec<u8>>> {
+    let first = match read_byte(input, deadline)? {
+        Some(byte) => byte,
+        None => return Ok(None),
+    };
+
+    let len = match first {
+        b if b >= 0xf0 => 4,
+        b if b >= 0xe0 => 3,
+        b if b >= 0xc0 => 2,
+        _ => 1,
+    };
+
+    let mut bytes = vec![first];
+
+    for _ in 1..len {
+        match read_byte(input, deadline)? {
+            Some(byte) => bytes.push(byte),
+            None => break,
+        }
+    }
+
+    Ok(Some(bytes))
+}
+
+/// Reads up to the delimiter, which is left out. Each byte comes with whether
+/// a backslash protected it, which only happens without `-r`.
+fn read_input(input: &mut dyn Read, options: &ReadOptions) -> (Vec<(u8, bool)>, Stop) {
+    let delimiter = options.delimiter.unwrap_or(b'\n');
+    let deadline = options
+        .timeout
+        .map(|seconds| Instant::now() + Duration::from_secs_f64(seconds));
+    let mut text = Vec::new();
+    let mut chars = 0;
+
+    loop {
+        if options.count.is_some_and(|count| chars >= count) {
+            return (text, Stop::Count);
+        }
+
+        let bytes = match read_char(input, deadline) {
+            Ok(Some(bytes)) => bytes,
+            Ok(None) => return (text, Stop::EndOfInput),
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => return (text, Stop::Timeout),
+            Err(_) => return (text, Stop::EndOfInput),
+        };
+
+        chars += 1;
+
+        if bytes == [delimiter] {
+            return (text, Stop::Delimiter);
+        }
+
+        if bytes == [b'\\'] && !options.raw {
+            match read_char(input, deadline) {
+                // a backslash before a newline joins the lines
+                Ok(Some(next)) if next == [b'\n'] => {}
+                Ok(Some(next)) => text.extend(next.into_iter().map(|b| (b, true))),
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => return (text, Stop::Timeout),
+                _ => return (text, Stop::EndOfInput),
+            }
+
+            continue;
+        }
+
+        text.extend(bytes.into_iter().map(|b| (b, false)));
+    }
+}
+
+/// Splits `text` into fields on the characters of `ifs`. Runs of IFS
+/// whitespace count as one separator and are trimmed at both ends. With
+/// `max`, the last field takes whatever is left of the line.
+fn split_fields(text: &[(u8, bool)], ifs: &str, max: Option<usize>) -> Vec<String> {
+    let is_separator = |&(b, escaped): &(u8, bool)| !escaped && ifs.as_bytes().contains(&b);
+    let is_blank = |c: &(u8, bool)| is_separator(c) && b" \t\n".contains(&c.0);
+    let field = |range: &[(u8, bool)]| {
+        String::from_utf8_lossy(&range.iter().map(|&(b, _)| b).collect::<Vec<u8>>()).into_owned()
+    };
+
+    let mut fields = Vec::new();
+    let mut i = 0;
+
+    while i < text.len() && is_blank(&text[i]) {
+        i += 1;
+    }
+
+    while i < text.len() {
+        if max.is_some_and(|max| fields.len() + 1 >= max) {
+            let mut end = text.len();
+
+            while end > i && is_blank(&text[end - 1]) {
+                end -= 1;
+            }
+
+            fields.push(field(&text[i..end]));
+            break;
+        }
+
+        let start = i;
+
+        while i < text.len() && !is_separator(&text[i]) {
+            i += 1;
+        }
+
+        fields.push(field(&text[start..i]));
+
+        while i < text.len() && is_blank(&text[i]) {
+            i += 1;
+        }
+
+        if i < text.len() && is_separator(&text[i]) {
+            i += 1;
+
+            while i < text.len() && is_blank(&text[i]) {
+                i += 1;
+            }
+        }
+    }
+
+    fields
+}
+
+/// Turns off the terminal echo while it lives, for `read -s`.
+struct Silence(Option<libc::termios>);
+
+impl Silence {
+    fn start() -> Silence {
+        unsafe {
+            let mut termios: libc::termios = mem::zeroed();
+
+            if libc::isatty(0) == 0 || libc::tcgetattr(0, &mut termios) != 0 {
+                return Silence(None);
+            }
+
+            let saved = termios;
+
+            termios.c_lflag &= !libc::ECHO;
+            libc::tcsetattr(0, libc::TCSANOW, &termios);
+
+            Silence(Some(saved))
+        }
+    }
+}
+
+impl Drop for Silence {
+    fn drop(&mut self) {
+        if let Some(termios) = &self.0 {
+            unsafe {
+                libc::tcsetattr(0, libc::TCSANOW, termios);
+            }
+        }
+    }
+}
+
+/// `read [-rs] [-a array] [-d delim] [-n count] [-p prompt] [-t timeout]
+/// [name…]` reads a line and splits it on `IFS` into the named variables,
+/// the last one taking the rest of the line. Without names the line goes to
+/// `REPLY`. Fails at end of input and, with a status above 128, on timeout,
+/// after assigning what was read until then.
+pub fn execute_read(shell: &mut Shell, argv: &[&str], stdio: &mut Stdio) -> i32 {
+    let options = match parse_options(argv) {
+        Ok(options) => options,
+        Err(e) => {
+            writeln!(stdio.stderr, "read: {}", e).ok();
+            return 2;
+        }
+    };
+
+    for name in options.names.iter().chain(&options.array) {
+        if !is_valid_name(name) {
+            writeln!(stdio.stderr, "read: `{}': not a valid identifier", name).ok();
+            return 1;
+        }
+    }
+
+    if options.timeout == Some(0.0) {
+        // only tells whether there is something to read
+        let mut poll = libc::pollfd {
+            fd: 0,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+
+        return if unsafe { libc::poll(&mut poll, 1, 0) } > 0 {
+            0
+        } else {
+            1
+        };
+    }
+
+    if let Some(prompt) = options.prompt {
+        if unsafe { libc::isatty(0) } == 1 {
+            write!(stdio.stderr, "{}", prompt).ok();
+            stdio.stderr.flush().ok();
+        }
+    }
+
+    let (text, stop) = {
+        let _silence = if options.silent {
+            Some(Silence::start())
+        } else {
+            None
+        };
+
+        read_input(stdio.stdin, &options)
+    };
+
+    let ifs = shell.var("IFS").unwrap_or_else(|| " \t\n".to_string());
+
+    let result = if let Some(array) = options.array {
+        let fields = split_fields(&text, &ifs, None);
+
+        shell.assign(array, Variable::Array(fields))
+    } else if options.names.is_empty() {
+        let line = split_fields(&text, "", Some(1)).pop().unwrap_or_default();
+
+        shell.assign("REPLY", Variable::Scalar(line))
+    } else {
+        let mut fields = split_fields(&text, &ifs, Some(options.names.len())).into_iter();
+
+        options.names.iter().try_for_each(|name| {
+            shell.assign(name, Variable::Scalar(fields.next().unwrap_or_default()))
+        })
+    };
+
+    if let Err(e) = result {
+        writeln!(stdio.stderr, "read: {}", e).ok();
+        return 1;
+    }
+
+    match stop {
+        Stop::EndOfInput => 1,
+        Stop::Timeout => 142,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+fn read_with_stdin(shell: &mut Shell, argv: &[&str], stdin: &mut dyn Read) -> i32 {
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+
+    execute_read(
+        shell,
+        argv,
+        &mut Stdio {
+            stdin,
+            stdout: &mut stdout,
+            stderr: &mut stderr,
+        },
+    )
+}
+
+#[cfg(test)]
+fn read_with_input(shell: &mut Shell, argv: &[&str], input: &str) -> i32 {
+    read_with_stdin(shell, argv, &mut input.as_bytes())
+}
+
+#[test]
+fn test_parse_options() {
+    assert_eq!(
+        parse_options(&["read", "-rs", "-p", "> ", "-n3", "-d,", "a", "-b"]),
+        Ok(ReadOptions {
+            raw: true,
+            silent: true,
+            prompt: Some("> "),
+            delimiter: Some(b','),
+            count: Some(3),
+            names: vec!["a", "-b"],
+            ..Default::default()
+        })
+    );
+    assert_eq!(
+        parse_options(&["read", "-t"]),
+        Err("-t: option requires an argument".to_string())
+    );
+    assert_eq!(
+        parse_options(&["read", "-x"]),
+        Err("-x: invalid option".to_string())
+    );
+}
+
+#[test]
+fn test_split_fields() {
+    let text = |s: &str| s.bytes().map(|b| (b, false)).collect::<Vec<_>>();
+
+    assert_eq!(
+        split_fields(&text("  a  b c  "), " \t\n", None),
+        vec!["a", "b", "c"]
+    );
+    assert_eq!(
+        split_fields(&text("  a  b c  "), " \t\n", Some(2)),
+        vec!["a", "b c"]
+    );
+    assert_eq!(
+        split_fields(&text("a:b::c"), ":", None),
+        vec!["a", "b", "", "c"]
+    );
+    assert_eq!(
+        split_fields(&text("a , b,c"), " ,", None),
+        vec!["a", "b", "c"]
+    );
+    assert_eq!(
+        split_fields(&[(b'a', false), (b' ', true), (b'b', false)], " ", None),
+        vec!["a b"]
+    );
+}
+
+#[test]
+fn test_read_into_variables() {
+    let mut shell = Shell::default();
+
+    assert_eq!(
+        read_with_input(&mut shell, &["read", "a", "b"], "one two  three\nnext"),
+        0
+    );
+    assert_eq!(shell.var("a"), Some("one".to_string()));
+    assert_eq!(shell.var("b"), Some("two  three".to_string()));
+
+    assert_eq!(
+        read_with_input(&mut shell, &["read"], "  as is \\\nstill\n"),
+        0
+    );
+    assert_eq!(shell.var("REPLY"), Some("  as is still".to_string()));
+
+    assert_eq!(
+        read_with_input(&mut shell, &["read", "-r", "a"], "x\\ y\n"),
+        0
+    );
+    assert_eq!(shell.var("a"), Some("x\\ y".to_string()));
+
+    assert_eq!(
+        read_with_input(&mut shell, &["read", "a", "b"], "x\\ y z"),
+        1
+    );
+    assert_eq!(shell.var("a"), Some("x y".to_string()));
+    assert_eq!(shell.var("b"), Some("z".to_string()));
+}
+
+#[test]
+fn test_read_array_delimiter_and_count() {
+    let mut shell = Shell::default();
+
+    assert_eq!(
+        read_with_input(&mut shell, &["read", "-a", "parts", "-d", ";"], "a b\nc;d"),
+        0
+    );
+    assert_eq!(
+        shell.vars.get("parts"),
+        Some(&Variable::Array(vec![
+            "a".to_string(),
+            "b".to_string(),
+            "c".to_string()
+        ]))
+    );
+
+    assert_eq!(
+        read_with_input(&mut shell, &["read", "-n", "2", "a"], "ñandú"),
+        0
+    );
+    assert_eq!(shell.var("a"), Some("ña".to_string()));
+
+    shell.set_var("IFS", Variable::Scalar(":".to_string()));
+
+    assert_eq!(
+        read_with_input(&mut shell, &["read", "a", "b"], "x y:z\n"),
+        0
+    );
+    assert_eq!(shell.var("a"), Some("x y".to_string()));
+
+    shell.readonly.insert("a".to_string());
+
+    assert_eq!(read_with_input(&mut shell, &["read", "a"], "x\n"), 1);
+}
+
+#[test]
+fn test_read_timeout_keeps_partial_input() {
+    use crate::builtins::FdReader;
+    use std::fs::File;
+    use std::io::Write;
+    use std::os::unix::io::FromRawFd;
+
+    if crate::process::isolated("read::test_read_timeout_keeps_partial_input") {
+        return;
+    }
+
+    let mut fds = [0; 2];
+
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+
+    // the timeout waits on the shell's stdin, so the pipe has to become it
+    assert_eq!(unsafe { libc::dup2(fds[0], 0) }, 0);
+
+    let mut writer = unsafe { File::from_raw_fd(fds[1]) };
+    let mut shell = Shell::default();
+
+    writer.write_all(b"par").unwrap();
+
+    assert_eq!(
+        read_with_stdin(&mut shell, &["read", "-t", "0.1", "a"], &mut FdReader(0)),
+        142
+    );
+    assert_eq!(shell.var("a"), Some("par".to_string()));
+
+    writer.write_all(b"tial\n").unwrap();
+
+    assert_eq!(
+        read_with_stdin(&mut shell, &["read", "-t", "1", "a"], &mut FdReader(0)),
+        0
+    );
+    assert_eq!(shell.var("a"), Some("tial".to_string()));
+}
@@ -0,0 +1,215 @@
+use crate::builtins::Builtins;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Variable {
+    Scalar(String),
+    Array(Vec<String>),
+}
+
+/// Options toggled with `set -o name` / `set +o name`.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct Options {
+    pub pipefail: bool,
+    /// Whether `#` starts a comment in commands typed at the prompt.
+    pub interactive_comments: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            pipefail: false,
+            interactive_comments: true,
+        }
+    }
+}
+
+impl Options {
+    /// Mutable access to an option by the name `set -o` knows it by.
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "pipefail" => Some(&mut self.pipefail),
+            "interactive_comments" => Some(&mut self.interactive_comments),
+            _ => None,
+        }
+    }
+}
+
+/// Everything the shell remembers from one command to the next.
+#[derive(Debug, Default)]
+pub struct Shell {
+    pub(crate) vars: HashMap<String, Variable>,
+    /// Names passed on to the environment of child processes.
+    pub(crate) exported: BTreeSet<String>,
+    /// Names that can no longer be assigned or unset.
+    pub(crate) readonly: BTreeSet<String>,
+    /// `$1`, `$2`… as set by `set -- args`.
+    pub(crate) positional: Vec<String>,
+    pub(crate) aliases: BTreeMap<String, String>,
+    /// Directories saved by `pushd`, most recent first. The current directory
+    /// is not part of it.
+    pub(crate) dir_stack: Vec<String>,
+    pub options: Options,
+    pub builtins: Builtins,
+    pub status: i32,
+    /// Where programs were found in `PATH`, by name.
+    pub(crate) hash: BTreeMap<String, PathBuf>,
+    /// Actions set with `trap`, by condition name. An empty action means the
+    /// signal is ignored.
+    pub(crate) traps: BTreeMap<String, String>,
+    /// Set while a trap action runs, so that it does not trigger traps itself.
+    pub(crate) in_trap: bool,
+    /// How many `&&` or `||` left-hand sides are running, where a failing
+    /// command does not trigger the `ERR` trap.
+    pub(crate) condition_depth: usize,
+    /// Set once the `ERR` trap has run for a failed command, so that the
+    /// brace groups passing its status on do not run it again.
+    pub(crate) err_trapped: bool,
+    /// Lines entered interactively, oldest first.
+    pub history: Vec<String>,
+    /// Set by `exit` so that the commands around it stop running.
+    pub(crate) exiting: bool,
+}
+
+/// An assignment to, or unset of, a `readonly` variable.
+#[derive(Debug, PartialEq)]
+pub struct ReadonlyVariable(pub String);
+
+impl fmt::Display for ReadonlyVariable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: readonly variable", self.0)
+    }
+}
+
+impl Shell {
+    /// A shell holding the environment it was started with as exported
+    /// variables.
+    pub fn new() -> Shell {
+        let mut shell = Shell::default();
+
+        for (name, value) in env::vars() {
+            shell.set_var(&name, Variable::Scalar(value));
+            shell.exported.insert(name);
+        }
+
+        if let Some(pwd) = initial_pwd() {
+            shell.set_var("PWD", Variable::Scalar(pwd));
+        }
+
+        shell
+    }
+
+    pub fn set_var(&mut self, name: &str, value: Variable) {
+        self.vars.insert(name.to_string(), value);
+    }
+
+    /// Like `set_var` but for assignments made by the user, which `readonly`
+    /// forbids.
+    pub fn assign(&mut self, name: &str, value: Variable) -> Result<(), ReadonlyVariable> {
+        if self.readonly.contains(name) {
+            return Err(ReadonlyVariable(name.to_string()));
+        }
+
+        // paths found with the old value may no longer be the right ones
+        if name == "PATH" {
+            self.hash.clear();
+        }
+
+        self.set_var(name, value);
+
+        Ok(())
+    }
+
+    /// Removes a variable along with its export attribute.
+    pub fn unset(&mut self, name: &str) -> Result<(), ReadonlyVariable> {
+        if self.readonly.contains(name) {
+            return Err(ReadonlyVariable(name.to_string()));
+        }
+
+        self.vars.remove(name);
+        self.exported.remove(name);
+
+        Ok(())
+    }
+
+    /// The value of a variable as a single string. For arrays it is their
+    /// first element.
+    pub fn var(&self, name: &str) -> Option<String> {
+        match self.vars.get(name)? {
+            Variable::Scalar(value) => Some(value.clone()),
+            Variable::Array(values) => values.first().cloned(),
+        }
+    }
+
+    /// What child processes get as their environment: every exported
+    /// variable that has a value. Arrays are not exported.
+    pub fn environment(&self) -> Vec<(String, String)> {
+        self.exported
+            .iter()
+            .filter_map(|name| match self.vars.get(name) {
+                Some(Variable::Scalar(value)) => Some((name.clone(), value.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// The inherited `PWD` if it really names the current directory, which keeps
+/// the symbolic links it went through, or else the physical path.
+fn initial_pwd() -> Option<String> {
+    if let Ok(pwd) = env::var("PWD") {
+        if is_current_dir(Path::new(&pwd)) {
+            return Some(pwd);
+        }
+    }
+
+    env::current_dir()
+        .ok()
+        .map(|dir| dir.to_string_lossy().into_owned())
+}
+
+/// Whether `path` is an absolute path to the current working directory.
+pub fn is_current_dir(path: &Path) -> bool {
+    match (fs::metadata(path), fs::metadata(".")) {
+        (Ok(a), Ok(b)) => path.is_absolute() && a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[test]
+fn test_readonly_and_environment() {
+    let mut shell = Shell::default();
+
+    shell
+        .assign("A", Variable::Scalar("1".to_string()))
+        .unwrap();
+    shell
+        .assign("B", Variable::Scalar("2".to_string()))
+        .unwrap();
+    shell.exported.insert("B".to_string());
+    shell.exported.insert("C".to_string());
+
+    assert_eq!(
+        shell.environment(),
+        vec![("B".to_string(), "2".to_string())]
+    );
+
+    shell.readonly.insert("A".to_string());
+
+    assert_eq!(
+        shell.assign("A", Variable::Scalar("3".to_string())),
+        Err(ReadonlyVariable("A".to_string()))
+    );
+    assert_eq!(shell.unset("A"), Err(ReadonlyVariable("A".to_string())));
+    assert_eq!(shell.var("A"), Some("1".to_string()));
+
+    shell.unset("B").unwrap();
+
+    assert!(shell.environment().is_empty());
+}
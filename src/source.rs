@@ -0,0 +1,453 @@
+use crate::alias;
+use crate::builtins::Stdio;
+use crate::diagnostic::Diagnostic;
+use crate::parser::{self, ParseErrorKind, Token, TokenType};
+use crate::process::{self, os_error_message};
+use crate::shell::Shell;
+use crate::trap;
+use crate::tree::{self, Expr};
+use std::collections::BTreeMap;
+use std::fs;
+use std::mem;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+/// Turns every line of a script into a tree before any of it runs, so a
+/// syntax error anywhere leaves the shell untouched. Positions in the trees
+/// and in the error point into `script` as a whole.
+pub fn parse_script(shell: &Shell, script: &str) -> Result<Vec<Expr>, Diagnostic> {
+    parse_lines(shell, script, true)
+}
+
+/// Like `parse_script`, for what was typed at the prompt, where `#` only
+/// starts a comment with the `interactive_comments` option on.
+pub fn parse_interactive(shell: &Shell, input: &str) -> Result<Vec<Expr>, Diagnostic> {
+    parse_lines(shell, input, shell.options.interactive_comments)
+}
+
+/// Whether `input`, typed at the prompt, stops in the middle of a command,
+/// the way `read_commands` goes on at the end of a line, so that the shell
+/// has to read another line before it can run it.
+pub fn needs_more_input(shell: &Shell, input: &str) -> bool {
+    if input.trim().is_empty() {
+        return false;
+    }
+
+    let tokens = match parser::parse_at(input, 0, 1, shell.options.interactive_comments) {
+        Ok(tokens) => tokens,
+        Err(e) => return e.kind != ParseErrorKind::InvalidCharacter,
+    };
+
+    // an escaped newline leaves the line without its newline token
+    if tokens.last().map(|token| &token.kind) != Some(&TokenType::Newline) {
+        return true;
+    }
+
+    let only_comments = tokens
+        .iter()
+        .all(|token| matches!(token.kind, TokenType::Newline | TokenType::Comment(_)));
+
+    match alias::expand_aliases(&shell.aliases, tokens) {
+        Ok(tokens) => !only_comments && tree::is_incomplete(&tokens),
+        Err(_) => false,
+    }
+}
+
+fn parse_lines(shell: &Shell, script: &str, comments: bool) -> Result<Vec<Expr>, Diagnostic> {
+    let commands = read_commands(script, comments, Some(&shell.aliases), false)?;
+
+    Ok(commands.into_iter().map(|command| command.expr).collect())
+}
+
+/// A complete command read from a script.
+pub(crate) struct Command {
+    pub(crate) expr: Expr,
+    /// The tokens the tree was built from.
+    tokens: Vec<Token>,
+    /// The lines it was read from, counting from 1.
+    pub(crate) lines: RangeInclusive<usize>,
+}
+
+impl Command {
+    /// Builds the tree again with the aliases the shell has now, for a
+    /// command read without them.
+    pub(crate) fn expand(&self, shell: &Shell) -> Result<Expr, Diagnostic> {
+        let tokens = alias::expand_aliases(&shell.aliases, self.tokens.clone())?;
+
+        Ok(tree::parse_program(&tokens)?)
+    }
+}
+
+/// Reads a script that is run a command at a time. The whole script is read
+/// up front, so a syntax error anywhere keeps all of it from running, but
+/// aliases are left for `Command::expand` as each command is about to run,
+/// so that the ones the script defines apply to the commands after them.
+pub(crate) fn read_script(script: &str) -> Result<Vec<Command>, Diagnostic> {
+    read_commands(script, true, None, false)
+}
+
+/// Splits `script` into complete commands and builds the tree of each. A
+/// command that is still incomplete at the end of a line, after `&&`, `||`,
+/// `|` or a backslash or inside a quote or a group, goes on in the next one.
+/// With `aliases` they are expanded in every command, and lines with nothing
+/// but comments only become commands if `comment_lines` is set.
+pub(crate) fn read_commands(
+    script: &str,
+    comments: bool,
+    aliases: Option<&BTreeMap<String, String>>,
+    comment_lines: bool,
+) -> Result<Vec<Command>, Diagnostic> {
+    let mut commands = Vec::new();
+    let mut offset = 0;
+    // where the command being read starts: its byte and its line
+    let mut pending = None;
+
+    for (i, line) in script.split_inclusive('\n').enumerate() {
+        let (start, first_line) = *pending.get_or_insert((offset, i + 1));
+
+        offset += line.len();
+
+        let text = &script[start..offset];
+        let more = offset < script.len();
+
+        if text.trim().is_empty() {
+            pending = None;
+            continue;
+        }
+
+        let tokens = match parser::parse_at(text, start, first_line, comments) {
+            Err(e) if more && e.kind != ParseErrorKind::InvalidCharacter => continue,
+            tokens => tokens?,
+        };
+
+        // an escaped newline leaves the line without its newline token
+        if more && tokens.last().map(|token| &token.kind) != Some(&TokenType::Newline) {
+            continue;
+        }
+
+        let tokens = match aliases {
+            Some(aliases) => alias::expand_aliases(aliases, tokens)?,
+            None => tokens,
+        };
+
+        if more && tree::is_incomplete(&tokens) {
+            continue;
+        }
+
+        pending = None;
+
+        let comment_only = tokens
+            .iter()
+            .all(|token| matches!(token.kind, TokenType::Newline | TokenType::Comment(_)));
+
+        if comment_only && !comment_lines {
+            continue;
+        }
+
+        commands.push(Command {
+            expr: tree::parse_program(&tokens)?,
+            tokens,
+            lines: first_line..=i + 1,
+        });
+    }
+
+    Ok(commands)
+}
+
+/// Where `source name` reads from: names without a `/` are searched for in
+/// `PATH` first and then in the current directory.
+fn find_script(shell: &Shell, name: &str) -> PathBuf {
+    if !name.contains('/') {
+        let path = shell.var("PATH").unwrap_or_default();
+
+        for dir in path.split(':').filter(|dir| !dir.is_empty()) {
+            let candidate = Path::new(dir).join(name);
+
+            if candidate.is_file() {
+                return candidate;
+            }
+        }
+    }
+
+    PathBuf::from(name)
+}
+
+/// `source file [args]` and `. file [args]` run a script in the current
+/// shell, so its variables, aliases and directory changes stay. With
+/// arguments they are the positional parameters while the script runs.
+pub fn execute_source(shell: &mut Shell, argv: &[&str], stdio: &mut Stdio) -> i32 {
+    let name = match argv.get(1) {
+        Some(name) => name,
+        None => {
+            writeln!(stdio.stderr, "{}: filename argument required", argv[0]).ok();
+            return 2;
+        }
+    };
+
+    let path = find_script(shell, name);
+
+    let script = match fs::read_to_string(&path) {
+        Ok(script) => script,
+        Err(e) => {
+            writeln!(
+                stdio.stderr,
+                "{}: {}: {}",
+                argv[0],
+                name,
+                os_error_message(&e)
+            )
+            .ok();
+            return 1;
+        }
+    };
+
+    let report = |e: Diagnostic, stdio: &mut Stdio| {
+        write!(
+            stdio.stderr,
+            "{}",
+            e.render(&path.display().to_string(), &script)
+        )
+        .ok();
+        2
+    };
+
+    let commands = match read_script(&script) {
+        Ok(commands) => commands,
+        Err(e) => return report(e, stdio),
+    };
+
+    let positional = if argv.len() > 2 {
+        let args = argv[2..].iter().map(|arg| arg.to_string()).collect();
+
+        Some(mem::replace(&mut shell.positional, args))
+    } else {
+        None
+    };
+
+    let mut status = 0;
+
+    for command in &commands {
+        let expr = match command.expand(shell) {
+            Ok(expr) => expr,
+            Err(e) => {
+                status = report(e, stdio);
+                break;
+            }
+        };
+
+        match process::execute(shell, &expr) {
+            Ok(s) => status = s,
+            Err(()) => {
+                // `exit` inside the script ends the shell that sourced it
+                shell.exiting = true;
+                status = shell.status;
+                break;
+            }
+        }
+    }
+
+    if let Some(positional) = positional {
+        shell.positional = positional;
+    }
+
+    if !shell.exiting && trap::run(shell, "RETURN").is_err() {
+        shell.exiting = true;
+    }
+
+    status
+}
+
+#[test]
+fn test_parse_script_reports_line() {
+    let shell = Shell::default();
+
+    assert_eq!(
+        parse_script(&shell, "true\n\nfalse; true\n").unwrap().len(),
+        2
+    );
+    assert_eq!(
+        parse_script(&shell, "true\n\n  { true\n").unwrap_err(),
+        Diagnostic {
+            message: "unclosed `{`".to_string(),
+            span: parser::Span {
+                start: 8,
+                end: 9,
+                line: 3,
+                column: 3,
+            },
+        }
+    );
+    assert_eq!(
+        parse_script(&shell, "true\necho \"oops\n")
+            .unwrap_err()
+            .span
+            .line,
+        2
+    );
+}
+
+#[test]
+fn test_needs_more_input() {
+    let shell = Shell::default();
+    let cases = [
+        ("echo a\n", false),
+        ("echo a &&\n", true),
+        ("echo a &&\n\n", true),
+        ("echo a &&\necho b\n", false),
+        ("ls |\n", true),
+        ("echo a \\\n", true),
+        ("echo 'a\n", true),
+        ("{ echo a\n", true),
+        ("\n", false),
+        ("# only a comment\n", false),
+        (")\n", false),
+    ];
+
+    for (input, more) in cases.iter() {
+        assert_eq!(needs_more_input(&shell, input), *more, "{:?}", input);
+    }
+}
+
+#[test]
+fn test_commands_over_several_lines() {
+    let shell = Shell::default();
+    let script = "true &&\n\n  false |\n  true\n{\n  a # x\n\n  b\n}\necho 'a\nb' \\\n  c\n";
+    let commands = read_commands(script, true, None, false).unwrap();
+
+    assert_eq!(
+        commands
+            .iter()
+            .map(|command| command.lines.clone())
+            .collect::<Vec<_>>(),
+        vec![1..=4, 5..=9, 10..=12]
+    );
+    assert_eq!(commands[1].expr.comments[0].text, "# x");
+    assert_eq!(
+        parse_script(&shell, "true |\n").unwrap_err().message,
+        "expected a command"
+    );
+    assert_eq!(
+        parse_script(&shell, "(true\nfalse\n")
+            .unwrap_err()
+            .span
+            .line,
+        1
+    );
+    assert_eq!(
+        parse_script(&shell, "true &&\n)\n").unwrap_err().span.line,
+        2
+    );
+}
+
+#[test]
+fn test_comments() {
+    let mut shell = Shell::default();
+
+    assert_eq!(
+        parse_script(
+            &shell,
+            "#!/usr/bin/env rust_shell\n# ( }\ntrue # )\nfalse\n"
+        )
+        .unwrap()
+        .len(),
+        2
+    );
+    assert_eq!(
+        read_commands("# a\n\ntrue # b\n", true, None, true)
+            .unwrap()
+            .len(),
+        2
+    );
+    assert!(parse_interactive(&shell, "true # )\n").is_ok());
+
+    shell.options.interactive_comments = false;
+
+    assert!(parse_interactive(&shell, "true # )\n").is_err());
+    assert!(parse_script(&shell, "true # )\n").is_ok());
+}
+
+#[test]
+fn test_source() {
+    use crate::builtins::capture;
+    use crate::shell::Variable;
+
+    if process::isolated("source::test_source") {
+        return;
+    }
+
+    let path = std::env::temp_dir().join("rust_shell_test_source");
+    let mut shell = Shell::default();
+
+    fs::write(&path, "A=1\nexport B=2; alias ll=ls\nset -- x\n").unwrap();
+    shell.positional = vec!["old".to_string()];
+
+    let file = path.display().to_string();
+
+    assert_eq!(capture(&mut shell, &["source", &file, "a", "b"]).0, 0);
+    assert_eq!(
+        shell.vars.get("A"),
+        Some(&Variable::Scalar("1".to_string()))
+    );
+    assert!(shell.exported.contains("B"));
+    assert!(shell.aliases.contains_key("ll"));
+    assert_eq!(shell.positional, vec!["old"]);
+
+    fs::write(&path, "alias fine=true\nfine\n").unwrap();
+
+    assert_eq!(capture(&mut shell, &["source", &file]).0, 0);
+
+    fs::write(&path, "A=3\n)\n").unwrap();
+
+    assert_eq!(
+        capture(&mut shell, &[".", &file]),
+        (
+            2,
+            String::new(),
+            format!(
+                "error: unexpected `)` with no matching `(`\n \
+                 --> {}:2:1\n  \
+                 |\n\
+                 2 | )\n  \
+                 | ^\n",
+                file
+            )
+        )
+    );
+    assert_eq!(shell.var("A"), Some("1".to_string()));
+
+    fs::write(&path, "false\nexit\ntrue\n").unwrap();
+
+    assert_eq!(capture(&mut shell, &[".", &file]).0, 1);
+    assert!(shell.exiting);
+
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(
+        capture(&mut Shell::default(), &["source", &file]),
+        (
+            1,
+            String::new(),
+            format!("source: {}: No such file or directory\n", file)
+        )
+    );
+}
+
+#[test]
+fn test_find_script_in_path() {
+    let mut shell = Shell::default();
+    let dir = std::env::temp_dir().join("rust_shell_test_source_path");
+
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("setup.sh"), "true\n").unwrap();
+    shell.set_var(
+        "PATH",
+        crate::shell::Variable::Scalar(format!("/nonexistent:{}", dir.display())),
+    );
+
+    assert_eq!(find_script(&shell, "setup.sh"), dir.join("setup.sh"));
+    assert_eq!(
+        find_script(&shell, "./setup.sh"),
+        PathBuf::from("./setup.sh")
+    );
+    assert_eq!(find_script(&shell, "missing"), PathBuf::from("missing"));
+}
@@ -0,0 +1,364 @@
+use crate::builtins::Stdio;
+use crate::process;
+use crate::shell::Shell;
+use crate::source;
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// The signals `trap` knows by name, in the order `trap -p` lists them.
+const SIGNALS: &[(&str, libc::c_int)] = &[
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("ILL", libc::SIGILL),
+    ("TRAP", libc::SIGTRAP),
+    ("ABRT", libc::SIGABRT),
+    ("BUS", libc::SIGBUS),
+    ("FPE", libc::SIGFPE),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("SEGV", libc::SIGSEGV),
+    ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("CHLD", libc::SIGCHLD),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+    ("TTIN", libc::SIGTTIN),
+    ("TTOU", libc::SIGTTOU),
+    ("URG", libc::SIGURG),
+    ("XCPU", libc::SIGXCPU),
+    ("XFSZ", libc::SIGXFSZ),
+    ("VTALRM", libc::SIGVTALRM),
+    ("PROF", libc::SIGPROF),
+    ("WINCH", libc::SIGWINCH),
+    ("IO", libc::SIGIO),
+    ("SYS", libc::SIGSYS),
+];
+
+/// Conditions that are not signals but moments in the life of the shell.
+const PSEUDO_SIGNALS: &[&str] = &["DEBUG", "ERR", "RETURN"];
+
+/// Signals received since their handlers last ran, indexed by number.
+static PENDING: [AtomicBool; 65] = [const { AtomicBool::new(false) }; 65];
+
+extern "C" fn record_signal(signal: libc::c_int) {
+    PENDING[signal as usize].store(true, Ordering::SeqCst);
+}
+
+fn signal_number(name: &str) -> Option<libc::c_int> {
+    SIGNALS.iter().find(|(n, _)| *n == name).map(|&(_, s)| s)
+}
+
+/// The name a trap is stored under for a signal specification as `trap`
+/// accepts it: `INT`, `SIGINT`, `int` or `2`, with `0` meaning `EXIT`.
+fn condition_name(spec: &str) -> Option<String> {
+    if let Ok(number) = spec.parse::<libc::c_int>() {
+        return match number {
+            0 => Some("EXIT".to_string()),
+            _ => SIGNALS
+                .iter()
+                .find(|&&(_, s)| s == number)
+                .map(|(n, _)| n.to_string()),
+        };
+    }
+
+    let upper = spec.to_ascii_uppercase();
+    let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+
+    if name == "EXIT" || PSEUDO_SIGNALS.contains(&name) || signal_number(name).is_some() {
+        Some(name.to_string())
+    } else {
+        None
+    }
+}
+
+fn set_disposition(signal: libc::c_int, handler: libc::sighandler_t) {
+    unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+
+        action.sa_sigaction = handler;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(signal, &action, ptr::null_mut());
+    }
+}
+
+/// Sets or clears the trap for a condition, and for real signals what the
+/// process does when it arrives.
+fn set_trap(shell: &mut Shell, name: &str, action: Option<&str>) {
+    if let Some(signal) = signal_number(name) {
+        let handler = match action {
+            None => libc::SIG_DFL,
+            Some("") => libc::SIG_IGN,
+            Some(_) => record_signal as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        };
+
+        set_disposition(signal, handler);
+    }
+
+    match action {
+        Some(action) => shell.traps.insert(name.to_string(), action.to_string()),
+        None => shell.traps.remove(name),
+    };
+}
+
+/// Runs the handler for `name` if one is set, leaving `$?` as it was unless
+/// the handler calls `exit`. Handlers do not trigger other traps.
+pub fn run(shell: &mut Shell, name: &str) -> Result<(), ()> {
+    let action = match shell.traps.get(name) {
+        Some(action) if !action.is_empty() && !shell.in_trap => action.clone(),
+        _ => return Ok(()),
+    };
+
+    let exprs = match source::parse_script(shell, &action) {
+        Ok(exprs) => exprs,
+        Err(e) => {
+            eprint!("{}", e.render("trap", &action));
+            return Ok(());
+        }
+    };
+
+    let status = shell.status;
+    let mut result = Ok(());
+
+    shell.in_trap = true;
+
+    for expr in &exprs {
+        if process::execute(shell, expr).is_err() {
+            result = Err(());
+            break;
+        }
+    }
+
+    shell.in_trap = false;
+
+    if result.is_ok() {
+        shell.status = status;
+    }
+
+    result
+}
+
+/// Runs the handlers of the signals that arrived since the last call. This
+/// happens between commands, never inside the signal handler itself.
+pub fn run_pending(shell: &mut Shell) -> Result<(), ()> {
+    for &(name, signal) in SIGNALS {
+        if PENDING[signal as usize].swap(false, Ordering::SeqCst) {
+            run(shell, name)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs the `EXIT` trap once, right before the shell goes away.
+pub fn run_exit(shell: &mut Shell) {
+    run_pending(shell).ok();
+
+    if shell.traps.contains_key("EXIT") {
+        run(shell, "EXIT").ok();
+        shell.traps.remove("EXIT");
+    }
+}
+
+/// A subshell starts with the traps of its parent reset, except for ignored
+/// signals, which stay ignored.
+pub fn reset_for_subshell(shell: &mut Shell) {
+    let names: Vec<String> = shell
+        .traps
+        .iter()
+        .filter(|(_, action)| !action.is_empty())
+        .map(|(name, _)| name.clone())
+        .collect();
+
+    for name in names {
+        set_trap(shell, &name, None);
+    }
+}
+
+/// Signals the shell ignores through `trap ''`, which programs it runs must
+/// ignore as well.
+pub fn ignored_signals(shell: &Shell) -> Vec<libc::c_int> {
+    shell
+        .traps
+        .iter()
+        .filter(|(_, action)| action.is_empty())
+        .filter_map(|(name, _)| signal_number(name))
+        .collect()
+}
+
+fn print_trap(shell: &Shell, name: &str, stdio: &mut Stdio) {
+    if let Some(action) = shell.traps.get(name) {
+        let spec = if signal_number(name).is_some() {
+            format!("SIG{}", name)
+        } else {
+            name.to_string()
+        };
+
+        writeln!(
+            stdio.stdout,
+            "trap -- {} {}",
+            crate::alias::quote(action),
+            spec
+        )
+        .ok();
+    }
+}
+
+/// `trap action condition…` runs `action` when a signal arrives or the shell
+/// reaches one of `EXIT`, `ERR`, `DEBUG` or `RETURN`. An empty action ignores
+/// the signal, `-` resets it and `trap -p` prints the traps in a form the
+/// shell can read back.
+pub fn execute_trap(shell: &mut Shell, argv: &[&str], stdio: &mut Stdio) -> i32 {
+    if argv.get(1) == Some(&"-l") {
+        for (name, signal) in SIGNALS {
+            writeln!(stdio.stdout, "{:2}) SIG{}", signal, name).ok();
+        }
+
+        return 0;
+    }
+
+    let listing = argv.len() == 1 || argv[1] == "-p";
+    let arguments = match argv.get(1) {
+        Some(&"-p") | Some(&"--") => &argv[2..],
+        _ => &argv[1..],
+    };
+
+    if listing {
+        let all: Vec<String> = if arguments.is_empty() {
+            let names = SIGNALS.iter().map(|(n, _)| *n);
+
+            std::iter::once("EXIT")
+                .chain(names)
+                .chain(PSEUDO_SIGNALS.iter().cloned())
+                .map(String::from)
+                .collect()
+        } else {
+            let mut names = Vec::new();
+
+            for spec in arguments {
+                match condition_name(spec) {
+                    Some(name) => names.push(name),
+                    None => {
+                        writeln!(stdio.stderr, "trap: {}: invalid signal specification", spec).ok();
+                        return 1;
+                    }
+                }
+            }
+
+            names
+        };
+
+        for name in all {
+            print_trap(shell, &name, stdio);
+        }
+
+        return 0;
+    }
+
+    // a lone operand or a leading `-` resets the conditions instead
+    let (action, specs) = match arguments {
+        [_] => (None, arguments),
+        ["-", specs @ ..] => (None, specs),
+        [action, specs @ ..] => (Some(*action), specs),
+        [] => unreachable!(),
+    };
+
+    let mut status = 0;
+
+    for spec in specs {
+        match condition_name(spec) {
+            Some(name) => set_trap(shell, &name, action),
+            None => {
+                writeln!(stdio.stderr, "trap: {}: invalid signal specification", spec).ok();
+                status = 1;
+            }
+        }
+    }
+
+    status
+}
+
+#[test]
+fn test_condition_name() {
+    assert_eq!(condition_name("INT"), Some("INT".to_string()));
+    assert_eq!(condition_name("sigterm"), Some("TERM".to_string()));
+    assert_eq!(condition_name("0"), Some("EXIT".to_string()));
+    assert_eq!(condition_name("10"), Some("USR1".to_string()));
+    assert_eq!(condition_name("err"), Some("ERR".to_string()));
+    assert_eq!(condition_name("NOPE"), None);
+    assert_eq!(condition_name("99"), None);
+}
+
+#[test]
+fn test_trap_builtin() {
+    use crate::builtins::capture;
+
+    if process::isolated("trap::test_trap_builtin") {
+        return;
+    }
+
+    let mut shell = Shell::default();
+
+    assert_eq!(
+        capture(&mut shell, &["trap", "echo 'bye'", "EXIT", "0"]).0,
+        0
+    );
+    assert_eq!(capture(&mut shell, &["trap", "", "SIGUSR2"]).0, 0);
+    assert_eq!(
+        capture(&mut shell, &["trap", "-p"]).1,
+        "trap -- 'echo '\\''bye'\\''' EXIT\ntrap -- '' SIGUSR2\n"
+    );
+    assert_eq!(
+        capture(&mut shell, &["trap", "-p", "USR2"]).1,
+        "trap -- '' SIGUSR2\n"
+    );
+    assert_eq!(ignored_signals(&shell), vec![libc::SIGUSR2]);
+    assert_eq!(capture(&mut shell, &["trap", "-", "EXIT", "USR2"]).0, 0);
+    assert!(shell.traps.is_empty());
+    assert_eq!(
+        capture(&mut shell, &["trap", "true", "FOO"]),
+        (
+            1,
+            String::new(),
+            "trap: FOO: invalid signal specification\n".to_string()
+        )
+    );
+}
+
+#[test]
+fn test_signal_runs_between_commands() {
+    if process::isolated("trap::test_signal_runs_between_commands") {
+        return;
+    }
+
+    // a signal may reach any thread, so the commands run in a child where
+    // theirs is the only one
+    let pid = unsafe { libc::fork() };
+
+    if pid == 0 {
+        let mut shell = Shell::new();
+        let script = format!(
+            "trap 'A=caught' USR1; kill -USR1 {}; B=$?",
+            std::process::id()
+        );
+
+        for expr in &source::parse_script(&shell, &script).unwrap() {
+            process::execute(&mut shell, expr).ok();
+        }
+
+        let caught = shell.var("A") == Some("caught".to_string());
+
+        unsafe { libc::_exit(if caught { 0 } else { 1 }) };
+    }
+
+    let mut status = 0;
+
+    unsafe { libc::waitpid(pid, &mut status, 0) };
+
+    assert_eq!(libc::WEXITSTATUS(status), 0);
+}
@@ -1,198 +1,967 @@
-use crate::parser::TokenType;
-use std::iter::Peekable;
+use crate::condition::{escape_quoted, regex_escape};
+use crate::parser::{Span, Token, TokenType};
+use crate::process::word_value;
+use std::error::Error;
+use std::fmt;
 
 #[derive(Debug, PartialEq)]
-pub enum GrammarError {
+#[non_exhaustive]
+pub enum GrammarErrorKind {
     InvalidCmdStart,
+    MissingCommand,
+    UnmatchedParenthesis,
+    UnmatchedBrace,
+    UnexpectedToken,
     MismatchedParenthesis,
+    MismatchedBrace,
+    MismatchedBracket,
+    MissingRedirectionTarget,
+    InvalidConditional,
 }
 
-trait FromTokens<T> {
-    fn from_tokens<'a, U: Iterator<Item = &'a TokenType>>(
-        tokens: &mut Peekable<U>,
-    ) -> Result<T, GrammarError>;
+impl fmt::Display for GrammarErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            GrammarErrorKind::InvalidCmdStart => "unexpected token at the start of a command",
+            GrammarErrorKind::MissingCommand => "expected a command",
+            GrammarErrorKind::UnmatchedParenthesis => "unexpected `)` with no matching `(`",
+            GrammarErrorKind::UnmatchedBrace => "unexpected `}` with no matching `{`",
+            GrammarErrorKind::UnexpectedToken => "unexpected token",
+            GrammarErrorKind::MismatchedParenthesis => "unclosed `(`",
+            GrammarErrorKind::MismatchedBrace => "unclosed `{`",
+            GrammarErrorKind::MismatchedBracket => "unclosed `[[`",
+            GrammarErrorKind::MissingRedirectionTarget => "missing redirection target",
+            GrammarErrorKind::InvalidConditional => "invalid conditional expression",
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct GrammarError {
+    pub kind: GrammarErrorKind,
+    pub span: Span,
+}
+
+impl fmt::Display for GrammarError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.span.line, self.span.column, self.kind)
+    }
+}
+
+impl Error for GrammarError {}
+
+/// The tokens a tree is built from, read from left to right.
+pub(crate) struct Tokens<'a> {
+    tokens: &'a [Token],
+    position: usize,
+    /// How many `(` and `{` groups enclose the next token.
+    parentheses: usize,
+    braces: usize,
+}
+
+impl<'a> Tokens<'a> {
+    pub fn new(tokens: &'a [Token]) -> Tokens<'a> {
+        Tokens {
+            tokens,
+            position: 0,
+            parentheses: 0,
+            braces: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<&'a TokenType> {
+        self.tokens.get(self.position).map(|token| &token.kind)
+    }
+
+    /// Where the next token is, or an empty span after the last one. Final
+    /// newlines do not count, so the end of the input is on its last line.
+    fn span(&self) -> Span {
+        if let Some(token) = self.tokens.get(self.position) {
+            return token.span;
+        }
+
+        self.tokens
+            .iter()
+            .rev()
+            .find(|token| token.kind != TokenType::Newline)
+            .map_or(Span::default(), Token::end)
+    }
+
+    /// Where the tokens read since `start`, an earlier `position`, are.
+    fn since(&self, start: usize) -> Span {
+        if self.position == start {
+            return self.span().empty();
+        }
+
+        self.tokens[start]
+            .span
+            .to(self.tokens[self.position - 1].span)
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = &'a TokenType;
+
+    fn next(&mut self) -> Option<&'a TokenType> {
+        let token = self.peek()?;
+
+        self.position += 1;
+
+        Some(token)
+    }
+}
+
+/// Words with a meaning of their own when they start a command.
+pub const RESERVED_WORDS: &[&str] = &["!", "{", "}", "[[", "]]"];
+
+pub(crate) trait FromTokens<T> {
+    fn from_tokens(tokens: &mut Tokens) -> Result<T, GrammarError>;
+}
+
+/// Redirections the shell knows how to carry out. Here-documents are read
+/// as tokens but cannot be used yet.
+fn is_redirection(token: &TokenType) -> bool {
+    matches!(
+        token,
+        TokenType::IoNumber(_)
+            | TokenType::Less
+            | TokenType::Great
+            | TokenType::DoubleGreat
+            | TokenType::Clobber
+            | TokenType::LessGreat
+            | TokenType::LessAnd
+            | TokenType::GreatAnd
+    )
+}
+
+fn is_word(token: &TokenType) -> bool {
+    matches!(
+        token,
+        TokenType::Word(_)
+            | TokenType::DoubleQuotedString(_)
+            | TokenType::SingleQuotedString(_)
+            | TokenType::Assignment(_)
+    )
+}
+
+/// Reads the next token if it is a word, keeping where it was.
+fn next_word(tokens: &mut Tokens) -> Option<Token> {
+    let token = tokens
+        .tokens
+        .get(tokens.position)
+        .filter(|token| is_word(&token.kind))?;
+
+    tokens.position += 1;
+
+    Some(token.clone())
+}
+
+/// Newlines separate commands like `;` does, may follow `&&`, `||` and `|`,
+/// and blank lines in between mean nothing.
+fn skip_newlines(tokens: &mut Tokens) {
+    while let Some(TokenType::Newline) = tokens.peek() {
+        tokens.next();
+    }
+}
+
+/// `{`, `}`, `!` and `[[` are only reserved words when they make up a whole word in
+/// command position, everywhere else they are ordinary characters.
+fn is_reserved(tokens: &Tokens, word: &str) -> bool {
+    match tokens.peek() {
+        Some(TokenType::Word(s)) => s == word,
+        _ => false,
+    }
+}
+
+fn ends_list(tokens: &Tokens) -> bool {
+    match tokens.peek() {
+        None | Some(TokenType::Parenthesis(')')) => true,
+        _ => is_reserved(tokens, "}"),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Redirection {
+    /// The file descriptor given before the operator, if any.
+    pub(crate) fd: Option<i32>,
+    pub(crate) operator: TokenType,
+    pub(crate) target: Token,
+    pub(crate) span: Span,
+}
+
+impl Redirection {
+    pub fn fd(&self) -> Option<i32> {
+        self.fd
+    }
+
+    /// One of the redirection operators, such as `TokenType::Great`.
+    pub fn operator(&self) -> &TokenType {
+        &self.operator
+    }
+
+    /// The word after the operator, as written.
+    pub fn target(&self) -> &Token {
+        &self.target
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+}
+
+impl FromTokens<Redirection> for Redirection {
+    fn from_tokens(tokens: &mut Tokens) -> Result<Redirection, GrammarError> {
+        let start = tokens.position;
+        let fd = match tokens.peek() {
+            Some(TokenType::IoNumber(n)) => {
+                tokens.next();
+                n.parse().ok()
+            }
+            _ => None,
+        };
+
+        let operator_position = tokens.position;
+        let operator = match tokens.next() {
+            Some(t) if is_redirection(t) && !matches!(t, TokenType::IoNumber(_)) => t.clone(),
+            _ => unreachable!(),
+        };
+
+        let target = match next_word(tokens) {
+            Some(target) => target,
+            None => {
+                return Err(GrammarError {
+                    kind: GrammarErrorKind::MissingRedirectionTarget,
+                    span: tokens.tokens[operator_position].span,
+                })
+            }
+        };
+
+        Ok(Redirection {
+            fd,
+            operator,
+            target,
+            span: tokens.since(start),
+        })
+    }
+}
+
+fn get_redirections(tokens: &mut Tokens) -> Result<Vec<Redirection>, GrammarError> {
+    let mut redirections = Vec::new();
+
+    while let Some(t) = tokens.peek() {
+        if !is_redirection(t) {
+            break;
+        }
+
+        redirections.push(Redirection::from_tokens(tokens)?);
+    }
+
+    Ok(redirections)
+}
+
+/// Unary operators understood by `test`, `[` and `[[`.
+pub const UNARY_TESTS: &[&str] = &[
+    "-b", "-c", "-d", "-e", "-f", "-g", "-h", "-k", "-L", "-n", "-O", "-G", "-p", "-r", "-s", "-S",
+    "-t", "-u", "-w", "-x", "-z",
+];
+
+/// Binary operators understood by `test`, `[` and `[[`. Inside `[[` the `=`,
+/// `==` and `!=` operators match glob patterns and `=~` a regular expression.
+pub const BINARY_TESTS: &[&str] = &[
+    "=", "==", "!=", "=~", "<", ">", "-eq", "-ne", "-lt", "-le", "-gt", "-ge", "-nt", "-ot", "-ef",
+];
+
+fn get_operand(tokens: &mut Tokens) -> Option<Token> {
+    if is_reserved(tokens, "]]") {
+        return None;
+    }
+
+    next_word(tokens)
+}
+
+fn get_binary_test(tokens: &mut Tokens) -> Option<String> {
+    let operator = match tokens.peek() {
+        Some(TokenType::Word(s)) if BINARY_TESTS.contains(&s.as_str()) => s.clone(),
+        Some(TokenType::Less) => "<".to_string(),
+        Some(TokenType::Great) => ">".to_string(),
+        _ => return None,
+    };
+
+    tokens.next();
+
+    Some(operator)
 }
 
+/// The right side of `=~` is lexed as ordinary tokens, so a regex such as
+/// `^(a|b)+$` is glued back together here. A lone quoted string is kept as is
+/// to be matched literally, and so are quoted pieces of a longer regex. The
+/// glued regex is a word that needs no quote removal.
+fn get_regex(tokens: &mut Tokens) -> Option<Token> {
+    let start = tokens.position;
+    let first = get_operand(tokens);
+    let mut regex = String::new();
+    let mut depth = 0;
+    let mut pieces = 0;
+
+    if let Some(ref token) = first {
+        pieces += 1;
+        push_regex_piece(&mut regex, &token.kind);
+    }
+
+    loop {
+        match tokens.peek() {
+            Some(TokenType::Parenthesis('(')) => {
+                depth += 1;
+                regex.push('(');
+            }
+            Some(TokenType::Parenthesis(')')) if depth > 0 => {
+                depth -= 1;
+                regex.push(')');
+            }
+            Some(TokenType::Pipe) => regex.push('|'),
+            _ => match get_operand(tokens) {
+                Some(token) => {
+                    push_regex_piece(&mut regex, &token.kind);
+                    pieces += 1;
+                    continue;
+                }
+                None => break,
+            },
+        }
+
+        pieces += 1;
+        tokens.next();
+    }
+
+    match first {
+        Some(token) if pieces == 1 && !matches!(token.kind, TokenType::Word(_)) => Some(token),
+        _ if pieces == 0 => None,
+        _ => Some(Token {
+            kind: TokenType::Word(regex),
+            span: tokens.since(start),
+            end: tokens.tokens[tokens.position - 1].end,
+        }),
+    }
+}
+
+/// Adds a word to a regex: backslash escapes are left for the regex engine
+/// and quoted parts are escaped so they match literally.
+fn push_regex_piece(regex: &mut String, token: &TokenType) {
+    match token {
+        TokenType::Word(s) => regex.push_str(&escape_quoted(s, regex_escape)),
+        _ => regex.push_str(&regex_escape(&word_value(token))),
+    }
+}
+
+/// The expression between `[[` and `]]`. Its words are only quote-removed, so
+/// `(`, `)`, `&&`, `||`, `<` and `>` take a meaning of their own here.
 #[derive(Debug, PartialEq)]
+pub enum CondExprOptions {
+    Word(Token),
+    Unary(String, Token),
+    Binary(Token, String, Token),
+    Not(Box<CondExpr>),
+    And(Box<CondExpr>, Box<CondExpr>),
+    Or(Box<CondExpr>, Box<CondExpr>),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct CondExpr {
+    pub(crate) value: CondExprOptions,
+    pub(crate) span: Span,
+}
+
+impl CondExpr {
+    pub fn value(&self) -> &CondExprOptions {
+        &self.value
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+}
+
+impl FromTokens<CondExpr> for CondExpr {
+    fn from_tokens(tokens: &mut Tokens) -> Result<CondExpr, GrammarError> {
+        let start = tokens.position;
+        let mut expr = get_cond_and(tokens)?;
+
+        while let Some(TokenType::Or) = tokens.peek() {
+            tokens.next();
+
+            let right = get_cond_and(tokens)?;
+
+            expr = CondExpr {
+                value: CondExprOptions::Or(Box::new(expr), Box::new(right)),
+                span: tokens.since(start),
+            };
+        }
+
+        Ok(expr)
+    }
+}
+
+fn get_cond_and(tokens: &mut Tokens) -> Result<CondExpr, GrammarError> {
+    let start = tokens.position;
+    let mut expr = get_cond_not(tokens)?;
+
+    while let Some(TokenType::And) = tokens.peek() {
+        tokens.next();
+
+        let right = get_cond_not(tokens)?;
+
+        expr = CondExpr {
+            value: CondExprOptions::And(Box::new(expr), Box::new(right)),
+            span: tokens.since(start),
+        };
+    }
+
+    Ok(expr)
+}
+
+fn get_cond_not(tokens: &mut Tokens) -> Result<CondExpr, GrammarError> {
+    let start = tokens.position;
+
+    if is_reserved(tokens, "!") {
+        tokens.next();
+
+        let inner_expr = get_cond_not(tokens)?;
+
+        return Ok(CondExpr {
+            value: CondExprOptions::Not(Box::new(inner_expr)),
+            span: tokens.since(start),
+        });
+    }
+
+    if let Some(TokenType::Parenthesis('(')) = tokens.peek() {
+        tokens.next();
+
+        let inner_expr = CondExpr::from_tokens(tokens)?;
+
+        if tokens.peek() != Some(&TokenType::Parenthesis(')')) {
+            let opening = tokens.tokens[start].span;
+
+            return Err(unclosed(
+                tokens,
+                GrammarErrorKind::MismatchedParenthesis,
+                opening,
+            ));
+        }
+
+        tokens.next();
+
+        return Ok(CondExpr {
+            span: tokens.since(start),
+            ..inner_expr
+        });
+    }
+
+    let value = get_cond_primary(tokens)?;
+
+    Ok(CondExpr {
+        value,
+        span: tokens.since(start),
+    })
+}
+
+fn get_cond_primary(tokens: &mut Tokens) -> Result<CondExprOptions, GrammarError> {
+    // points at whatever stands where an operand was expected
+    let invalid = |tokens: &Tokens| GrammarError {
+        kind: GrammarErrorKind::InvalidConditional,
+        span: tokens.span(),
+    };
+
+    let left = match get_operand(tokens) {
+        Some(operand) => operand,
+        None => return Err(invalid(tokens)),
+    };
+
+    if let TokenType::Word(ref op) = left.kind {
+        if UNARY_TESTS.contains(&op.as_str()) {
+            if let Some(operand) = get_operand(tokens) {
+                return Ok(CondExprOptions::Unary(op.clone(), operand));
+            }
+        }
+    }
+
+    match get_binary_test(tokens) {
+        Some(ref op) if op == "=~" => match get_regex(tokens) {
+            Some(right) => Ok(CondExprOptions::Binary(left, op.clone(), right)),
+            None => Err(invalid(tokens)),
+        },
+        Some(op) => match get_operand(tokens) {
+            Some(right) => Ok(CondExprOptions::Binary(left, op, right)),
+            None => Err(invalid(tokens)),
+        },
+        None => Ok(CondExprOptions::Word(left)),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+#[non_exhaustive]
 pub enum CallExprOptions {
-    ProgCall(TokenType, Vec<TokenType>),
+    ProgCall(Token, Vec<Token>),
     Parenthesis(Box<Expr>),
+    Braces(Box<Expr>),
+    Conditional(CondExpr),
     Empty,
 }
 
 #[derive(Debug, PartialEq)]
 pub struct CallExpr {
-    value: CallExprOptions,
+    pub(crate) value: CallExprOptions,
+    pub(crate) redirections: Vec<Redirection>,
+    pub(crate) span: Span,
+}
+
+impl CallExpr {
+    pub fn value(&self) -> &CallExprOptions {
+        &self.value
+    }
+
+    /// The redirections after the command, in the order they are applied.
+    pub fn redirections(&self) -> &[Redirection] {
+        &self.redirections
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
 }
 
 impl FromTokens<CallExpr> for CallExpr {
-    fn from_tokens<'a, U: Iterator<Item = &'a TokenType>>(
-        tokens: &mut Peekable<U>,
-    ) -> Result<CallExpr, GrammarError> {
-        match tokens.peek() {
-            Some(&TokenType::Word(s)) => {
-                tokens.next();
+    fn from_tokens(tokens: &mut Tokens) -> Result<CallExpr, GrammarError> {
+        let start = tokens.position;
+        let (value, redirections) = get_call(tokens)?;
 
-                let mut args = Vec::new();
+        Ok(CallExpr {
+            value,
+            redirections,
+            span: tokens.since(start),
+        })
+    }
+}
 
-                loop {
-                    match tokens.peek() {
-                        Some(&TokenType::Word(s)) => {
-                            tokens.next();
-                            args.push(TokenType::Word(s.clone()));
-                        }
-                        Some(&TokenType::DoubleQuotedString(s)) => {
-                            tokens.next();
-                            args.push(TokenType::DoubleQuotedString(s.clone()));
-                        }
-                        Some(&TokenType::SingleQuotedString(s)) => {
-                            tokens.next();
-                            args.push(TokenType::SingleQuotedString(s.clone()));
-                        }
-                        _ => {
-                            break;
-                        }
-                    }
-                }
+fn get_call(tokens: &mut Tokens) -> Result<(CallExprOptions, Vec<Redirection>), GrammarError> {
+    // unclosed groups are reported where they open
+    let opening = tokens.span();
 
-                Ok(CallExpr {
-                    value: CallExprOptions::ProgCall(TokenType::Word(s.clone()), args),
-                })
+    if is_reserved(tokens, "{") {
+        tokens.next();
+        tokens.braces += 1;
+
+        let inner_expr = Expr::from_tokens(tokens)?;
+
+        tokens.braces -= 1;
+
+        if !is_reserved(tokens, "}") {
+            return Err(unclosed(tokens, GrammarErrorKind::MismatchedBrace, opening));
+        }
+
+        tokens.next();
+
+        return Ok((
+            CallExprOptions::Braces(Box::new(inner_expr)),
+            get_redirections(tokens)?,
+        ));
+    }
+
+    if is_reserved(tokens, "[[") {
+        tokens.next();
+
+        let cond_expr = CondExpr::from_tokens(tokens)?;
+
+        if !is_reserved(tokens, "]]") {
+            return Err(unclosed(
+                tokens,
+                GrammarErrorKind::MismatchedBracket,
+                opening,
+            ));
+        }
+
+        tokens.next();
+
+        return Ok((
+            CallExprOptions::Conditional(cond_expr),
+            get_redirections(tokens)?,
+        ));
+    }
+
+    match tokens.peek() {
+        // an empty group, or a closing token with no group to close
+        Some(TokenType::Word(s)) if s == "}" && tokens.braces == 0 => Err(unexpected(tokens)),
+        Some(TokenType::Parenthesis(')')) if tokens.parentheses == 0 => Err(unexpected(tokens)),
+        Some(TokenType::Word(s)) if s == "}" => Err(GrammarError {
+            kind: GrammarErrorKind::MissingCommand,
+            span: opening,
+        }),
+        Some(t) if is_word(t) || is_redirection(t) => get_simple_command(tokens),
+        Some(TokenType::Parenthesis('(')) => {
+            tokens.next();
+            tokens.parentheses += 1;
+
+            let inner_expr = Expr::from_tokens(tokens)?;
+
+            tokens.parentheses -= 1;
+
+            if tokens.peek() != Some(&TokenType::Parenthesis(')')) {
+                return Err(unclosed(
+                    tokens,
+                    GrammarErrorKind::MismatchedParenthesis,
+                    opening,
+                ));
             }
-            Some(&TokenType::Parenthesis('(')) => {
-                tokens.next();
 
-                let inner_expr = Expr::from_tokens(tokens)?;
+            tokens.next();
 
-                match tokens.peek() {
-                    Some(&TokenType::Parenthesis(')')) => Ok(CallExpr {
-                        value: CallExprOptions::Parenthesis(Box::new(inner_expr)),
-                    }),
-                    _ => Err(GrammarError::MismatchedParenthesis),
-                }
+            Ok((
+                CallExprOptions::Parenthesis(Box::new(inner_expr)),
+                get_redirections(tokens)?,
+            ))
+        }
+        // only a line with nothing on it is an empty command
+        None if tokens.tokens.iter().all(|t| t.kind == TokenType::Newline) => {
+            Ok((CallExprOptions::Empty, Vec::new()))
+        }
+        None | Some(TokenType::Parenthesis(')')) => Err(GrammarError {
+            kind: GrammarErrorKind::MissingCommand,
+            span: opening,
+        }),
+        _ => Err(GrammarError {
+            kind: GrammarErrorKind::InvalidCmdStart,
+            span: opening,
+        }),
+    }
+}
+
+/// A command name and its arguments with redirections anywhere among them,
+/// before the name too, or only redirections, which still open their files.
+fn get_simple_command(
+    tokens: &mut Tokens,
+) -> Result<(CallExprOptions, Vec<Redirection>), GrammarError> {
+    let mut redirections = get_redirections(tokens)?;
+
+    let name = match next_word(tokens) {
+        Some(name) => name,
+        None => return Ok((CallExprOptions::Empty, redirections)),
+    };
+
+    let mut args = Vec::new();
+
+    loop {
+        match tokens.peek() {
+            Some(t) if is_word(t) => args.extend(next_word(tokens)),
+            Some(t) if is_redirection(t) => {
+                redirections.push(Redirection::from_tokens(tokens)?);
+            }
+            _ => {
+                break;
             }
-            None => Ok(CallExpr {
-                value: CallExprOptions::Empty,
-            }),
-            _ => Err(GrammarError::InvalidCmdStart),
         }
     }
+
+    Ok((CallExprOptions::ProgCall(name, args), redirections))
 }
 
+/// A pipeline: one or more commands joined by `|`, optionally preceded by a
+/// `!` that inverts its exit status.
 #[derive(Debug, PartialEq)]
-pub enum OrExprOptions {
-    SingleExpr(CallExpr),
-    Or(CallExpr, Box<OrExpr>),
+pub struct PipeExpr {
+    pub(crate) negated: bool,
+    pub(crate) commands: Vec<CallExpr>,
+    pub(crate) span: Span,
 }
 
-#[derive(Debug, PartialEq)]
-pub struct OrExpr {
-    value: OrExprOptions,
+impl PipeExpr {
+    /// Whether the pipeline starts with `!`.
+    pub fn is_negated(&self) -> bool {
+        self.negated
+    }
+
+    pub fn commands(&self) -> &[CallExpr] {
+        &self.commands
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
 }
 
-impl FromTokens<OrExpr> for OrExpr {
-    fn from_tokens<'a, U: Iterator<Item = &'a TokenType>>(
-        tokens: &mut Peekable<U>,
-    ) -> Result<OrExpr, GrammarError> {
-        let call_expr = CallExpr::from_tokens(tokens)?;
+impl FromTokens<PipeExpr> for PipeExpr {
+    fn from_tokens(tokens: &mut Tokens) -> Result<PipeExpr, GrammarError> {
+        let start = tokens.position;
+        let negated = is_reserved(tokens, "!");
 
-        match tokens.peek() {
-            Some(&TokenType::Or) => {
-                tokens.next();
+        if negated {
+            tokens.next();
+        }
 
-                Ok(OrExpr {
-                    value: OrExprOptions::Or(call_expr, Box::new(OrExpr::from_tokens(tokens)?)),
-                })
-            }
-            _ => Ok(OrExpr {
-                value: OrExprOptions::SingleExpr(call_expr),
-            }),
+        let mut commands = vec![CallExpr::from_tokens(tokens)?];
+
+        while let Some(TokenType::Pipe) = tokens.peek() {
+            tokens.next();
+            skip_newlines(tokens);
+            commands.push(CallExpr::from_tokens(tokens)?);
         }
+
+        Ok(PipeExpr {
+            negated,
+            commands,
+            span: tokens.since(start),
+        })
     }
 }
 
+/// An AND-OR list: pipelines joined by `&&` and `||`, which have the same
+/// precedence and group to the left, so `a && b || c` is `(a && b) || c`.
 #[derive(Debug, PartialEq)]
-pub enum AndExprOptions {
-    SingleExpr(OrExpr),
-    And(OrExpr, Box<AndExpr>),
+pub enum AndOrExprOptions {
+    SingleExpr(PipeExpr),
+    And(Box<AndOrExpr>, PipeExpr),
+    Or(Box<AndOrExpr>, PipeExpr),
 }
 
 #[derive(Debug, PartialEq)]
-pub struct AndExpr {
-    value: AndExprOptions,
+pub struct AndOrExpr {
+    pub(crate) value: AndOrExprOptions,
+    pub(crate) span: Span,
 }
 
-impl FromTokens<AndExpr> for AndExpr {
-    fn from_tokens<'a, U: Iterator<Item = &'a TokenType>>(
-        tokens: &mut Peekable<U>,
-    ) -> Result<AndExpr, GrammarError> {
-        let or_expr = OrExpr::from_tokens(tokens)?;
+impl AndOrExpr {
+    pub fn value(&self) -> &AndOrExprOptions {
+        &self.value
+    }
 
-        match tokens.peek() {
-            Some(&TokenType::And) => {
-                tokens.next();
+    pub fn span(&self) -> Span {
+        self.span
+    }
+}
 
-                Ok(AndExpr {
-                    value: AndExprOptions::And(or_expr, Box::new(AndExpr::from_tokens(tokens)?)),
-                })
-            }
-            _ => Ok(AndExpr {
-                value: AndExprOptions::SingleExpr(or_expr),
-            }),
+impl FromTokens<AndOrExpr> for AndOrExpr {
+    fn from_tokens(tokens: &mut Tokens) -> Result<AndOrExpr, GrammarError> {
+        let start = tokens.position;
+        let mut and_or_expr = AndOrExpr {
+            value: AndOrExprOptions::SingleExpr(PipeExpr::from_tokens(tokens)?),
+            span: tokens.since(start),
+        };
+
+        loop {
+            let is_and = match tokens.peek() {
+                Some(TokenType::And) => true,
+                Some(TokenType::Or) => false,
+                _ => return Ok(and_or_expr),
+            };
+
+            tokens.next();
+            skip_newlines(tokens);
+
+            let left = Box::new(and_or_expr);
+            let right = PipeExpr::from_tokens(tokens)?;
+
+            and_or_expr = AndOrExpr {
+                value: if is_and {
+                    AndOrExprOptions::And(left, right)
+                } else {
+                    AndOrExprOptions::Or(left, right)
+                },
+                span: tokens.since(start),
+            };
         }
     }
 }
 
 #[derive(Debug, PartialEq)]
 pub enum SemicolonExprOptions {
-    SingleExpr(AndExpr),
-    Semicolon(AndExpr, Box<SemicolonExpr>),
+    SingleExpr(AndOrExpr),
+    Semicolon(AndOrExpr, Box<SemicolonExpr>),
 }
 
 #[derive(Debug, PartialEq)]
 pub struct SemicolonExpr {
-    value: SemicolonExprOptions,
+    pub(crate) value: SemicolonExprOptions,
+    pub(crate) span: Span,
+}
+
+impl SemicolonExpr {
+    pub fn value(&self) -> &SemicolonExprOptions {
+        &self.value
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
 }
 
 impl FromTokens<SemicolonExpr> for SemicolonExpr {
-    fn from_tokens<'a, U: Iterator<Item = &'a TokenType>>(
-        tokens: &mut Peekable<U>,
-    ) -> Result<SemicolonExpr, GrammarError> {
-        let and_expr = AndExpr::from_tokens(tokens)?;
+    fn from_tokens(tokens: &mut Tokens) -> Result<SemicolonExpr, GrammarError> {
+        skip_newlines(tokens);
+
+        let start = tokens.position;
+        let and_or_expr = AndOrExpr::from_tokens(tokens)?;
 
         match tokens.peek() {
-            Some(&TokenType::Semicolon) => {
+            Some(TokenType::Semicolon) | Some(TokenType::Newline) => {
                 tokens.next();
+                skip_newlines(tokens);
+
+                if ends_list(tokens) {
+                    // a trailing `;` terminates the list instead of separating
+                    return Ok(SemicolonExpr {
+                        value: SemicolonExprOptions::SingleExpr(and_or_expr),
+                        span: tokens.since(start),
+                    });
+                }
+
+                let rest = SemicolonExpr::from_tokens(tokens)?;
 
                 Ok(SemicolonExpr {
-                    value: SemicolonExprOptions::Semicolon(
-                        and_expr,
-                        Box::new(SemicolonExpr::from_tokens(tokens)?),
-                    ),
+                    value: SemicolonExprOptions::Semicolon(and_or_expr, Box::new(rest)),
+                    span: tokens.since(start),
                 })
             }
             _ => Ok(SemicolonExpr {
-                value: SemicolonExprOptions::SingleExpr(and_expr),
+                value: SemicolonExprOptions::SingleExpr(and_or_expr),
+                span: tokens.since(start),
             }),
         }
     }
 }
 
+/// A `#` comment found among the tokens of a command.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Comment {
+    pub(crate) text: String,
+    pub(crate) span: Span,
+    /// Whether nothing but blanks comes before it on its line.
+    pub(crate) own_line: bool,
+}
+
+impl Comment {
+    /// The comment as written, starting with `#`.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn is_own_line(&self) -> bool {
+        self.own_line
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub struct Expr {
-    value: SemicolonExpr,
+    pub(crate) value: SemicolonExpr,
+    pub(crate) span: Span,
+    /// The comments among the tokens, in order. Only trees built by
+    /// `parse_program` have them, never the groups nested inside.
+    pub(crate) comments: Vec<Comment>,
+}
+
+impl Expr {
+    /// The list of commands the line is made of.
+    pub fn value(&self) -> &SemicolonExpr {
+        &self.value
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn comments(&self) -> &[Comment] {
+        &self.comments
+    }
 }
 
 impl FromTokens<Expr> for Expr {
-    fn from_tokens<'a, U: Iterator<Item = &'a TokenType>>(
-        tokens: &mut Peekable<U>,
-    ) -> Result<Expr, GrammarError> {
-        return Ok(Expr {
-            value: SemicolonExpr::from_tokens(tokens)?,
-        });
+    fn from_tokens(tokens: &mut Tokens) -> Result<Expr, GrammarError> {
+        let value = SemicolonExpr::from_tokens(tokens)?;
+        let span = value.span;
+
+        Ok(Expr {
+            value,
+            span,
+            comments: Vec::new(),
+        })
     }
 }
 
+/// The error for a token found where nothing more was expected.
+fn unexpected(tokens: &Tokens) -> GrammarError {
+    let kind = match tokens.peek() {
+        Some(TokenType::Parenthesis(')')) => GrammarErrorKind::UnmatchedParenthesis,
+        Some(TokenType::Word(s)) if s == "}" => GrammarErrorKind::UnmatchedBrace,
+        _ => GrammarErrorKind::UnexpectedToken,
+    };
+
+    GrammarError {
+        kind,
+        span: tokens.span(),
+    }
+}
+
+/// The error for a group whose closing token is missing: if the line ended
+/// it is reported where the group opens, otherwise at what came instead.
+fn unclosed(tokens: &Tokens, kind: GrammarErrorKind, opening: Span) -> GrammarError {
+    match tokens.peek() {
+        None => GrammarError {
+            kind,
+            span: opening,
+        },
+        Some(_) => unexpected(tokens),
+    }
+}
+
+/// Takes the comments out of `tokens`, which the grammar knows nothing
+/// about.
+fn split_comments(tokens: &[Token]) -> (Vec<Token>, Vec<Comment>) {
+    let mut rest = Vec::new();
+    let mut comments = Vec::new();
+
+    for token in tokens {
+        match token.kind {
+            TokenType::Comment(ref text) => comments.push(Comment {
+                text: text.clone(),
+                span: token.span,
+                own_line: rest
+                    .last()
+                    .is_none_or(|t: &Token| t.kind == TokenType::Newline),
+            }),
+            _ => rest.push(token.clone()),
+        }
+    }
+
+    (rest, comments)
+}
+
+/// Builds the tree for a whole command, which has to use up every token.
+pub fn parse_program(tokens: &[Token]) -> Result<Expr, GrammarError> {
+    let (tokens, comments) = split_comments(tokens);
+    let mut tokens = Tokens::new(&tokens);
+    let expr = Expr::from_tokens(&mut tokens)?;
+
+    match tokens.peek() {
+        None => Ok(Expr { comments, ..expr }),
+        Some(_) => Err(unexpected(&tokens)),
+    }
+}
+
+/// Whether `tokens` only fail to make a command because they stop too
+/// early, as in `ls &&` or `{ ls`, so that the next line could complete it.
+pub(crate) fn is_incomplete(tokens: &[Token]) -> bool {
+    let (tokens, _) = split_comments(tokens);
+    let mut tokens = Tokens::new(&tokens);
+
+    Expr::from_tokens(&mut tokens).is_err() && tokens.peek().is_none()
+}
+
+#[cfg(test)]
+fn unspanned(tokens: &[TokenType]) -> Vec<Token> {
+    tokens.iter().cloned().map(Token::from).collect()
+}
+
 #[test]
 fn test_simple_tree() {
     let tokens = [
@@ -200,23 +969,32 @@ fn test_simple_tree() {
         TokenType::DoubleQuotedString("\"foo\"".to_string()),
     ];
 
-    let mut it = tokens.iter().peekable();
+    let tokens = unspanned(&tokens);
+    let mut it = Tokens::new(&tokens);
 
     assert_eq!(
         Expr::from_tokens(&mut it).unwrap(),
         Expr {
             value: SemicolonExpr {
-                value: SemicolonExprOptions::SingleExpr(AndExpr {
-                    value: AndExprOptions::SingleExpr(OrExpr {
-                        value: OrExprOptions::SingleExpr(CallExpr {
+                value: SemicolonExprOptions::SingleExpr(AndOrExpr {
+                    value: AndOrExprOptions::SingleExpr(PipeExpr {
+                        negated: false,
+                        commands: vec![CallExpr {
                             value: CallExprOptions::ProgCall(
-                                TokenType::Word("echo".to_string()),
-                                vec![TokenType::DoubleQuotedString("\"foo\"".to_string())]
+                                TokenType::Word("echo".to_string()).into(),
+                                vec![TokenType::DoubleQuotedString("\"foo\"".to_string()).into()]
                             ),
-                        }),
+                            redirections: vec![],
+                            span: Span::default(),
+                        }],
+                        span: Span::default(),
                     }),
+                    span: Span::default(),
                 }),
+                span: Span::default(),
             },
+            span: Span::default(),
+            comments: vec![],
         }
     );
 }
@@ -229,35 +1007,49 @@ fn test_and() {
         TokenType::Word("ls".to_string()),
     ];
 
-    let mut it = tokens.iter().peekable();
+    let tokens = unspanned(&tokens);
+    let mut it = Tokens::new(&tokens);
 
     assert_eq!(
         Expr::from_tokens(&mut it).unwrap(),
         Expr {
             value: SemicolonExpr {
-                value: SemicolonExprOptions::SingleExpr(AndExpr {
-                    value: AndExprOptions::And(
-                        OrExpr {
-                            value: OrExprOptions::SingleExpr(CallExpr {
-                                value: CallExprOptions::ProgCall(
-                                    TokenType::Word("ls".to_string()),
-                                    vec![]
-                                ),
-                            }),
-                        },
-                        Box::new(AndExpr {
-                            value: AndExprOptions::SingleExpr(OrExpr {
-                                value: OrExprOptions::SingleExpr(CallExpr {
+                value: SemicolonExprOptions::SingleExpr(AndOrExpr {
+                    value: AndOrExprOptions::And(
+                        Box::new(AndOrExpr {
+                            value: AndOrExprOptions::SingleExpr(PipeExpr {
+                                negated: false,
+                                commands: vec![CallExpr {
                                     value: CallExprOptions::ProgCall(
-                                        TokenType::Word("ls".to_string()),
+                                        TokenType::Word("ls".to_string()).into(),
                                         vec![]
                                     ),
-                                })
+                                    redirections: vec![],
+                                    span: Span::default(),
+                                }],
+                                span: Span::default(),
                             }),
-                        })
+                            span: Span::default(),
+                        }),
+                        PipeExpr {
+                            negated: false,
+                            commands: vec![CallExpr {
+                                value: CallExprOptions::ProgCall(
+                                    TokenType::Word("ls".to_string()).into(),
+                                    vec![]
+                                ),
+                                redirections: vec![],
+                                span: Span::default(),
+                            }],
+                            span: Span::default(),
+                        }
                     ),
+                    span: Span::default(),
                 }),
+                span: Span::default(),
             },
+            span: Span::default(),
+            comments: vec![],
         }
     );
 }
@@ -270,33 +1062,49 @@ fn test_or() {
         TokenType::Word("ls".to_string()),
     ];
 
-    let mut it = tokens.iter().peekable();
+    let tokens = unspanned(&tokens);
+    let mut it = Tokens::new(&tokens);
 
     assert_eq!(
         Expr::from_tokens(&mut it).unwrap(),
         Expr {
             value: SemicolonExpr {
-                value: SemicolonExprOptions::SingleExpr(AndExpr {
-                    value: AndExprOptions::SingleExpr(OrExpr {
-                        value: OrExprOptions::Or(
-                            CallExpr {
-                                value: CallExprOptions::ProgCall(
-                                    TokenType::Word("ls".to_string()),
-                                    vec![]
-                                ),
-                            },
-                            Box::new(OrExpr {
-                                value: OrExprOptions::SingleExpr(CallExpr {
+                value: SemicolonExprOptions::SingleExpr(AndOrExpr {
+                    value: AndOrExprOptions::Or(
+                        Box::new(AndOrExpr {
+                            value: AndOrExprOptions::SingleExpr(PipeExpr {
+                                negated: false,
+                                commands: vec![CallExpr {
                                     value: CallExprOptions::ProgCall(
-                                        TokenType::Word("ls".to_string()),
+                                        TokenType::Word("ls".to_string()).into(),
                                         vec![]
                                     ),
-                                }),
-                            })
-                        ),
-                    }),
+                                    redirections: vec![],
+                                    span: Span::default(),
+                                }],
+                                span: Span::default(),
+                            }),
+                            span: Span::default(),
+                        }),
+                        PipeExpr {
+                            negated: false,
+                            commands: vec![CallExpr {
+                                value: CallExprOptions::ProgCall(
+                                    TokenType::Word("ls".to_string()).into(),
+                                    vec![]
+                                ),
+                                redirections: vec![],
+                                span: Span::default(),
+                            }],
+                            span: Span::default(),
+                        }
+                    ),
+                    span: Span::default(),
                 }),
+                span: Span::default(),
             },
+            span: Span::default(),
+            comments: vec![],
         }
     );
 }
@@ -309,37 +1117,52 @@ fn test_semicolon() {
         TokenType::Word("ls".to_string()),
     ];
 
-    let mut it = tokens.iter().peekable();
+    let tokens = unspanned(&tokens);
+    let mut it = Tokens::new(&tokens);
 
     assert_eq!(
         Expr::from_tokens(&mut it).unwrap(),
         Expr {
             value: SemicolonExpr {
                 value: SemicolonExprOptions::Semicolon(
-                    AndExpr {
-                        value: AndExprOptions::SingleExpr(OrExpr {
-                            value: OrExprOptions::SingleExpr(CallExpr {
+                    AndOrExpr {
+                        value: AndOrExprOptions::SingleExpr(PipeExpr {
+                            negated: false,
+                            commands: vec![CallExpr {
                                 value: CallExprOptions::ProgCall(
-                                    TokenType::Word("ls".to_string()),
+                                    TokenType::Word("ls".to_string()).into(),
                                     vec![]
                                 ),
-                            }),
+                                redirections: vec![],
+                                span: Span::default(),
+                            }],
+                            span: Span::default(),
                         }),
+                        span: Span::default(),
                     },
                     Box::new(SemicolonExpr {
-                        value: SemicolonExprOptions::SingleExpr(AndExpr {
-                            value: AndExprOptions::SingleExpr(OrExpr {
-                                value: OrExprOptions::SingleExpr(CallExpr {
+                        value: SemicolonExprOptions::SingleExpr(AndOrExpr {
+                            value: AndOrExprOptions::SingleExpr(PipeExpr {
+                                negated: false,
+                                commands: vec![CallExpr {
                                     value: CallExprOptions::ProgCall(
-                                        TokenType::Word("ls".to_string()),
+                                        TokenType::Word("ls".to_string()).into(),
                                         vec![]
                                     ),
-                                }),
+                                    redirections: vec![],
+                                    span: Span::default(),
+                                }],
+                                span: Span::default(),
                             }),
+                            span: Span::default(),
                         }),
+                        span: Span::default(),
                     })
                 ),
+                span: Span::default(),
             },
+            span: Span::default(),
+            comments: vec![],
         }
     );
 }
@@ -354,45 +1177,67 @@ fn test_parenthesis() {
         TokenType::Parenthesis(')'),
     ];
 
-    let mut it = tokens.iter().peekable();
+    let tokens = unspanned(&tokens);
+    let mut it = Tokens::new(&tokens);
 
     assert_eq!(
         Expr::from_tokens(&mut it).unwrap(),
         Expr {
             value: SemicolonExpr {
-                value: SemicolonExprOptions::SingleExpr(AndExpr {
-                    value: AndExprOptions::And(
-                        OrExpr {
-                            value: OrExprOptions::SingleExpr(CallExpr {
-                                value: CallExprOptions::ProgCall(
-                                    TokenType::Word("ls".to_string()),
-                                    vec![]
-                                ),
+                value: SemicolonExprOptions::SingleExpr(AndOrExpr {
+                    value: AndOrExprOptions::And(
+                        Box::new(AndOrExpr {
+                            value: AndOrExprOptions::SingleExpr(PipeExpr {
+                                negated: false,
+                                commands: vec![CallExpr {
+                                    value: CallExprOptions::ProgCall(
+                                        TokenType::Word("ls".to_string()).into(),
+                                        vec![]
+                                    ),
+                                    redirections: vec![],
+                                    span: Span::default(),
+                                }],
+                                span: Span::default(),
                             }),
-                        },
-                        Box::new(AndExpr {
-                            value: AndExprOptions::SingleExpr(OrExpr {
-                                value: OrExprOptions::SingleExpr(CallExpr {
-                                    value: CallExprOptions::Parenthesis(Box::new(Expr {
-                                        value: SemicolonExpr {
-                                            value: SemicolonExprOptions::SingleExpr(AndExpr {
-                                                value: AndExprOptions::SingleExpr(OrExpr {
-                                                    value: OrExprOptions::SingleExpr(CallExpr {
-                                                        value: CallExprOptions::ProgCall(
-                                                            TokenType::Word("ls".to_string()),
-                                                            vec![]
-                                                        ),
-                                                    }),
-                                                }),
+                            span: Span::default(),
+                        }),
+                        PipeExpr {
+                            negated: false,
+                            commands: vec![CallExpr {
+                                value: CallExprOptions::Parenthesis(Box::new(Expr {
+                                    value: SemicolonExpr {
+                                        value: SemicolonExprOptions::SingleExpr(AndOrExpr {
+                                            value: AndOrExprOptions::SingleExpr(PipeExpr {
+                                                negated: false,
+                                                commands: vec![CallExpr {
+                                                    value: CallExprOptions::ProgCall(
+                                                        TokenType::Word("ls".to_string()).into(),
+                                                        vec![]
+                                                    ),
+                                                    redirections: vec![],
+                                                    span: Span::default(),
+                                                }],
+                                                span: Span::default(),
                                             }),
-                                        },
-                                    })),
-                                })
-                            }),
-                        })
+                                            span: Span::default(),
+                                        }),
+                                        span: Span::default(),
+                                    },
+                                    span: Span::default(),
+                                    comments: vec![],
+                                })),
+                                redirections: vec![],
+                                span: Span::default(),
+                            }],
+                            span: Span::default(),
+                        }
                     ),
+                    span: Span::default(),
                 }),
+                span: Span::default(),
             },
+            span: Span::default(),
+            comments: vec![],
         }
     );
 }
@@ -406,10 +1251,461 @@ fn test_parenthesis_mismatched() {
         TokenType::Word("ls".to_string()),
     ];
 
-    let mut it = tokens.iter().peekable();
+    let tokens = unspanned(&tokens);
+    let mut it = Tokens::new(&tokens);
+
+    assert_eq!(
+        Expr::from_tokens(&mut it).unwrap_err().kind,
+        GrammarErrorKind::MismatchedParenthesis
+    );
+}
+
+#[test]
+fn test_braces() {
+    let tokens = [
+        TokenType::Word("{".to_string()),
+        TokenType::Word("ls".to_string()),
+        TokenType::Semicolon,
+        TokenType::Word("}".to_string()),
+        TokenType::Great,
+        TokenType::Word("out".to_string()),
+    ];
+
+    let tokens = unspanned(&tokens);
+    let mut it = Tokens::new(&tokens);
+
+    assert_eq!(
+        CallExpr::from_tokens(&mut it).unwrap(),
+        CallExpr {
+            value: CallExprOptions::Braces(Box::new(Expr {
+                value: SemicolonExpr {
+                    value: SemicolonExprOptions::SingleExpr(AndOrExpr {
+                        value: AndOrExprOptions::SingleExpr(PipeExpr {
+                            negated: false,
+                            commands: vec![CallExpr {
+                                value: CallExprOptions::ProgCall(
+                                    TokenType::Word("ls".to_string()).into(),
+                                    vec![]
+                                ),
+                                redirections: vec![],
+                                span: Span::default(),
+                            }],
+                            span: Span::default(),
+                        }),
+                        span: Span::default(),
+                    }),
+                    span: Span::default(),
+                },
+                span: Span::default(),
+                comments: vec![],
+            })),
+            redirections: vec![Redirection {
+                fd: None,
+                operator: TokenType::Great,
+                target: TokenType::Word("out".to_string()).into(),
+                span: Span::default(),
+            }],
+            span: Span::default(),
+        }
+    );
+}
+
+#[test]
+fn test_braces_as_arguments() {
+    let tokens = [
+        TokenType::Word("echo".to_string()),
+        TokenType::Word("{".to_string()),
+        TokenType::Word("}".to_string()),
+    ];
+
+    let tokens = unspanned(&tokens);
+    let mut it = Tokens::new(&tokens);
+
+    assert_eq!(
+        CallExpr::from_tokens(&mut it).unwrap(),
+        CallExpr {
+            value: CallExprOptions::ProgCall(
+                TokenType::Word("echo".to_string()).into(),
+                vec![
+                    TokenType::Word("{".to_string()).into(),
+                    TokenType::Word("}".to_string()).into()
+                ]
+            ),
+            redirections: vec![],
+            span: Span::default(),
+        }
+    );
+}
+
+#[test]
+fn test_braces_mismatched() {
+    let tokens = [
+        TokenType::Word("{".to_string()),
+        TokenType::Word("ls".to_string()),
+        TokenType::Semicolon,
+    ];
+
+    let tokens = unspanned(&tokens);
+    let mut it = Tokens::new(&tokens);
+
+    assert_eq!(
+        Expr::from_tokens(&mut it).unwrap_err().kind,
+        GrammarErrorKind::MismatchedBrace
+    );
+}
+
+#[test]
+fn test_redirection_without_target() {
+    let tokens = [TokenType::Word("ls".to_string()), TokenType::Great];
+
+    let tokens = unspanned(&tokens);
+    let mut it = Tokens::new(&tokens);
+
+    assert_eq!(
+        Expr::from_tokens(&mut it).unwrap_err().kind,
+        GrammarErrorKind::MissingRedirectionTarget
+    );
+}
+
+#[test]
+fn test_negated_pipe() {
+    let tokens = [
+        TokenType::Word("!".to_string()),
+        TokenType::Word("ls".to_string()),
+        TokenType::Pipe,
+        TokenType::Word("grep".to_string()),
+        TokenType::Word("foo".to_string()),
+    ];
+
+    let tokens = unspanned(&tokens);
+    let mut it = Tokens::new(&tokens);
+
+    assert_eq!(
+        PipeExpr::from_tokens(&mut it).unwrap(),
+        PipeExpr {
+            negated: true,
+            commands: vec![
+                CallExpr {
+                    value: CallExprOptions::ProgCall(
+                        TokenType::Word("ls".to_string()).into(),
+                        vec![]
+                    ),
+                    redirections: vec![],
+                    span: Span::default(),
+                },
+                CallExpr {
+                    value: CallExprOptions::ProgCall(
+                        TokenType::Word("grep".to_string()).into(),
+                        vec![TokenType::Word("foo".to_string()).into()]
+                    ),
+                    redirections: vec![],
+                    span: Span::default(),
+                },
+            ],
+            span: Span::default(),
+        }
+    );
+}
+
+#[test]
+fn test_conditional() {
+    let tokens = [
+        TokenType::Word("[[".to_string()),
+        TokenType::Word("-f".to_string()),
+        TokenType::Word("a".to_string()),
+        TokenType::Or,
+        TokenType::Word("!".to_string()),
+        TokenType::Word("b".to_string()),
+        TokenType::Word("==".to_string()),
+        TokenType::DoubleQuotedString("\"c\"".to_string()),
+        TokenType::And,
+        TokenType::Word("d".to_string()),
+        TokenType::Word("]]".to_string()),
+    ];
+
+    let tokens = unspanned(&tokens);
+    let mut it = Tokens::new(&tokens);
+
+    assert_eq!(
+        CallExpr::from_tokens(&mut it).unwrap(),
+        CallExpr {
+            value: CallExprOptions::Conditional(CondExpr {
+                value: CondExprOptions::Or(
+                    Box::new(CondExpr {
+                        value: CondExprOptions::Unary(
+                            "-f".to_string(),
+                            TokenType::Word("a".to_string()).into()
+                        ),
+                        span: Span::default(),
+                    }),
+                    Box::new(CondExpr {
+                        value: CondExprOptions::And(
+                            Box::new(CondExpr {
+                                value: CondExprOptions::Not(Box::new(CondExpr {
+                                    value: CondExprOptions::Binary(
+                                        TokenType::Word("b".to_string()).into(),
+                                        "==".to_string(),
+                                        TokenType::DoubleQuotedString("\"c\"".to_string()).into()
+                                    ),
+                                    span: Span::default(),
+                                })),
+                                span: Span::default(),
+                            }),
+                            Box::new(CondExpr {
+                                value: CondExprOptions::Word(
+                                    TokenType::Word("d".to_string()).into()
+                                ),
+                                span: Span::default(),
+                            })
+                        ),
+                        span: Span::default(),
+                    })
+                ),
+                span: Span::default(),
+            }),
+            redirections: vec![],
+            span: Span::default(),
+        }
+    );
+}
+
+#[test]
+fn test_conditional_mismatched() {
+    let tokens = [
+        TokenType::Word("[[".to_string()),
+        TokenType::Word("a".to_string()),
+    ];
+
+    let tokens = unspanned(&tokens);
+    let mut it = Tokens::new(&tokens);
+
+    assert_eq!(
+        Expr::from_tokens(&mut it).unwrap_err().kind,
+        GrammarErrorKind::MismatchedBracket
+    );
+}
+
+#[test]
+fn test_spans() {
+    use crate::parser::parse;
+
+    let tokens = parse("echo a | cat > out; ls").unwrap();
+    let expr = Expr::from_tokens(&mut Tokens::new(&tokens)).unwrap();
+
+    assert_eq!((expr.span.start, expr.span.end), (0, 22));
+
+    let pipe = match expr.value.value {
+        SemicolonExprOptions::Semicolon(and_or_expr, _) => match and_or_expr.value {
+            AndOrExprOptions::SingleExpr(pipe) => pipe,
+            _ => unreachable!(),
+        },
+        _ => unreachable!(),
+    };
+
+    assert_eq!((pipe.span.start, pipe.span.end), (0, 18));
+    assert_eq!(
+        (pipe.commands[1].span.start, pipe.commands[1].span.end),
+        (9, 18)
+    );
+    assert_eq!(pipe.commands[1].redirections[0].span.column, 14);
+}
+
+#[test]
+fn test_error_spans() {
+    use crate::parser::parse;
+
+    let error = |line: &str| {
+        let tokens = parse(line).unwrap();
+
+        Expr::from_tokens(&mut Tokens::new(&tokens)).unwrap_err()
+    };
+
+    assert_eq!(
+        error("ls && (cat; ls"),
+        GrammarError {
+            kind: GrammarErrorKind::MismatchedParenthesis,
+            span: Span {
+                start: 6,
+                end: 7,
+                line: 1,
+                column: 7
+            }
+        }
+    );
+    assert_eq!(error("ls | ; ls").span.column, 6);
+    assert_eq!(error("cat >").span.column, 5);
+    assert_eq!(error("[[ a == ]]").span.column, 9);
+
+    let end = error("echo a &&\n\n").span;
+
+    assert_eq!((end.start, end.line, end.column), (9, 1, 10));
+}
+
+#[test]
+fn test_malformed_programs() {
+    use crate::parser::parse;
+
+    let cases = [
+        ("ls ) foo", GrammarErrorKind::UnmatchedParenthesis, 4),
+        (")", GrammarErrorKind::UnmatchedParenthesis, 1),
+        ("(ls))", GrammarErrorKind::UnmatchedParenthesis, 5),
+        ("ls; }", GrammarErrorKind::UnmatchedBrace, 5),
+        ("}", GrammarErrorKind::UnmatchedBrace, 1),
+        ("ls (", GrammarErrorKind::UnexpectedToken, 4),
+        ("(ls) foo", GrammarErrorKind::UnexpectedToken, 6),
+        ("{ ls; } foo", GrammarErrorKind::UnexpectedToken, 9),
+        ("[[ a ]] b", GrammarErrorKind::UnexpectedToken, 9),
+        ("[[ a b ]]", GrammarErrorKind::UnexpectedToken, 6),
+        ("{ ls )", GrammarErrorKind::UnmatchedParenthesis, 6),
+        ("( ls }", GrammarErrorKind::MismatchedParenthesis, 1),
+        ("( ls; }", GrammarErrorKind::UnmatchedBrace, 7),
+        ("ls &&", GrammarErrorKind::MissingCommand, 6),
+        ("ls ||", GrammarErrorKind::MissingCommand, 6),
+        ("ls |", GrammarErrorKind::MissingCommand, 5),
+        ("!", GrammarErrorKind::MissingCommand, 2),
+        ("ls && { }", GrammarErrorKind::MissingCommand, 9),
+        ("( )", GrammarErrorKind::MissingCommand, 3),
+        ("(ls && )", GrammarErrorKind::MissingCommand, 8),
+        ("&& ls", GrammarErrorKind::InvalidCmdStart, 1),
+        ("|| ls", GrammarErrorKind::InvalidCmdStart, 1),
+        ("| ls", GrammarErrorKind::InvalidCmdStart, 1),
+        ("; ls", GrammarErrorKind::InvalidCmdStart, 1),
+        ("ls; ; ls", GrammarErrorKind::InvalidCmdStart, 5),
+        ("ls;; ls", GrammarErrorKind::UnexpectedToken, 3),
+        ("ls & ls", GrammarErrorKind::UnexpectedToken, 4),
+        ("cat <<EOF", GrammarErrorKind::UnexpectedToken, 5),
+        ("ls 2>", GrammarErrorKind::MissingRedirectionTarget, 5),
+        ("ls && && ls", GrammarErrorKind::InvalidCmdStart, 7),
+        ("ls | | ls", GrammarErrorKind::InvalidCmdStart, 6),
+        ("(ls", GrammarErrorKind::MismatchedParenthesis, 1),
+        ("{ ls;", GrammarErrorKind::MismatchedBrace, 1),
+        ("[[ a", GrammarErrorKind::MismatchedBracket, 1),
+        ("[[ ( a ]]", GrammarErrorKind::UnexpectedToken, 8),
+        ("ls >", GrammarErrorKind::MissingRedirectionTarget, 4),
+        ("ls > ;", GrammarErrorKind::MissingRedirectionTarget, 4),
+        (">", GrammarErrorKind::MissingRedirectionTarget, 1),
+        ("2>; ls", GrammarErrorKind::MissingRedirectionTarget, 2),
+        (">out (ls)", GrammarErrorKind::UnexpectedToken, 6),
+        ("[[ ]]", GrammarErrorKind::InvalidConditional, 4),
+        ("[[ a == ]]", GrammarErrorKind::InvalidConditional, 9),
+    ];
+
+    for (line, kind, column) in cases.iter() {
+        let error = parse_program(&parse(line).unwrap()).unwrap_err();
+
+        assert_eq!(
+            (line, &error.kind, error.span.column),
+            (line, kind, *column)
+        );
+    }
+}
+
+#[test]
+fn test_well_formed_programs() {
+    use crate::parser::parse;
+
+    for line in [
+        "",
+        "ls",
+        "ls;",
+        "ls; ls",
+        "(ls) > out; ls",
+        "((ls))",
+        "{ ls; }",
+        "{ { ls; }; } | ls",
+        "! ls && ls || ls",
+        "[[ ( a ) && ! b ]] > out",
+        "ls | (ls; ls)",
+        "ls\n",
+        "\n",
+        "ls;\n",
+        "A=1",
+        "A=1 B=2 env >out",
+        "\"ls\" -l",
+        "ls 2>&1 3<>file 4>&- >|out <&0",
+        "2>/dev/null ls",
+        ">out echo x",
+        "<in 2>err cat >out",
+        "A=1 >out cmd",
+        ">out",
+        "ls && >out",
+    ]
+    .iter()
+    {
+        assert!(parse_program(&parse(line).unwrap()).is_ok(), "{}", line);
+    }
+}
+
+#[test]
+fn test_and_or_group_to_the_left() {
+    use crate::parser::parse;
+
+    let tokens = parse("a || b && c").unwrap();
+    let expr = parse_program(&tokens).unwrap();
+
+    let (left, right) = match expr.value.value {
+        SemicolonExprOptions::SingleExpr(AndOrExpr {
+            value: AndOrExprOptions::And(left, right),
+            ..
+        }) => (left, right),
+        other => panic!("{:?}", other),
+    };
+
+    assert!(matches!(left.value, AndOrExprOptions::Or(..)));
+    assert_eq!((left.span.start, left.span.end), (0, 6));
+    assert_eq!(right.span.column, 11);
+}
+
+#[test]
+fn test_words_keep_their_spans() {
+    use crate::parser::parse;
+
+    let tokens = parse("echo  hi >out").unwrap();
+    let call = CallExpr::from_tokens(&mut Tokens::new(&tokens)).unwrap();
+
+    match call.value() {
+        CallExprOptions::ProgCall(name, args) => {
+            assert_eq!((name.span.start, name.span.end), (0, 4));
+            assert_eq!((args[0].span.start, args[0].span.column), (6, 7));
+        }
+        other => panic!("{:?}", other),
+    }
+
+    assert_eq!(call.redirections()[0].target().span.column, 11);
+
+    let tokens = parse("[[ x =~ ^(y|z)$ ]]").unwrap();
+    let call = CallExpr::from_tokens(&mut Tokens::new(&tokens)).unwrap();
+
+    match call.value() {
+        CallExprOptions::Conditional(CondExpr {
+            value: CondExprOptions::Binary(left, _, right),
+            ..
+        }) => {
+            assert_eq!(left.span.column, 4);
+            assert_eq!((right.span.start, right.span.end), (8, 15));
+        }
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn test_comments_survive() {
+    use crate::parser::parse;
+
+    let expr = parse_program(&parse("# a\nls && # b\n  ls # c\n").unwrap()).unwrap();
 
     assert_eq!(
-        Expr::from_tokens(&mut it).unwrap_err(),
-        GrammarError::MismatchedParenthesis
+        expr.comments
+            .iter()
+            .map(|comment| (comment.text(), comment.span().line, comment.is_own_line()))
+            .collect::<Vec<_>>(),
+        vec![("# a", 1, true), ("# b", 2, false), ("# c", 3, false)]
     );
+    assert!(matches!(
+        expr.value.value,
+        SemicolonExprOptions::SingleExpr(AndOrExpr {
+            value: AndOrExprOptions::And(..),
+            ..
+        })
+    ));
 }
@@ -0,0 +1,356 @@
+use crate::alias::quote;
+use crate::builtins::Stdio;
+use crate::process;
+use crate::shell::{Shell, Variable};
+
+/// Whether `name` can be used as a variable name: a letter or underscore
+/// followed by letters, digits and underscores.
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+/// A variable's value as it would be written in an assignment.
+fn format_value(value: &Variable) -> String {
+    match value {
+        Variable::Scalar(value) => quote(value),
+        Variable::Array(values) => format!(
+            "({})",
+            values
+                .iter()
+                .map(|v| quote(v))
+                .collect::<Vec<_>>()
+                .join(" ")
+        ),
+    }
+}
+
+/// Prints `NAME=value` for every variable, sorted by name, the way `set` with
+/// no arguments does.
+pub fn print_variables(shell: &Shell, stdio: &mut Stdio) {
+    let mut names: Vec<&String> = shell.vars.keys().collect();
+
+    names.sort();
+
+    for name in names {
+        writeln!(stdio.stdout, "{}={}", name, format_value(&shell.vars[name])).ok();
+    }
+}
+
+/// Prints `command NAME='value'`, or just `command NAME` for names without a
+/// value, for each of `names`.
+fn print_declarations<'a>(
+    shell: &Shell,
+    command: &str,
+    names: impl Iterator<Item = &'a String>,
+    stdio: &mut Stdio,
+) {
+    for name in names {
+        match shell.vars.get(name) {
+            Some(value) => writeln!(stdio.stdout, "{} {}={}", command, name, format_value(value)),
+            None => writeln!(stdio.stdout, "{} {}", command, name),
+        }
+        .ok();
+    }
+}
+
+/// Splits the leading `-x` style flags of a builtin from its operands,
+/// stopping at `--`. Returns the first flag that is not in `allowed` as the
+/// error.
+fn split_flags<'a>(
+    argv: &'a [&'a str],
+    allowed: &str,
+) -> Result<(Vec<char>, &'a [&'a str]), &'a str> {
+    let mut flags = Vec::new();
+    let mut arguments = &argv[1..];
+
+    while let Some(&argument) = arguments.first() {
+        if argument == "--" {
+            arguments = &arguments[1..];
+            break;
+        }
+
+        if !argument.starts_with('-') || argument.len() < 2 {
+            break;
+        }
+
+        for c in argument[1..].chars() {
+            if !allowed.contains(c) {
+                return Err(argument);
+            }
+
+            flags.push(c);
+        }
+
+        arguments = &arguments[1..];
+    }
+
+    Ok((flags, arguments))
+}
+
+/// Applies `NAME` or `NAME=value` for `export` and `readonly`, returning the
+/// name if the assignment went through.
+fn declare<'a>(
+    shell: &mut Shell,
+    command: &str,
+    argument: &'a str,
+    stdio: &mut Stdio,
+) -> Option<&'a str> {
+    let (name, value) = match argument.find('=') {
+        Some(i) => (&argument[..i], Some(&argument[i + 1..])),
+        None => (argument, None),
+    };
+
+    if !is_valid_name(name) {
+        writeln!(
+            stdio.stderr,
+            "{}: `{}': not a valid identifier",
+            command, argument
+        )
+        .ok();
+        return None;
+    }
+
+    if let Some(value) = value {
+        if let Err(e) = shell.assign(name, Variable::Scalar(value.to_string())) {
+            writeln!(stdio.stderr, "{}: {}", command, e).ok();
+            return None;
+        }
+    }
+
+    Some(name)
+}
+
+/// `export [-n] [-p] [name[=value]…]` marks variables to be passed on to
+/// child processes, `-n` takes the mark away. Without names it prints the
+/// exported variables.
+pub fn execute_export(shell: &mut Shell, argv: &[&str], stdio: &mut Stdio) -> i32 {
+    let (flags, arguments) = match split_flags(argv, "np") {
+        Ok(split) => split,
+        Err(flag) => {
+            writeln!(stdio.stderr, "export: {}: invalid option", flag).ok();
+            return 2;
+        }
+    };
+
+    if arguments.is_empty() {
+        print_declarations(shell, "export", shell.exported.iter(), stdio);
+        return 0;
+    }
+
+    let mut status = 0;
+
+    for argument in arguments {
+        match declare(shell, "export", argument, stdio) {
+            Some(name) if flags.contains(&'n') => {
+                shell.exported.remove(name);
+            }
+            Some(name) => {
+                shell.exported.insert(name.to_string());
+            }
+            None => status = 1,
+        }
+    }
+
+    status
+}
+
+/// `readonly [-p] [name[=value]…]` forbids further assignments to variables.
+/// Without names it prints the read-only variables.
+pub fn execute_readonly(shell: &mut Shell, argv: &[&str], stdio: &mut Stdio) -> i32 {
+    let arguments = match split_flags(argv, "p") {
+        Ok((_, arguments)) => arguments,
+        Err(flag) => {
+            writeln!(stdio.stderr, "readonly: {}: invalid option", flag).ok();
+            return 2;
+        }
+    };
+
+    if arguments.is_empty() {
+        print_declarations(shell, "readonly", shell.readonly.iter(), stdio);
+        return 0;
+    }
+
+    let mut status = 0;
+
+    for argument in arguments {
+        match declare(shell, "readonly", argument, stdio) {
+            Some(name) => {
+                shell.readonly.insert(name.to_string());
+            }
+            None => status = 1,
+        }
+    }
+
+    status
+}
+
+/// `unset [-v|-f] name…` removes variables. The shell has no functions yet,
+/// so `-f` has nothing to remove.
+pub fn execute_unset(shell: &mut Shell, argv: &[&str], stdio: &mut Stdio) -> i32 {
+    let (flags, arguments) = match split_flags(argv, "vf") {
+        Ok(split) => split,
+        Err(flag) => {
+            writeln!(stdio.stderr, "unset: {}: invalid option", flag).ok();
+            return 2;
+        }
+    };
+
+    if flags.last() == Some(&'f') {
+        return 0;
+    }
+
+    let mut status = 0;
+
+    for name in arguments {
+        if !is_valid_name(name) {
+            writeln!(stdio.stderr, "unset: `{}': not a valid identifier", name).ok();
+            status = 1;
+        } else if shell.unset(name).is_err() {
+            writeln!(
+                stdio.stderr,
+                "unset: {}: cannot unset: readonly variable",
+                name
+            )
+            .ok();
+            status = 1;
+        }
+    }
+
+    status
+}
+
+/// `env [-i] [-u name]… [name=value]… [command [args…]]` runs a program with
+/// the exported variables as its environment, after `-i` empties it, `-u`
+/// takes variables out and the assignments add or change them. Without a
+/// command it prints that environment. The shell's own variables stay as
+/// they are.
+pub fn execute_env(shell: &mut Shell, argv: &[&str], stdio: &mut Stdio) -> i32 {
+    let mut environment = shell.environment();
+    let mut arguments = &argv[1..];
+
+    while let Some(&argument) = arguments.first() {
+        match argument {
+            "-i" | "-" => environment.clear(),
+            "-u" => match arguments.get(1) {
+                Some(name) => {
+                    environment.retain(|(n, _)| n != name);
+                    arguments = &arguments[1..];
+                }
+                None => {
+                    writeln!(stdio.stderr, "env: -u: option requires an argument").ok();
+                    return 2;
+                }
+            },
+            "--" => {
+                arguments = &arguments[1..];
+                break;
+            }
+            _ if argument.starts_with('-') && argument.len() > 1 => {
+                writeln!(stdio.stderr, "env: {}: invalid option", argument).ok();
+                return 2;
+            }
+            _ => break,
+        }
+
+        arguments = &arguments[1..];
+    }
+
+    while let Some((name, value)) = arguments.first().and_then(|a| a.split_once('=')) {
+        environment.retain(|(n, _)| n != name);
+        environment.push((name.to_string(), value.to_string()));
+        arguments = &arguments[1..];
+    }
+
+    if arguments.is_empty() {
+        for (name, value) in &environment {
+            writeln!(stdio.stdout, "{}={}", name, value).ok();
+        }
+
+        return 0;
+    }
+
+    process::execute_program_in(shell, arguments, environment)
+}
+
+#[test]
+fn test_is_valid_name() {
+    assert!(is_valid_name("_a1"));
+    assert!(is_valid_name("PATH"));
+    assert!(!is_valid_name("1a"));
+    assert!(!is_valid_name("a-b"));
+    assert!(!is_valid_name(""));
+}
+
+#[test]
+fn test_export() {
+    use crate::builtins::capture;
+
+    let mut shell = Shell::default();
+
+    assert_eq!(capture(&mut shell, &["export", "A=it's", "B"]).0, 0);
+    assert_eq!(
+        capture(&mut shell, &["export"]).1,
+        "export A='it'\\''s'\nexport B\n"
+    );
+    assert_eq!(
+        shell.environment(),
+        vec![("A".to_string(), "it's".to_string())]
+    );
+    assert_eq!(capture(&mut shell, &["export", "-n", "A"]).0, 0);
+    assert_eq!(capture(&mut shell, &["export", "-p"]).1, "export B\n");
+    assert_eq!(shell.var("A"), Some("it's".to_string()));
+    assert_eq!(
+        capture(&mut shell, &["env", "-u", "A", "C=1", "B=2"]),
+        (0, "C=1\nB=2\n".to_string(), String::new())
+    );
+    assert_eq!(capture(&mut shell, &["env", "-i"]).1, "");
+    assert_eq!(capture(&mut shell, &["env", "-x"]).0, 2);
+    assert_eq!(
+        capture(&mut shell, &["export", "1A=x"]),
+        (
+            1,
+            String::new(),
+            "export: `1A=x': not a valid identifier\n".to_string()
+        )
+    );
+}
+
+#[test]
+fn test_readonly_and_unset() {
+    use crate::builtins::capture;
+
+    let mut shell = Shell::default();
+
+    assert_eq!(capture(&mut shell, &["readonly", "A=1"]).0, 0);
+    assert_eq!(capture(&mut shell, &["readonly"]).1, "readonly A='1'\n");
+    assert_eq!(
+        capture(&mut shell, &["export", "A=2"]),
+        (
+            1,
+            String::new(),
+            "export: A: readonly variable\n".to_string()
+        )
+    );
+    assert_eq!(
+        capture(&mut shell, &["unset", "A"]),
+        (
+            1,
+            String::new(),
+            "unset: A: cannot unset: readonly variable\n".to_string()
+        )
+    );
+
+    shell.set_var("B", Variable::Scalar("2".to_string()));
+
+    assert_eq!(capture(&mut shell, &["unset", "-f", "B"]).0, 0);
+    assert_eq!(shell.var("B"), Some("2".to_string()));
+    assert_eq!(capture(&mut shell, &["unset", "-v", "B"]).0, 0);
+    assert_eq!(shell.var("B"), None);
+}